@@ -4,9 +4,14 @@
 mod kubeconfig;
 
 use crate::k8s_types::K8sType;
+use crate::resource::{K8sResource, ObjectId};
+
+use serde_json::Value;
 
 use std::collections::HashMap;
+use std::fmt::{self, Debug};
 use std::io;
+use std::sync::Arc;
 use std::{path::Path, time::Duration};
 
 /// Default label that's added to all child resources, so that roperator can track the ownership of resources.
@@ -17,6 +22,11 @@ pub const DEFAULT_TRACKING_LABEL_NAME: &str = "app.kubernetes.io/instance";
 /// The value will always be the `operator_name` from the `OperatorConfig`.
 pub const DEFAULT_OWNERSHIP_LABEL_NAME: &str = "app.kubernetes.io/managed-by";
 
+/// Default annotation that's added to child resources to record the desired state that was last applied, so that
+/// roperator can remove any fields that the handler no longer sets. Only used for child types that are configured with
+/// `ChildConfig::record_last_applied`.
+pub const DEFAULT_LAST_APPLIED_ANNOTATION_NAME: &str = "roperator.io/last-applied-configuration";
+
 const SERVICE_ACCOUNT_TOKEN_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
 const SERVICE_ACCOUNT_CA_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt";
 const API_SERVER_HOSTNAME: &str = "kubernetes.default.svc";
@@ -24,17 +34,29 @@ const API_SERVER_HOSTNAME: &str = "kubernetes.default.svc";
 pub use self::kubeconfig::{KubeConfig, KubeConfigError};
 
 /// What to do when there's a difference between the "desired" state of a given resource and the
-/// actual state of that resource in the cluster. The three options are:
+/// actual state of that resource in the cluster. The options are:
 /// - Update the resource in place using an HTTP PUT request
+/// - Update the resource in place using a PATCH request that's computed from the differences
+/// - Update the resource in place using a server-side apply PATCH request
 /// - First delete the resource, then try to re-create it later
 /// - Don't update it automatically, and instead wait for something else to delete the resource and then re-create it with the new state
-///
-/// There's not yet any option for using PATCH requests.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum UpdateStrategy {
     /// Means that the resource will be updated in place using an HTTP PUT request
     Replace,
 
+    /// The resource will be updated in place using an HTTP PATCH request. The patch is computed from
+    /// the differences between the desired and actual state, so only the fields that the operator
+    /// actually sets will be modified. `Patch(MergeStrategy::Apply)` is the same as `Apply`.
+    Patch(MergeStrategy),
+
+    /// The resource will be updated in place by sending the desired state as a server-side apply
+    /// PATCH request, using the `field_manager` from the `OperatorConfig`. Unlike `Replace`, this does
+    /// not send a `resourceVersion`, so it won't fail with a conflict when other controllers modify
+    /// fields that aren't included in the desired state. Conflicts with other field managers are
+    /// always forced, so the operator will take ownership of any fields it sets.
+    Apply,
+
     /// The resource will first get deleted, and then re-created with the new state
     Recreate,
 
@@ -42,17 +64,359 @@ pub enum UpdateStrategy {
     OnDelete,
 }
 
+/// The type of PATCH request to use for updating resources. Each of these corresponds to a different
+/// `Content-Type` that the Kubernetes api server accepts for PATCH requests.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MergeStrategy {
+    /// A [JSON Patch](https://tools.ietf.org/html/rfc6902), which is a list of operations to apply
+    Json,
+    /// A [JSON Merge Patch](https://tools.ietf.org/html/rfc7386). Arrays in the patch will always
+    /// replace the whole existing array.
+    JsonMerge,
+    /// A Kubernetes strategic merge patch, which is like a JSON Merge Patch except that some arrays
+    /// will be merged with the existing ones instead of replaced. This only works for builtin types,
+    /// and not for custom resources.
+    StrategicMerge,
+    /// A server-side apply patch
+    Apply,
+}
+
+impl MergeStrategy {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            MergeStrategy::Json => "application/json-patch+json",
+            MergeStrategy::JsonMerge => "application/merge-patch+json",
+            MergeStrategy::StrategicMerge => "application/strategic-merge-patch+json",
+            // json is a subset of yaml, so there's no need to actually serialize apply patches as yaml
+            MergeStrategy::Apply => "application/apply-patch+yaml",
+        }
+    }
+}
+
+/// Determines how the dependents of a child are deleted when roperator deletes the child. If no policy is set, then
+/// the api server will use the default for the child's type.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PropagationPolicy {
+    /// The child is deleted only after all of its dependents have been deleted
+    Foreground,
+    /// The child is deleted immediately, and its dependents are deleted in the background
+    Background,
+    /// The dependents of the child are left alone, and their ownerReferences to the child are removed
+    Orphan,
+}
+
+impl PropagationPolicy {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            PropagationPolicy::Foreground => "Foreground",
+            PropagationPolicy::Background => "Background",
+            PropagationPolicy::Orphan => "Orphan",
+        }
+    }
+}
+
+/// Where the children of a given type live, relative to their parent. Children are normally tracked using both a
+/// label and an `ownerReference`, which allows Kubernetes to garbage collect them when the parent is deleted. But
+/// Kubernetes doesn't allow a namespaced parent to own children outside of its namespace, so those children are tracked
+/// using only the label, and roperator deletes them itself before it removes the parent's finalizer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChildScope {
+    /// Children are in the same namespace as the parent, or are cluster-scoped if the parent is. This is the default.
+    SameNamespace,
+
+    /// Children are cluster-scoped resources, such as Namespaces, ClusterRoles, or PersistentVolumes. Children of this
+    /// type are always watched across the whole cluster, even if the operator is constrained to specific namespaces.
+    Cluster,
+
+    /// Children may be in any namespace, such as a Secret in a shared namespace that's created for each tenant.
+    /// Children of this type are always watched across the whole cluster, even if the operator is constrained to
+    /// specific namespaces. Children that do end up in the parent's namespace are still given an `ownerReference`.
+    AnyNamespace,
+}
+
+/// What happens to the children of a given type when their parent is deleted
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RetentionPolicy {
+    /// Children are deleted along with their parent. This is the default.
+    Delete,
+    /// Children are left in place when the parent is deleted. Before the parent's finalizer is removed, roperator
+    /// removes the parent's `ownerReference` and the tracking labels from each child, so that it won't be garbage
+    /// collected, and won't be picked up as a child of a new parent that happens to have the same name.
+    Retain,
+}
+
+/// What to do when a child can't be created because a resource with the same name already exists, but isn't
+/// labeled as a child of the parent. This happens when the resource was created by something other than the
+/// operator, for example when migrating resources that were previously managed by Helm.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AdoptionPolicy {
+    /// The sync fails with an error saying that the resource already exists. This is the default. The error is
+    /// reported as a `SyncFailed` Warning event on the parent, and the sync is retried with the usual error backoff.
+    /// Roperator doesn't add anything to the parent's status for this, since the status is always exactly what the
+    /// handler returned, and it's written before any children are created.
+    Refuse,
+    /// The existing resource is labeled as a child of the parent and given an `ownerReference`, and is then updated
+    /// on the next sync just like any other child. Resources that are controlled by another owner, or are already
+    /// children of a different parent, are never adopted.
+    Adopt,
+    /// The existing resource is left alone, and the sync continues as if the child had been created
+    Skip,
+}
+
+/// The keys that identify the items of associative arrays, which are arrays of objects that are compared by matching
+/// up items with the same key instead of by their position. Keys are registered by the name of the field that holds
+/// the array, and each field may have several alternative keys, which are tried in order. An alternative is used if
+/// every desired item has a value for its first field, and items match if every field of the key that's present in the
+/// desired item is equal in the existing item. Arrays without a registered key are associative if every desired item
+/// has a string `name`.
+///
+/// The default keys are seeded from the strategic merge keys of the builtin Kubernetes types, for example `ports` are
+/// keyed by `containerPort` and `protocol` in Pods, or by `port` and `protocol` in Services.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeKeys {
+    keys: HashMap<String, Vec<Vec<String>>>,
+}
+
+impl MergeKeys {
+    /// Returns an empty set of merge keys, without any of the defaults for builtin types
+    pub fn empty() -> MergeKeys {
+        MergeKeys {
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Adds a key for arrays in the given field, which is tried before any keys that were previously added
+    pub fn with_key(mut self, field: impl Into<String>, key: &[&str]) -> Self {
+        let key = key.iter().map(|k| (*k).to_owned()).collect();
+        self.keys.entry(field.into()).or_default().insert(0, key);
+        self
+    }
+
+    pub(crate) fn keys_for(&self, field: &str) -> &[Vec<String>] {
+        self.keys.get(field).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+impl Default for MergeKeys {
+    fn default() -> MergeKeys {
+        MergeKeys::empty()
+            .with_key("ports", &["port", "protocol"])
+            .with_key("ports", &["containerPort", "protocol"])
+            .with_key("volumeMounts", &["mountPath"])
+            .with_key("volumeDevices", &["devicePath"])
+            .with_key("tolerations", &["key", "effect"])
+            .with_key("hostAliases", &["ip"])
+            .with_key("conditions", &["type"])
+            .with_key(
+                "topologySpreadConstraints",
+                &["topologyKey", "whenUnsatisfiable"],
+            )
+    }
+}
+
+/// The format of a value that the api server may return in a different representation than the one that was sent,
+/// for example a `cpu` limit of `"1"` may be returned as `"1000m"`. Values with a known format are compared by what
+/// they represent instead of by their representation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ValueFormat {
+    /// A resource quantity, such as `500m` or `1Gi`, which may also be a number
+    Quantity,
+    /// A value that may be either an integer or a string, such as a `targetPort`. An integer is equal to a string
+    /// with the same digits.
+    IntOrString,
+    /// A duration string, such as `1h30m`
+    Duration,
+}
+
+/// The formats of the values in certain fields, which are registered by the name of the field. If the field holds an
+/// object, then the format applies to each of its values, so that `limits` applies to both `limits.cpu` and
+/// `limits.memory`.
+///
+/// The default formats cover the builtin Kubernetes types, such as the `limits` and `requests` of resources, and
+/// `targetPort` or `maxSurge`, which may be integers or strings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueFormats {
+    formats: HashMap<String, ValueFormat>,
+}
+
+impl ValueFormats {
+    /// Returns an empty set of formats, without any of the defaults for builtin types
+    pub fn empty() -> ValueFormats {
+        ValueFormats {
+            formats: HashMap::new(),
+        }
+    }
+
+    /// Sets the format of the values in the given field
+    pub fn with_format(mut self, field: impl Into<String>, format: ValueFormat) -> Self {
+        self.formats.insert(field.into(), format);
+        self
+    }
+
+    pub(crate) fn format_of(&self, field: &str) -> Option<ValueFormat> {
+        self.formats.get(field).cloned()
+    }
+}
+
+impl Default for ValueFormats {
+    fn default() -> ValueFormats {
+        let quantities = &[
+            "limits",
+            "requests",
+            "hard",
+            "capacity",
+            "allocatable",
+            "overhead",
+            "sizeLimit",
+        ];
+        let int_or_strings = &[
+            "targetPort",
+            "port",
+            "maxSurge",
+            "maxUnavailable",
+            "minAvailable",
+        ];
+        let formats = ValueFormats::empty();
+        let formats = quantities.iter().fold(formats, |formats, field| {
+            formats.with_format(*field, ValueFormat::Quantity)
+        });
+        int_or_strings.iter().fold(formats, |formats, field| {
+            formats.with_format(*field, ValueFormat::IntOrString)
+        })
+    }
+}
+
 /// Configuration object that's specific to each type of child
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChildConfig {
     /// The update strategy for this child type, which determines what roperator should do when a
     /// desired from a `SyncResponse` doesn't match the actual state of the cluster.
     pub update_strategy: UpdateStrategy,
+
+    /// Where children of this type live, relative to their parent. Defaults to `ChildScope::SameNamespace`
+    pub scope: ChildScope,
+
+    /// The propagation policy that's used when deleting children of this type. Defaults to the api server's default
+    pub propagation_policy: Option<PropagationPolicy>,
+
+    /// The grace period that's used when deleting children of this type. Defaults to the api server's default
+    pub grace_period_seconds: Option<u32>,
+
+    /// What happens to children of this type when their parent is deleted. Defaults to `RetentionPolicy::Delete`
+    pub retention_policy: RetentionPolicy,
+
+    /// What to do when a child of this type already exists, but isn't labeled as a child of the parent. Defaults
+    /// to `AdoptionPolicy::Refuse`
+    pub adoption_policy: AdoptionPolicy,
+
+    /// The keys that are used to compare the associative arrays of existing and desired children of this type.
+    /// Defaults to the keys of the builtin Kubernetes types
+    pub merge_keys: MergeKeys,
+
+    /// The formats of values that are compared semantically in children of this type. Defaults to the formats of
+    /// the builtin Kubernetes types
+    pub value_formats: ValueFormats,
+
+    /// JSON pointers to fields of children of this type that are ignored when comparing the existing and desired
+    /// state, such as fields that are managed by other controllers. A `*` matches any part of a single segment
+    pub ignored_paths: Vec<String>,
+
+    /// Whether to delete and re-create children of this type when an update is rejected because it would change an
+    /// immutable field. Defaults to false, in which case the sync fails
+    pub recreate_on_immutable_field: bool,
+
+    /// Whether to record the desired state of children of this type in the last applied annotation, so that fields
+    /// the handler stops setting are removed. Defaults to false
+    pub record_last_applied: bool,
 }
 
 impl ChildConfig {
     pub fn new(update_strategy: UpdateStrategy) -> ChildConfig {
-        ChildConfig { update_strategy }
+        ChildConfig {
+            update_strategy,
+            scope: ChildScope::SameNamespace,
+            propagation_policy: None,
+            grace_period_seconds: None,
+            retention_policy: RetentionPolicy::Delete,
+            adoption_policy: AdoptionPolicy::Refuse,
+            merge_keys: MergeKeys::default(),
+            value_formats: ValueFormats::default(),
+            ignored_paths: Vec::new(),
+            recreate_on_immutable_field: false,
+            record_last_applied: false,
+        }
+    }
+
+    /// Records the desired state of children of this type in the last applied annotation, so that any fields that
+    /// the handler stops setting are removed from the children when they're next updated
+    pub fn record_last_applied(mut self) -> Self {
+        self.record_last_applied = true;
+        self
+    }
+
+    /// Deletes and re-creates children of this type when an update is rejected because it would change an immutable
+    /// field, such as the `clusterIP` of a Service. The new child is created once the old one is gone, just like with
+    /// `UpdateStrategy::Recreate`
+    pub fn recreate_on_immutable_field(mut self) -> Self {
+        self.recreate_on_immutable_field = true;
+        self
+    }
+
+    /// Ignores the field at the given JSON pointer, and everything inside of it, when comparing children of this
+    /// type. For example, `.ignore_path("/spec/replicas")` or `.ignore_path("/metadata/annotations/sidecar.io~1*")`
+    pub fn ignore_path(mut self, path: impl Into<String>) -> Self {
+        self.ignored_paths.push(path.into());
+        self
+    }
+
+    /// Sets the format of the values in the given field of children of this type, so that they're compared by what
+    /// they represent. For example, `.value_format("duration", ValueFormat::Duration)`
+    pub fn value_format(mut self, field: impl Into<String>, format: ValueFormat) -> Self {
+        self.value_formats = self.value_formats.with_format(field, format);
+        self
+    }
+
+    /// Adds a key for comparing associative arrays in the given field of children of this type, which takes
+    /// precedence over the default keys for that field. For example, `.merge_key("rules", &["host"])`
+    pub fn merge_key(mut self, field: impl Into<String>, key: &[&str]) -> Self {
+        self.merge_keys = self.merge_keys.with_key(field, key);
+        self
+    }
+
+    /// Sets what to do when a child of this type already exists, but isn't labeled as a child of the parent
+    pub fn adoption_policy(mut self, adoption_policy: AdoptionPolicy) -> Self {
+        self.adoption_policy = adoption_policy;
+        self
+    }
+
+    /// Sets what happens to children of this type when their parent is deleted
+    pub fn retention_policy(mut self, retention_policy: RetentionPolicy) -> Self {
+        self.retention_policy = retention_policy;
+        self
+    }
+
+    /// Sets the propagation policy that's used when deleting children of this type
+    pub fn propagation_policy(mut self, propagation_policy: PropagationPolicy) -> Self {
+        self.propagation_policy = Some(propagation_policy);
+        self
+    }
+
+    /// Sets the grace period that's used when deleting children of this type
+    pub fn grace_period_seconds(mut self, grace_period_seconds: u32) -> Self {
+        self.grace_period_seconds = Some(grace_period_seconds);
+        self
+    }
+
+    /// Marks this child type as cluster-scoped, which allows namespaced parents to have children of this type
+    pub fn cluster_scoped(mut self) -> Self {
+        self.scope = ChildScope::Cluster;
+        self
+    }
+
+    /// Allows children of this type to be in namespaces other than their parent's
+    pub fn any_namespace(mut self) -> Self {
+        self.scope = ChildScope::AnyNamespace;
+        self
     }
 
     /// returns a `ChildConfig` with the `update_strategy` set to `UpdateStrategy::Recreate`
@@ -69,6 +433,133 @@ impl ChildConfig {
     pub fn on_delete() -> ChildConfig {
         ChildConfig::new(UpdateStrategy::OnDelete)
     }
+
+    /// returns a `ChildConfig` with the `update_strategy` set to `UpdateStrategy::Apply`
+    pub fn apply() -> ChildConfig {
+        ChildConfig::new(UpdateStrategy::Apply)
+    }
+
+    /// returns a `ChildConfig` with the `update_strategy` set to `UpdateStrategy::Patch` using the given `MergeStrategy`
+    pub fn patch(merge_strategy: MergeStrategy) -> ChildConfig {
+        ChildConfig::new(UpdateStrategy::Patch(merge_strategy))
+    }
+}
+
+/// Identifies a parent resource that a related resource maps to. Parents may be identified by either their
+/// `metadata.uid` or by their namespace and name, whichever is more convenient for your mapping function.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ParentRef {
+    /// The `metadata.uid` of the parent
+    Uid(String),
+    /// The namespace and name of the parent. The namespace should be empty for cluster-scoped parents.
+    Id(ObjectId),
+}
+
+type RelatedMapper = Arc<dyn Fn(&K8sResource) -> Vec<ParentRef> + Send + Sync>;
+
+/// Configuration for a type of related resource. Related resources are resources that are not owned by the
+/// parent, but that may be referenced by it, for example a ConfigMap or Secret that's named in the parent's spec.
+/// Roperator watches all the resources of each related type, and uses the mapping function to determine which
+/// parents should be re-synced whenever one of them changes. The related resources that map to a parent are
+/// included in its `SyncRequest`, so handlers can read them without making any requests to the api server.
+#[derive(Clone)]
+pub struct RelatedConfig {
+    /// Function that returns the parents that the given related resource maps to
+    pub(crate) mapper: RelatedMapper,
+    /// Optional label selector to limit which related resources are watched
+    pub label_selector: Option<String>,
+}
+
+impl RelatedConfig {
+    /// Returns a new `RelatedConfig` that uses the given function to map related resources to their parents
+    pub fn new(
+        mapper: impl Fn(&K8sResource) -> Vec<ParentRef> + Send + Sync + 'static,
+    ) -> RelatedConfig {
+        RelatedConfig {
+            mapper: Arc::new(mapper),
+            label_selector: None,
+        }
+    }
+
+    /// Only watch related resources that match the given label selector
+    pub fn label_selector(mut self, label_selector: impl Into<String>) -> Self {
+        self.label_selector = Some(label_selector.into());
+        self
+    }
+
+    pub(crate) fn map_to_parents(&self, resource: &K8sResource) -> Vec<ParentRef> {
+        (self.mapper)(resource)
+    }
+}
+
+impl Debug for RelatedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RelatedConfig")
+            .field("label_selector", &self.label_selector)
+            .finish()
+    }
+}
+
+impl PartialEq for RelatedConfig {
+    fn eq(&self, other: &RelatedConfig) -> bool {
+        Arc::ptr_eq(&self.mapper, &other.mapper) && self.label_selector == other.label_selector
+    }
+}
+
+type ConversionFn = Arc<dyn Fn(Value) -> Result<Value, anyhow::Error> + Send + Sync>;
+
+/// A function that converts a parent resource from one version to another. Conversions are used by the conversion
+/// webhook, which the api server invokes whenever it needs a parent in a different version than the one it's stored
+/// as. The function only needs to convert the content of the resource, since the `apiVersion` of the result will
+/// be set automatically. Conversions must not modify anything in `metadata` except for labels and annotations.
+#[derive(Clone)]
+pub struct Conversion {
+    /// The version that this conversion starts from
+    pub from: &'static K8sType,
+    /// The version that this conversion produces
+    pub to: &'static K8sType,
+    pub(crate) convert: ConversionFn,
+}
+
+impl Conversion {
+    pub fn new(
+        from: &'static K8sType,
+        to: &'static K8sType,
+        convert: impl Fn(Value) -> Result<Value, anyhow::Error> + Send + Sync + 'static,
+    ) -> Conversion {
+        Conversion {
+            from,
+            to,
+            convert: Arc::new(convert),
+        }
+    }
+
+    /// Converts the resource and sets its `apiVersion` to the target version
+    pub(crate) fn convert(&self, resource: Value) -> Result<Value, anyhow::Error> {
+        let mut converted = (self.convert)(resource)?;
+        if let Some(obj) = converted.as_object_mut() {
+            obj.insert(
+                "apiVersion".to_owned(),
+                Value::String(self.to.api_version.to_owned()),
+            );
+        }
+        Ok(converted)
+    }
+}
+
+impl Debug for Conversion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Conversion")
+            .field("from", &self.from.api_version)
+            .field("to", &self.to.api_version)
+            .finish()
+    }
+}
+
+impl PartialEq for Conversion {
+    fn eq(&self, other: &Conversion) -> bool {
+        Arc::ptr_eq(&self.convert, &other.convert) && self.from == other.from && self.to == other.to
+    }
 }
 
 /// This is the main configuration of your operator. It is where you'll specify the type of your
@@ -80,11 +571,30 @@ pub struct OperatorConfig {
     pub parent: &'static K8sType,
     /// The type of each child resource that the operator will deal with.
     pub child_types: HashMap<&'static K8sType, ChildConfig>,
+    /// The types of related resources that the operator will watch, which are not owned by the parent
+    pub related_types: HashMap<&'static K8sType, RelatedConfig>,
     /// Optional namespace to constrain the operator to. If None, then the operator will monitor
     /// and act on any instance of the parent resource in any namespace. If Some, then the operator
     /// will only ever watch and modify resources in the given namespace.
     pub namespace: Option<String>,
 
+    /// Additional namespaces to constrain the operator to. If any are given, then the operator will watch and act on
+    /// resources in only these namespaces, plus the `namespace` if that is also set.
+    pub namespaces: Vec<String>,
+
+    /// Optional label selector for Namespaces. If `Some`, then the operator will also watch and act on resources in
+    /// every Namespace that matches it, and will start and stop watching Namespaces as they are created, deleted, or
+    /// relabeled.
+    pub namespace_selector: Option<String>,
+
+    /// Optional label selector for the parent watch. If `Some`, then the operator will only see and sync the
+    /// parents that match it. This can be used to shard parents across several deployments of the operator.
+    pub parent_label_selector: Option<String>,
+
+    /// Optional field selector for the parent watch, for example `metadata.name!=ignored`. If `Some`, then the
+    /// operator will only see and sync the parents that match it.
+    pub parent_field_selector: Option<String>,
+
     /// The name of the operator, which must consist of only ascii alphabetic characters and numerals.
     /// This value will be used to add a label to every child resource being managed by this operator,
     /// which will have the `operator_name` as its value.
@@ -96,6 +606,16 @@ pub struct OperatorConfig {
     /// The label to use for marking the `operator_name`. Defaults to `"kubernetes.io/managed-by"`
     pub ownership_label_name: String,
 
+    /// The annotation that records the desired state of each child that was last applied, for child types that are
+    /// configured with `ChildConfig::record_last_applied`. Any fields that were
+    /// included in the last applied state, but are no longer desired, are removed from the child. Defaults to
+    /// `"roperator.io/last-applied-configuration"`
+    pub last_applied_annotation_name: String,
+
+    /// The field manager to use for server-side apply requests, which are used for any child types
+    /// with an `UpdateStrategy::Apply`. Defaults to the `operator_name`.
+    pub field_manager: String,
+
     /// The HTTP port to listen on for exposing health checks and metrics. No server will be started
     /// if both `expose_metrics` and `expose_health` are `false`
     pub server_port: u16,
@@ -111,6 +631,42 @@ pub struct OperatorConfig {
     //// This is used to space out the time between `Handler::sync()` calls on the same parent resource in a uniform way. If `None`, no exponential backoff is performed.
     /// maximum period between requested resyncs
     pub max_error_backoff: Duration,
+
+    /// The maximum number of parents that may be synced concurrently. Parents that need synced while this many
+    /// syncs are already in progress will wait in a FIFO queue. Defaults to 32.
+    pub max_concurrent_syncs: usize,
+
+    /// The maximum amount of time to wait for in-progress syncs to complete during a graceful shutdown. Defaults to 30 seconds.
+    pub shutdown_timeout: Duration,
+
+    /// If true, then the operator will start a graceful shutdown when the process receives a SIGTERM or SIGINT.
+    /// This is disabled by default.
+    pub handle_signals: bool,
+
+    /// If true, then Kubernetes Events will be recorded against parent resources for sync and finalize failures,
+    /// changes to children, and changes to finalizers. This is enabled by default.
+    pub record_events: bool,
+
+    /// Functions for converting the parent between versions, which are used by the conversion webhook
+    pub conversions: Vec<Conversion>,
+
+    /// The version of the parent that's persisted by the api server, if it's different from the version in `parent`.
+    /// The operator always watches and syncs the version in `parent`, and relies on the conversion webhook to convert
+    /// between that and the storage version.
+    pub storage_version: Option<&'static K8sType>,
+
+    /// Optional `CustomResourceDefinition` for the parent, which will be created or updated when the operator starts.
+    /// The operator waits for the CRD to be established before it starts watching any resources.
+    pub crd: Option<Value>,
+
+    /// Optional configuration for the admission webhook server. If `Some`, then the operator will serve
+    /// validating and mutating admission webhooks for the parent type, which invoke `Handler::validate` and `Handler::mutate`.
+    pub webhook: Option<WebhookConfig>,
+
+    /// Optional configuration for leader election. If `Some`, then the operator will only sync parents
+    /// while it holds the configured Lease. Operators that are on standby will still watch all the
+    /// resources, so that they're ready to start syncing as soon as they acquire the Lease.
+    pub leader_election: Option<LeaderElectionConfig>,
 }
 
 impl OperatorConfig {
@@ -118,15 +674,31 @@ impl OperatorConfig {
         let operator_name = operator_name.into();
         OperatorConfig {
             parent,
+            field_manager: operator_name.clone(),
             operator_name,
             child_types: HashMap::new(),
+            related_types: HashMap::new(),
             namespace: None,
+            namespaces: Vec::new(),
+            namespace_selector: None,
+            parent_label_selector: None,
+            parent_field_selector: None,
             tracking_label_name: DEFAULT_TRACKING_LABEL_NAME.to_owned(),
             ownership_label_name: DEFAULT_OWNERSHIP_LABEL_NAME.to_owned(),
+            last_applied_annotation_name: DEFAULT_LAST_APPLIED_ANNOTATION_NAME.to_owned(),
             server_port: 8080,
             expose_metrics: true,
             expose_health: true,
             max_error_backoff: Duration::from_secs(600),
+            max_concurrent_syncs: 32,
+            shutdown_timeout: Duration::from_secs(30),
+            handle_signals: false,
+            record_events: true,
+            conversions: Vec::new(),
+            storage_version: None,
+            crd: None,
+            webhook: None,
+            leader_election: None,
         }
     }
 
@@ -137,6 +709,54 @@ impl OperatorConfig {
         self
     }
 
+    /// Constrain the operator to the given set of namespaces. This may be combined with `namespace_selector`, in which
+    /// case the operator will act on resources in these namespaces as well as any that match the selector.
+    pub fn within_namespaces<S: Into<String>>(
+        mut self,
+        namespaces: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.namespaces
+            .extend(namespaces.into_iter().map(Into::into));
+        self
+    }
+
+    /// Constrain the operator to the namespaces that match the given label selector. The operator's service account
+    /// must be allowed to `list` and `watch` Namespaces.
+    pub fn namespace_selector(mut self, label_selector: impl Into<String>) -> Self {
+        self.namespace_selector = Some(label_selector.into());
+        self
+    }
+
+    /// Returns all of the namespaces that the operator is statically constrained to
+    pub(crate) fn fixed_namespaces(&self) -> Vec<String> {
+        let mut namespaces = self
+            .namespace
+            .iter()
+            .chain(self.namespaces.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        namespaces.sort();
+        namespaces.dedup();
+        namespaces
+    }
+
+    /// Returns true if the operator watches resources in all namespaces
+    pub(crate) fn is_cluster_wide(&self) -> bool {
+        self.namespace.is_none() && self.namespaces.is_empty() && self.namespace_selector.is_none()
+    }
+
+    /// Only watch and sync the parents that match the given label selector
+    pub fn parent_label_selector(mut self, label_selector: impl Into<String>) -> Self {
+        self.parent_label_selector = Some(label_selector.into());
+        self
+    }
+
+    /// Only watch and sync the parents that match the given field selector
+    pub fn parent_field_selector(mut self, field_selector: impl Into<String>) -> Self {
+        self.parent_field_selector = Some(field_selector.into());
+        self
+    }
+
     /// Adds a new child type to this configuration. Every type of resource that the operator may manage
     /// must be included in the `OperatorConfig`.
     pub fn with_child(mut self, child_type: &'static K8sType, config: ChildConfig) -> Self {
@@ -144,6 +764,13 @@ impl OperatorConfig {
         self
     }
 
+    /// Adds a new related type to this configuration. Changes to any resource of this type will trigger a
+    /// sync of each parent that the `RelatedConfig` maps it to.
+    pub fn with_related(mut self, related_type: &'static K8sType, config: RelatedConfig) -> Self {
+        self.related_types.insert(related_type, config);
+        self
+    }
+
     /// Sets whether to expose a health check HTTP endpoint
     pub fn expose_health(mut self, expose_health: bool) -> Self {
         self.expose_health = expose_health;
@@ -162,10 +789,217 @@ impl OperatorConfig {
         self
     }
 
+    /// Sets the field manager that's used for server-side apply requests
+    pub fn field_manager(mut self, field_manager: impl Into<String>) -> Self {
+        self.field_manager = field_manager.into();
+        self
+    }
+
+    /// Sets the annotation that records the desired state of each child that was last applied
+    pub fn last_applied_annotation_name(mut self, annotation_name: impl Into<String>) -> Self {
+        self.last_applied_annotation_name = annotation_name.into();
+        self
+    }
+
     pub fn max_error_backoff(mut self, max_error_backoff: Duration) -> Self {
         self.max_error_backoff = max_error_backoff;
         self
     }
+
+    /// Sets the maximum number of parents that may be synced concurrently. Must be at least 1.
+    pub fn max_concurrent_syncs(mut self, max_concurrent_syncs: usize) -> Self {
+        self.max_concurrent_syncs = max_concurrent_syncs;
+        self
+    }
+
+    /// Sets the maximum amount of time to wait for in-progress syncs to complete during a graceful shutdown
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Sets whether to start a graceful shutdown when the process receives a SIGTERM or SIGINT. This is
+    /// typically what you want when running in Kubernetes, where pods are sent a SIGTERM before they're stopped.
+    pub fn handle_signals(mut self, handle_signals: bool) -> Self {
+        self.handle_signals = handle_signals;
+        self
+    }
+
+    /// Sets whether to record Kubernetes Events against parent resources. The operator's service account
+    /// must be allowed to `create` and `patch` Events in order for them to be recorded.
+    pub fn record_events(mut self, record_events: bool) -> Self {
+        self.record_events = record_events;
+        self
+    }
+
+    /// Adds a function for converting parents from one version to another. Conversions are served by the webhook
+    /// server at `/convert`, so `webhook` must also be configured. You'll typically want to add conversions in both directions
+    /// between each version that may be stored and the version that the operator watches.
+    pub fn with_conversion(
+        mut self,
+        from: &'static K8sType,
+        to: &'static K8sType,
+        convert: impl Fn(Value) -> Result<Value, anyhow::Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.conversions.push(Conversion::new(from, to, convert));
+        self
+    }
+
+    /// Sets the version of the parent that's persisted by the api server, for when it's different from the version
+    /// that the operator watches
+    pub fn storage_version(mut self, storage_version: &'static K8sType) -> Self {
+        self.storage_version = Some(storage_version);
+        self
+    }
+
+    /// Sets the `CustomResourceDefinition` to create or update when the operator starts, which is typically built
+    /// using `roperator::crd::CrdBuilder`. The operator's service account must be allowed to `get`, `create`, and
+    /// `update` CustomResourceDefinitions.
+    pub fn install_crd(mut self, crd: Value) -> Self {
+        self.crd = Some(crd);
+        self
+    }
+
+    /// Returns the conversion from one version to another, if one has been added
+    pub(crate) fn get_conversion(&self, from: &str, to: &str) -> Option<&Conversion> {
+        self.conversions
+            .iter()
+            .find(|c| c.from.api_version == from && c.to.api_version == to)
+    }
+
+    /// Enables the admission webhook server using the given configuration. You'll also need to create a
+    /// `ValidatingWebhookConfiguration` and/or `MutatingWebhookConfiguration` that sends requests for your parent
+    /// type to the `/validate` and `/mutate` paths.
+    pub fn webhook(mut self, config: WebhookConfig) -> Self {
+        self.webhook = Some(config);
+        self
+    }
+
+    /// Enables leader election using the given configuration. This allows running multiple replicas
+    /// of the operator, where only the one holding the Lease will sync parent resources.
+    pub fn leader_election(mut self, config: LeaderElectionConfig) -> Self {
+        self.leader_election = Some(config);
+        self
+    }
+}
+
+/// Configuration for the admission webhook server. The server handles `AdmissionReview` requests at `/validate`
+/// and `/mutate`, and dispatches them to `Handler::validate` and `Handler::mutate`. The api server requires
+/// webhooks to be served over HTTPS, so `WebhookConfig::tls` is what you'll want in a real cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    /// The port to listen on. Defaults to 8443
+    pub port: u16,
+
+    /// The certificate and private key to use for serving HTTPS. If `None`, then the server will use plain HTTP,
+    /// which is only useful for testing or when running behind a proxy that terminates TLS.
+    pub tls: Option<WebhookTlsConfig>,
+}
+
+/// Paths to the PEM encoded certificate and private key to use for serving the admission webhooks
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookTlsConfig {
+    /// Path to the certificate chain. The first certificate in the file must be the server certificate
+    pub certificate_path: String,
+    pub private_key_path: String,
+}
+
+impl WebhookConfig {
+    /// Creates a new configuration that serves HTTPS using the given PEM files, which is typically a Secret
+    /// that's mounted into the operator's pod
+    pub fn tls(
+        certificate_path: impl Into<String>,
+        private_key_path: impl Into<String>,
+    ) -> WebhookConfig {
+        WebhookConfig {
+            port: 8443,
+            tls: Some(WebhookTlsConfig {
+                certificate_path: certificate_path.into(),
+                private_key_path: private_key_path.into(),
+            }),
+        }
+    }
+
+    /// Creates a new configuration that serves plain HTTP
+    pub fn insecure() -> WebhookConfig {
+        WebhookConfig {
+            port: 8443,
+            tls: None,
+        }
+    }
+
+    /// Sets the port to listen on
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+}
+
+/// Configuration for leader election, which uses a `coordination.k8s.io/v1` Lease to ensure that only
+/// one replica of an operator is syncing parents at a time. The operator's service account must be
+/// allowed to `get`, `create`, and `update` Leases in the `lease_namespace`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaderElectionConfig {
+    /// The name of the Lease resource
+    pub lease_name: String,
+    /// The namespace of the Lease resource
+    pub lease_namespace: String,
+    /// The identity of this replica, which is unique among all replicas of the operator. Defaults to the
+    /// value of the `HOSTNAME` environment variable, which is the pod name when running in Kubernetes.
+    pub identity: String,
+    /// How long other replicas must wait after the last observed renewal before they may acquire the Lease
+    pub lease_duration: Duration,
+    /// How long the leader will keep trying to renew the Lease before it stops syncing
+    pub renew_deadline: Duration,
+    /// How long to wait between attempts to acquire or renew the Lease
+    pub retry_period: Duration,
+}
+
+impl LeaderElectionConfig {
+    /// Returns a new `LeaderElectionConfig` with default durations that match those used by
+    /// the builtin Kubernetes controllers
+    pub fn new(
+        lease_name: impl Into<String>,
+        lease_namespace: impl Into<String>,
+    ) -> LeaderElectionConfig {
+        let identity = std::env::var("HOSTNAME")
+            .ok()
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| format!("roperator-{}", std::process::id()));
+        LeaderElectionConfig {
+            lease_name: lease_name.into(),
+            lease_namespace: lease_namespace.into(),
+            identity,
+            lease_duration: Duration::from_secs(15),
+            renew_deadline: Duration::from_secs(10),
+            retry_period: Duration::from_secs(2),
+        }
+    }
+
+    /// Sets the identity of this replica
+    pub fn identity(mut self, identity: impl Into<String>) -> Self {
+        self.identity = identity.into();
+        self
+    }
+
+    /// Sets the duration of the Lease
+    pub fn lease_duration(mut self, lease_duration: Duration) -> Self {
+        self.lease_duration = lease_duration;
+        self
+    }
+
+    /// Sets how long the leader will try to renew the Lease before giving up leadership. This should
+    /// be less than the `lease_duration`.
+    pub fn renew_deadline(mut self, renew_deadline: Duration) -> Self {
+        self.renew_deadline = renew_deadline;
+        self
+    }
+
+    /// Sets the time to wait between attempts to acquire or renew the Lease
+    pub fn retry_period(mut self, retry_period: Duration) -> Self {
+        self.retry_period = retry_period;
+        self
+    }
 }
 
 /// Certificate Authority data for verifying Kubernetes TLS certificates. This typically comes from either a
@@ -0,0 +1,335 @@
+//! Helpers for generating the `CustomResourceDefinition` for your parent type. The schema for each version can be
+//! derived from the Rust types that you deserialize the parent's spec and status into, which ensures that the CRD
+//! never drifts from your code.
+//!
+//! ```rust
+//! #[macro_use]
+//! extern crate serde_derive;
+//!
+//! use roperator::crd::{CrdBuilder, CrdVersion};
+//! use roperator::prelude::K8sType;
+//!
+//! static PARENT_TYPE: &K8sType = &K8sType {
+//!    api_version: "example.com/v1",
+//!    kind: "Foo",
+//!    plural_kind: "foos",
+//! };
+//!
+//! #[derive(Deserialize)]
+//! struct FooSpec {
+//!     replicas: i32,
+//!     image: Option<String>,
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct FooStatus {
+//!     ready: bool,
+//! }
+//!
+//! fn main() {
+//!     let crd = CrdBuilder::new()
+//!         .version(CrdVersion::from_types::<FooSpec, FooStatus>(PARENT_TYPE).unwrap())
+//!         .build()
+//!         .unwrap();
+//!     assert_eq!("foos.example.com", crd["metadata"]["name"]);
+//!     assert_eq!(
+//!         roperator::serde_json::json!(["replicas"]),
+//!         crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"]["required"]
+//!     );
+//! }
+//! ```
+mod schema;
+
+use crate::k8s_types::apiextensions_k8s_io::v1::CustomResourceDefinition;
+use crate::k8s_types::K8sType;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use std::fmt::{self, Display};
+
+pub use self::schema::{parent_schema, schema_for};
+
+/// Returned when a schema can't be derived for a type, or when the CRD is invalid
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrdError {
+    pub message: String,
+}
+
+impl CrdError {
+    pub fn new(message: impl Into<String>) -> CrdError {
+        CrdError {
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for CrdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid CustomResourceDefinition: {}", self.message)
+    }
+}
+
+impl std::error::Error for CrdError {}
+
+/// A single version of the CRD
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrdVersion {
+    pub k8s_type: &'static K8sType,
+    /// The `openAPIV3Schema` for this version
+    pub schema: Value,
+    pub served: bool,
+    /// Exactly one version must be the storage version. If no version is explicitly set as the storage version, then
+    /// the first one will be used.
+    pub storage: bool,
+    /// Whether to enable the `/status` subresource, which roperator uses to update the parent status. Defaults to true.
+    pub status_subresource: bool,
+}
+
+impl CrdVersion {
+    /// Creates a new version with the given `openAPIV3Schema`
+    pub fn new(k8s_type: &'static K8sType, schema: Value) -> CrdVersion {
+        CrdVersion {
+            k8s_type,
+            schema,
+            served: true,
+            storage: false,
+            status_subresource: true,
+        }
+    }
+
+    /// Creates a new version with a schema that's derived from the `Deserialize` impls of the given spec and status types
+    pub fn from_types<Spec: DeserializeOwned, Status: DeserializeOwned>(
+        k8s_type: &'static K8sType,
+    ) -> Result<CrdVersion, CrdError> {
+        parent_schema::<Spec, Status>().map(|schema| CrdVersion::new(k8s_type, schema))
+    }
+
+    pub fn served(mut self, served: bool) -> Self {
+        self.served = served;
+        self
+    }
+
+    pub fn storage(mut self, storage: bool) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn status_subresource(mut self, status_subresource: bool) -> Self {
+        self.status_subresource = status_subresource;
+        self
+    }
+}
+
+/// Builds an `apiextensions.k8s.io/v1` `CustomResourceDefinition` for the parent type
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CrdBuilder {
+    versions: Vec<CrdVersion>,
+    cluster_scoped: bool,
+    short_names: Vec<String>,
+    conversion_webhook: Option<Value>,
+}
+
+impl CrdBuilder {
+    pub fn new() -> CrdBuilder {
+        CrdBuilder::default()
+    }
+
+    /// Adds a version to the CRD. All versions must have the same group and kind
+    pub fn version(mut self, version: CrdVersion) -> Self {
+        self.versions.push(version);
+        self
+    }
+
+    /// Makes the CRD cluster scoped. CRDs are namespaced by default
+    pub fn cluster_scoped(mut self) -> Self {
+        self.cluster_scoped = true;
+        self
+    }
+
+    pub fn short_name(mut self, short_name: impl Into<String>) -> Self {
+        self.short_names.push(short_name.into());
+        self
+    }
+
+    /// Configures the CRD to use the operator's conversion webhook, which is served at `/convert` by the webhook
+    /// server. The `ca_bundle` is the base64 encoded PEM of the CA that signed the webhook's certificate.
+    pub fn conversion_webhook(
+        mut self,
+        service_namespace: impl Into<String>,
+        service_name: impl Into<String>,
+        port: u16,
+        ca_bundle: impl Into<String>,
+    ) -> Self {
+        self.conversion_webhook = Some(json!({
+            "strategy": "Webhook",
+            "webhook": {
+                "conversionReviewVersions": ["v1", "v1beta1"],
+                "clientConfig": {
+                    "service": {
+                        "namespace": service_namespace.into(),
+                        "name": service_name.into(),
+                        "path": "/convert",
+                        "port": port,
+                    },
+                    "caBundle": ca_bundle.into(),
+                },
+            },
+        }));
+        self
+    }
+
+    /// Returns the CRD as JSON
+    pub fn build(self) -> Result<Value, CrdError> {
+        let first = self
+            .versions
+            .first()
+            .ok_or_else(|| CrdError::new("at least one version is required"))?
+            .k8s_type;
+        let group = first.group();
+        if group.is_empty() {
+            return Err(CrdError::new("custom resources must have a group"));
+        }
+        let mismatched = self
+            .versions
+            .iter()
+            .find(|v| v.k8s_type.group() != group || v.k8s_type.kind != first.kind);
+        if let Some(version) = mismatched {
+            return Err(CrdError::new(format!(
+                "version '{}' does not have the same group and kind as '{}'",
+                version.k8s_type, first
+            )));
+        }
+        let storage_count = self.versions.iter().filter(|v| v.storage).count();
+        if storage_count > 1 {
+            return Err(CrdError::new("only one version may be the storage version"));
+        }
+
+        let versions = self
+            .versions
+            .iter()
+            .enumerate()
+            .map(|(i, version)| {
+                let mut json = json!({
+                    "name": version.k8s_type.version(),
+                    "served": version.served,
+                    "storage": version.storage || (storage_count == 0 && i == 0),
+                    "schema": {
+                        "openAPIV3Schema": version.schema,
+                    },
+                });
+                if version.status_subresource {
+                    json["subresources"] = json!({ "status": {} });
+                }
+                json
+            })
+            .collect::<Vec<_>>();
+
+        let mut crd = json!({
+            "apiVersion": CustomResourceDefinition.api_version,
+            "kind": CustomResourceDefinition.kind,
+            "metadata": {
+                "name": format!("{}.{}", first.plural_kind, group),
+            },
+            "spec": {
+                "group": group,
+                "scope": if self.cluster_scoped { "Cluster" } else { "Namespaced" },
+                "names": {
+                    "kind": first.kind,
+                    "plural": first.plural_kind,
+                    "singular": first.kind.to_lowercase(),
+                    "shortNames": self.short_names,
+                },
+                "versions": versions,
+            },
+        });
+        if let Some(conversion) = self.conversion_webhook {
+            crd["spec"]["conversion"] = conversion;
+        }
+        Ok(crd)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static V1ALPHA1: &K8sType = &K8sType {
+        api_version: "example.com/v1alpha1",
+        kind: "Foo",
+        plural_kind: "foos",
+    };
+
+    static V1: &K8sType = &K8sType {
+        api_version: "example.com/v1",
+        kind: "Foo",
+        plural_kind: "foos",
+    };
+
+    #[test]
+    fn builds_crd_with_multiple_versions() {
+        let schema = json!({"type": "object"});
+        let crd = CrdBuilder::new()
+            .version(CrdVersion::new(V1ALPHA1, schema.clone()).storage(true))
+            .version(CrdVersion::new(V1, schema.clone()).status_subresource(false))
+            .short_name("fo")
+            .build()
+            .unwrap();
+        let expected = json!({
+            "apiVersion": "apiextensions.k8s.io/v1",
+            "kind": "CustomResourceDefinition",
+            "metadata": {
+                "name": "foos.example.com",
+            },
+            "spec": {
+                "group": "example.com",
+                "scope": "Namespaced",
+                "names": {
+                    "kind": "Foo",
+                    "plural": "foos",
+                    "singular": "foo",
+                    "shortNames": ["fo"],
+                },
+                "versions": [
+                    {
+                        "name": "v1alpha1",
+                        "served": true,
+                        "storage": true,
+                        "schema": {"openAPIV3Schema": schema},
+                        "subresources": {"status": {}},
+                    },
+                    {
+                        "name": "v1",
+                        "served": true,
+                        "storage": false,
+                        "schema": {"openAPIV3Schema": schema},
+                    },
+                ],
+            },
+        });
+        assert_eq!(expected, crd);
+    }
+
+    #[test]
+    fn invalid_crds_return_errors() {
+        let schema = json!({"type": "object"});
+        assert!(CrdBuilder::new().build().is_err());
+
+        let result = CrdBuilder::new()
+            .version(CrdVersion::new(V1ALPHA1, schema.clone()).storage(true))
+            .version(CrdVersion::new(V1, schema.clone()).storage(true))
+            .build();
+        assert!(result.is_err());
+
+        static OTHER: &K8sType = &K8sType {
+            api_version: "other.com/v1",
+            kind: "Foo",
+            plural_kind: "foos",
+        };
+        let result = CrdBuilder::new()
+            .version(CrdVersion::new(V1, schema.clone()))
+            .version(CrdVersion::new(OTHER, schema))
+            .build();
+        assert!(result.is_err());
+    }
+}
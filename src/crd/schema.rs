@@ -0,0 +1,657 @@
+//! Derives an OpenAPI v3 schema from a type's `Deserialize` impl. This works by deserializing the type from a special
+//! `Deserializer` that records every value that the type asks for, so the schema always matches the way your types
+//! are actually deserialized, including any `#[serde(rename)]` attributes. Fields are required unless they're an
+//! `Option` or have a `#[serde(default)]`, which is determined by attempting to deserialize the type again with
+//! each field omitted.
+//!
+//! There are some limitations. Enums are represented as a string if their first variant is a unit variant, and
+//! otherwise accept any value. Untagged enums, flattened structs, and types that reject the placeholder values
+//! (empty strings and zeros) can't be traced, and will return an error. Recursive types are also unsupported.
+use super::CrdError;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde_json::{json, Map, Value};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+/// Types nested deeper than this are assumed to be recursive
+const MAX_DEPTH: usize = 32;
+
+/// Returns the OpenAPI v3 schema for the given type
+pub fn schema_for<T: DeserializeOwned>() -> Result<Value, CrdError> {
+    let first_pass = TraceState::new(None, HashSet::new());
+    trace::<T>(&first_pass)?;
+
+    let mut required = HashSet::new();
+    for (path, field) in first_pass.fields.into_inner() {
+        let probe = TraceState::new(Some((path.clone(), field)), HashSet::new());
+        if trace::<T>(&probe).is_err() {
+            required.insert((path, field));
+        }
+    }
+
+    let state = TraceState::new(None, required);
+    trace::<T>(&state)
+}
+
+/// Returns the schema for a parent resource with the given spec and status types. The `apiVersion`, `kind`, and
+/// `metadata` are implicit in every CRD, so they're not included.
+pub fn parent_schema<Spec: DeserializeOwned, Status: DeserializeOwned>() -> Result<Value, CrdError>
+{
+    let spec = schema_for::<Spec>()?;
+    let status = schema_for::<Status>()?;
+    Ok(json!({
+        "type": "object",
+        "properties": {
+            "spec": spec,
+            "status": status,
+        },
+    }))
+}
+
+fn trace<T: DeserializeOwned>(state: &TraceState) -> Result<Value, CrdError> {
+    let slot = Slot::default();
+    let tracer = Tracer {
+        state,
+        path: String::new(),
+        depth: 0,
+        slot: slot.clone(),
+    };
+    T::deserialize(tracer).map_err(|err| CrdError::new(err.to_string()))?;
+    Ok(slot.take())
+}
+
+struct TraceState {
+    /// The struct field that should be omitted, identified by the path of the struct and the field name
+    omit: Option<(String, &'static str)>,
+    required: HashSet<(String, &'static str)>,
+    /// every struct field that was encountered
+    fields: RefCell<Vec<(String, &'static str)>>,
+}
+
+impl TraceState {
+    fn new(
+        omit: Option<(String, &'static str)>,
+        required: HashSet<(String, &'static str)>,
+    ) -> TraceState {
+        TraceState {
+            omit,
+            required,
+            fields: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn is_omitted(&self, path: &str, field: &str) -> bool {
+        self.omit
+            .as_ref()
+            .map(|(p, f)| p == path && *f == field)
+            .unwrap_or(false)
+    }
+}
+
+/// Holds the schema of a single value once it's been traced
+#[derive(Clone, Default)]
+struct Slot(Rc<RefCell<Value>>);
+
+impl Slot {
+    fn set(&self, schema: Value) {
+        *self.0.borrow_mut() = schema;
+    }
+
+    fn take(&self) -> Value {
+        self.0.replace(Value::Null)
+    }
+}
+
+#[derive(Debug)]
+enum TraceError {
+    MissingField(&'static str),
+    Custom(String),
+}
+
+impl Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::MissingField(field) => write!(f, "missing field '{}'", field),
+            TraceError::Custom(message) => write!(f, "failed to trace schema: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T: Display>(msg: T) -> Self {
+        TraceError::Custom(msg.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        TraceError::MissingField(field)
+    }
+}
+
+struct Tracer<'s> {
+    state: &'s TraceState,
+    path: String,
+    depth: usize,
+    slot: Slot,
+}
+
+impl<'s> Tracer<'s> {
+    fn child(&self, path_segment: &str) -> Result<(Tracer<'s>, Slot), TraceError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(TraceError::Custom(format!(
+                "type is nested too deeply at '{}', recursive types are not supported",
+                self.path
+            )));
+        }
+        let slot = Slot::default();
+        let tracer = Tracer {
+            state: self.state,
+            path: format!("{}{}", self.path, path_segment),
+            depth: self.depth + 1,
+            slot: slot.clone(),
+        };
+        Ok((tracer, slot))
+    }
+
+    fn primitive(&self, schema: Value) {
+        self.slot.set(schema);
+    }
+}
+
+impl<'de, 's> de::Deserializer<'de> for Tracer<'s> {
+    type Error = TraceError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({ "x-kubernetes-preserve-unknown-fields": true }));
+        visitor.visit_unit()
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "boolean"}));
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int32"}));
+        visitor.visit_i8(0)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int32"}));
+        visitor.visit_i16(0)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int32"}));
+        visitor.visit_i32(0)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int64"}));
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int32", "minimum": 0}));
+        visitor.visit_u8(0)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int32", "minimum": 0}));
+        visitor.visit_u16(0)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int64", "minimum": 0}));
+        visitor.visit_u32(0)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "integer", "format": "int64", "minimum": 0}));
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "number", "format": "float"}));
+        visitor.visit_f32(0.0)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "number", "format": "double"}));
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "string", "minLength": 1, "maxLength": 1}));
+        visitor.visit_char('a')
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "string"}));
+        visitor.visit_str("")
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "string"}));
+        visitor.visit_string(String::new())
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "string", "format": "byte"}));
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "string", "format": "byte"}));
+        visitor.visit_byte_buf(Vec::new())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let (inner, inner_slot) = self.child("")?;
+        let result = visitor.visit_some(inner);
+        let mut schema = inner_slot.take();
+        if let Some(obj) = schema.as_object_mut() {
+            obj.insert("nullable".to_owned(), Value::Bool(true));
+        }
+        self.slot.set(schema);
+        result
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.primitive(json!({"type": "object"}));
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let (inner, inner_slot) = self.child("")?;
+        let result = visitor.visit_newtype_struct(inner);
+        self.slot.set(inner_slot.take());
+        result
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let (item, item_slot) = self.child("[]")?;
+        let result = visitor.visit_seq(TraceSeq { items: vec![item] });
+        self.slot.set(json!({
+            "type": "array",
+            "items": item_slot.take(),
+        }));
+        result
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        // tuples may contain different types, which can't be represented by a structural schema
+        let items = (0..len)
+            .map(|i| self.child(&format!("[{}]", i)).map(|(item, _)| item))
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = visitor.visit_seq(TraceSeq { items });
+        self.slot.set(json!({
+            "type": "array",
+            "items": { "x-kubernetes-preserve-unknown-fields": true },
+        }));
+        result
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let (key, _) = self.child("{key}")?;
+        let (value, value_slot) = self.child("{}")?;
+        let result = visitor.visit_map(TraceMap {
+            key: Some(key),
+            value: Some(value),
+        });
+        self.slot.set(json!({
+            "type": "object",
+            "additionalProperties": value_slot.take(),
+        }));
+        result
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let state = self.state;
+        let mut access = TraceStruct {
+            tracer: &self,
+            fields: fields
+                .iter()
+                .cloned()
+                .filter(|field| !state.is_omitted(&self.path, field))
+                .collect(),
+            index: 0,
+            slots: Vec::new(),
+        };
+        let result = visitor.visit_map(&mut access);
+
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        for (field, slot) in access.slots {
+            if state.required.contains(&(self.path.clone(), field)) {
+                required.push(Value::String(field.to_owned()));
+            }
+            properties.insert(field.to_owned(), slot.take());
+        }
+        let mut schema = json!({
+            "type": "object",
+            "properties": properties,
+        });
+        if !required.is_empty() {
+            schema["required"] = Value::Array(required);
+        }
+        self.slot.set(schema);
+        result
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let variant = variants.first().ok_or_else(|| {
+            TraceError::Custom(format!("enum at '{}' has no variants", self.path))
+        })?;
+        let is_unit = Rc::new(Cell::new(false));
+        let result = visitor.visit_enum(TraceEnum {
+            tracer: &self,
+            variant,
+            is_unit: is_unit.clone(),
+        });
+        if is_unit.get() {
+            self.slot.set(json!({
+                "type": "string",
+                "enum": variants,
+            }));
+        } else {
+            self.slot
+                .set(json!({ "x-kubernetes-preserve-unknown-fields": true }));
+        }
+        result
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_any(visitor)
+    }
+}
+
+struct TraceSeq<'s> {
+    items: Vec<Tracer<'s>>,
+}
+
+impl<'de, 's> SeqAccess<'de> for TraceSeq<'s> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        if self.items.is_empty() {
+            Ok(None)
+        } else {
+            let item = self.items.remove(0);
+            seed.deserialize(item).map(Some)
+        }
+    }
+}
+
+struct TraceMap<'s> {
+    key: Option<Tracer<'s>>,
+    value: Option<Tracer<'s>>,
+}
+
+impl<'de, 's> MapAccess<'de> for TraceMap<'s> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        match self.key.take() {
+            Some(key) => seed.deserialize(key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| TraceError::Custom("map value was requested twice".to_owned()))?;
+        seed.deserialize(value)
+    }
+}
+
+struct TraceStruct<'a, 's> {
+    tracer: &'a Tracer<'s>,
+    fields: Vec<&'static str>,
+    index: usize,
+    slots: Vec<(&'static str, Slot)>,
+}
+
+impl<'de, 'a, 's> MapAccess<'de> for TraceStruct<'a, 's> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        match self.fields.get(self.index) {
+            Some(field) => {
+                let field: de::value::StrDeserializer<TraceError> = field.into_deserializer();
+                seed.deserialize(field).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let field = self.fields[self.index];
+        self.index += 1;
+        let (value, slot) = self.tracer.child(&format!(".{}", field))?;
+        self.tracer
+            .state
+            .fields
+            .borrow_mut()
+            .push((self.tracer.path.clone(), field));
+        self.slots.push((field, slot));
+        seed.deserialize(value)
+    }
+}
+
+struct TraceEnum<'a, 's> {
+    tracer: &'a Tracer<'s>,
+    variant: &'static str,
+    is_unit: Rc<Cell<bool>>,
+}
+
+impl<'de, 'a, 's> EnumAccess<'de> for TraceEnum<'a, 's> {
+    type Error = TraceError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), TraceError> {
+        let variant: de::value::StrDeserializer<TraceError> = self.variant.into_deserializer();
+        let value = seed.deserialize(variant)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'a, 's> VariantAccess<'de> for TraceEnum<'a, 's> {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        self.is_unit.set(true);
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, TraceError> {
+        let (value, _) = self.tracer.child(&format!("::{}", self.variant))?;
+        seed.deserialize(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let (value, _) = self.tracer.child(&format!("::{}", self.variant))?;
+        de::Deserializer::deserialize_tuple(value, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let (value, _) = self.tracer.child(&format!("::{}", self.variant))?;
+        de::Deserializer::deserialize_struct(value, self.variant, fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    #[allow(dead_code)]
+    struct Spec {
+        replicas: i32,
+        image_name: String,
+        #[serde(default)]
+        verbose: bool,
+        pull_policy: Option<PullPolicy>,
+        ports: Vec<Port>,
+        labels: BTreeMap<String, String>,
+        extra: Value,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum PullPolicy {
+        Always,
+        IfNotPresent,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Port {
+        port: u16,
+        name: Option<String>,
+    }
+
+    #[test]
+    fn schema_is_derived_from_deserialize_impl() {
+        let schema = schema_for::<Spec>().expect("failed to get schema");
+        let expected = json!({
+            "type": "object",
+            "properties": {
+                "replicas": {"type": "integer", "format": "int32"},
+                "imageName": {"type": "string"},
+                "verbose": {"type": "boolean"},
+                "pullPolicy": {
+                    "type": "string",
+                    "enum": ["Always", "IfNotPresent"],
+                    "nullable": true,
+                },
+                "ports": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "port": {"type": "integer", "format": "int32", "minimum": 0},
+                            "name": {"type": "string", "nullable": true},
+                        },
+                        "required": ["port"],
+                    },
+                },
+                "labels": {
+                    "type": "object",
+                    "additionalProperties": {"type": "string"},
+                },
+                "extra": {"x-kubernetes-preserve-unknown-fields": true},
+            },
+            "required": ["replicas", "imageName", "ports", "labels", "extra"],
+        });
+        assert_eq!(expected, schema);
+    }
+
+    #[test]
+    fn parent_schema_includes_spec_and_status() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Status {
+            ready: bool,
+        }
+        let schema = parent_schema::<Port, Option<Status>>().unwrap();
+        assert_eq!(
+            Some(&json!("integer")),
+            schema.pointer("/properties/spec/properties/port/type")
+        );
+        assert_eq!(
+            Some(&json!(true)),
+            schema.pointer("/properties/status/nullable")
+        );
+    }
+
+    #[test]
+    fn recursive_types_return_an_error() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Node {
+            children: Vec<Node>,
+        }
+        assert!(schema_for::<Node>().is_err());
+    }
+}
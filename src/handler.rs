@@ -5,6 +5,9 @@
 //! snapshot of the state of a given parent resource, along with any children that currently exist for it.
 //! This request struct has lots of functions on it for accessing and deserializing child resources.
 
+/// Types used for validating and mutating admission webhooks
+pub mod admission;
+
 /// Helpers for implementing handlers that may recover from their own errors
 #[cfg(any(feature = "failable", docs))]
 pub mod failable;
@@ -17,11 +20,14 @@ pub mod request;
 mod request;
 
 use anyhow::Error;
+use futures::future::BoxFuture;
 use serde::Serialize;
 use serde_json::Value;
-use std::fmt::{self, Debug};
+use std::fmt::{self, Debug, Display};
+use std::sync::Arc;
 use std::time::Duration;
 
+pub use self::admission::{AdmissionRequest, AdmissionResponse};
 pub use self::request::{RawView, RequestChildren, SyncRequest, TypedIter, TypedView};
 /// The return value from your handler function, which has the status to set for the parent, as well as any
 /// desired child resources. Any existing child resources that are **not** included in this response **will be deleted**.
@@ -155,6 +161,27 @@ pub trait Handler: Send + Sync + 'static {
             retry: None,
         })
     }
+
+    /// Invoked by the validating admission webhook whenever a parent is created, updated, or deleted. This is only
+    /// used if the webhook server is enabled using `OperatorConfig::webhook`. Returning `AdmissionResponse::deny`
+    /// will cause the api server to reject the request, so invalid resources never get persisted or synced.
+    ///
+    /// The default implementation allows every request. If this function returns an `Err`, then the request
+    /// will be denied.
+    fn validate(&self, _request: &AdmissionRequest) -> Result<AdmissionResponse, Error> {
+        Ok(AdmissionResponse::allow())
+    }
+
+    /// Invoked by the mutating admission webhook whenever a parent is created, updated, or deleted. This is only
+    /// used if the webhook server is enabled using `OperatorConfig::webhook`. This is typically used to set default
+    /// values, by returning an `AdmissionResponse::patch` with the JSON patch operations to apply. Mutating webhooks are
+    /// invoked by the api server before validating webhooks.
+    ///
+    /// The default implementation allows every request without modification. If this function returns an `Err`,
+    /// then the request will be denied.
+    fn mutate(&self, _request: &AdmissionRequest) -> Result<AdmissionResponse, Error> {
+        Ok(AdmissionResponse::allow())
+    }
 }
 
 impl<F> Handler for F
@@ -183,3 +210,130 @@ where
         })
     }
 }
+
+/// An asynchronous version of the `Handler` trait. The futures returned by these functions are driven directly on the
+/// operator's tokio runtime, so they must not block the thread. This is useful for handlers that need to call out to
+/// HTTP services or databases using async clients. Every `Handler` automatically implements `AsyncHandler` by invoking
+/// the synchronous functions using `tokio::task::spawn_blocking`, so you only need to implement one or the other.
+///
+/// The semantics of `sync` and `finalize` are exactly the same as the corresponding functions on `Handler`. The only
+/// difference is that the request is passed as an `Arc<SyncRequest>`, since the returned future must be `'static`.
+pub trait AsyncHandler: Send + Sync + 'static {
+    /// Asynchronous equivalent of `Handler::sync`
+    fn sync(
+        self: Arc<Self>,
+        request: Arc<SyncRequest>,
+    ) -> BoxFuture<'static, Result<SyncResponse, Error>>;
+
+    /// Asynchronous equivalent of `Handler::finalize`. The default implementation simply allows the deletion to
+    /// proceed and does not modify the status.
+    fn finalize(
+        self: Arc<Self>,
+        request: Arc<SyncRequest>,
+    ) -> BoxFuture<'static, Result<FinalizeResponse, Error>> {
+        Box::pin(async move {
+            Ok(FinalizeResponse {
+                status: request.parent.status().cloned().unwrap_or(Value::Null),
+                retry: None,
+            })
+        })
+    }
+
+    /// Asynchronous equivalent of `Handler::validate`. The default implementation allows every request.
+    fn validate(
+        self: Arc<Self>,
+        _request: Arc<AdmissionRequest>,
+    ) -> BoxFuture<'static, Result<AdmissionResponse, Error>> {
+        Box::pin(async move { Ok(AdmissionResponse::allow()) })
+    }
+
+    /// Asynchronous equivalent of `Handler::mutate`. The default implementation allows every request without modification.
+    fn mutate(
+        self: Arc<Self>,
+        _request: Arc<AdmissionRequest>,
+    ) -> BoxFuture<'static, Result<AdmissionResponse, Error>> {
+        Box::pin(async move { Ok(AdmissionResponse::allow()) })
+    }
+}
+
+impl<H: Handler> AsyncHandler for H {
+    fn sync(
+        self: Arc<Self>,
+        request: Arc<SyncRequest>,
+    ) -> BoxFuture<'static, Result<SyncResponse, Error>> {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || Handler::sync(self.as_ref(), &request))
+                .await
+                .map_err(|_| Error::new(HandlerPanic))?
+        })
+    }
+
+    fn finalize(
+        self: Arc<Self>,
+        request: Arc<SyncRequest>,
+    ) -> BoxFuture<'static, Result<FinalizeResponse, Error>> {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || Handler::finalize(self.as_ref(), &request))
+                .await
+                .map_err(|_| Error::new(HandlerPanic))?
+        })
+    }
+
+    fn validate(
+        self: Arc<Self>,
+        request: Arc<AdmissionRequest>,
+    ) -> BoxFuture<'static, Result<AdmissionResponse, Error>> {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || Handler::validate(self.as_ref(), &request))
+                .await
+                .map_err(|_| Error::new(HandlerPanic))?
+        })
+    }
+
+    fn mutate(
+        self: Arc<Self>,
+        request: Arc<AdmissionRequest>,
+    ) -> BoxFuture<'static, Result<AdmissionResponse, Error>> {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || Handler::mutate(self.as_ref(), &request))
+                .await
+                .map_err(|_| Error::new(HandlerPanic))?
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct HandlerPanic;
+impl std::error::Error for HandlerPanic {}
+
+impl Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Handler paniced")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::request::test_request;
+
+    #[test]
+    fn handler_functions_are_usable_as_async_handlers() {
+        let handler = Arc::new(|req: &SyncRequest| {
+            let status = serde_json::json!({ "name": req.parent.name() });
+            Ok(SyncResponse::new(status))
+        });
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let request = Arc::new(test_request());
+
+        let response = runtime
+            .block_on(AsyncHandler::sync(handler.clone(), request.clone()))
+            .expect("sync returned error");
+        assert_eq!(request.parent.name(), response.status["name"]);
+
+        let finalize_response = runtime
+            .block_on(AsyncHandler::finalize(handler, request))
+            .expect("finalize returned error");
+        assert!(finalize_response.retry.is_none());
+    }
+}
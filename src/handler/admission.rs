@@ -0,0 +1,103 @@
+//! Types for validating and mutating admission webhooks. These are only used if the webhook server is enabled using
+//! `OperatorConfig::webhook`, in which case the api server will send `AdmissionReview` requests for your parent
+//! resources to the operator, and roperator will invoke `Handler::validate` or `Handler::mutate` with the decoded
+//! `AdmissionRequest`.
+use serde_json::Value;
+
+/// The operation that's being performed on the resource
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Connect,
+}
+
+/// Information about the user that's making the request
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub uid: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// The `request` from an `AdmissionReview`. Note that `object` is a plain `Value` instead of a `K8sResource`, since
+/// the resource may not exist yet, and so fields like `metadata.uid` may not be populated.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionRequest {
+    /// Uniquely identifies this admission call. This is copied into the response automatically.
+    pub uid: String,
+
+    pub operation: Operation,
+
+    /// The name of the resource. This may be empty for CREATE operations if the resource uses `generateName`
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub namespace: Option<String>,
+
+    /// The new state of the resource. This will be `Null` for DELETE operations
+    #[serde(default)]
+    pub object: Value,
+
+    /// The existing state of the resource. This will be `Null` for CREATE operations
+    #[serde(default)]
+    pub old_object: Value,
+
+    #[serde(default)]
+    pub user_info: UserInfo,
+
+    /// If true, then the api server will not persist the resource, so any side effects should be avoided
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// The result of validating or mutating a resource. A response that's allowed may also include a JSON patch, which
+/// will be applied to the resource by the api server. Patches are only honored for mutating webhooks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdmissionResponse {
+    /// Whether the request should be allowed
+    pub allowed: bool,
+
+    /// The reason that's shown to the user when the request is denied
+    pub reason: Option<String>,
+
+    /// A list of JSON patch operations to apply to the resource, e.g. `[{"op": "add", "path": "/spec/foo", "value": 1}]`
+    pub patch: Option<Value>,
+}
+
+impl AdmissionResponse {
+    /// Allows the request without making any changes
+    pub fn allow() -> AdmissionResponse {
+        AdmissionResponse {
+            allowed: true,
+            reason: None,
+            patch: None,
+        }
+    }
+
+    /// Denies the request, with a reason that will be shown to the user
+    pub fn deny(reason: impl Into<String>) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed: false,
+            reason: Some(reason.into()),
+            patch: None,
+        }
+    }
+
+    /// Allows the request after applying the given JSON patch, which must be an array of patch operations
+    pub fn patch(json_patch: Value) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed: true,
+            reason: None,
+            patch: Some(json_patch),
+        }
+    }
+}
@@ -4,7 +4,10 @@
 //!
 //!
 //! **This module is only available when the `failable` feature is enabled in your Cargo.toml**
-use crate::handler::{Error, FinalizeResponse, Handler, SyncRequest, SyncResponse};
+use crate::handler::{
+    AdmissionRequest, AdmissionResponse, Error, FinalizeResponse, Handler, SyncRequest,
+    SyncResponse,
+};
 
 use serde_json::Value;
 
@@ -431,6 +434,19 @@ pub trait FailableHandler: Send + Sync + 'static {
         req: &SyncRequest,
         result: HandlerResult<Self::Validated, Self::Error>,
     ) -> Self::Status;
+
+    /// Invoked by the validating admission webhook, exactly like `Handler::validate`. This is named differently
+    /// from `validate`, which validates sync requests rather than admission requests. The default implementation
+    /// allows every request.
+    fn validate_admission(&self, _request: &AdmissionRequest) -> Result<AdmissionResponse, Error> {
+        Ok(AdmissionResponse::allow())
+    }
+
+    /// Invoked by the mutating admission webhook, exactly like `Handler::mutate`. The default implementation allows
+    /// every request without modification.
+    fn mutate_admission(&self, _request: &AdmissionRequest) -> Result<AdmissionResponse, Error> {
+        Ok(AdmissionResponse::allow())
+    }
 }
 
 impl<Syncf, Sf, Status, E> FailableHandler for (Syncf, Sf)
@@ -624,11 +640,20 @@ impl<H: FailableHandler> Handler for DefaultFailableHandler<H> {
         })?;
         Ok(FinalizeResponse { status, retry })
     }
+
+    fn validate(&self, request: &AdmissionRequest) -> Result<AdmissionResponse, Error> {
+        self.inner.validate_admission(request)
+    }
+
+    fn mutate(&self, request: &AdmissionRequest) -> Result<AdmissionResponse, Error> {
+        self.inner.mutate_admission(request)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::handler::admission::Operation;
     use crate::handler::request::{test_request, SyncRequest};
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
@@ -691,4 +716,67 @@ mod test {
         let resp = handler.sync(request).expect("handler returned an error");
         assert_eq!(Some(backoff_config.initial_interval), resp.resync);
     }
+
+    struct DeniesDeletes;
+
+    impl FailableHandler for DeniesDeletes {
+        type Validated = ();
+        type Error = TestError;
+        type Status = Value;
+
+        fn validate(&self, _request: &SyncRequest) -> Result<(), TestError> {
+            Ok(())
+        }
+
+        fn sync_children(&self, _: &mut (), _req: &SyncRequest) -> Result<Vec<Value>, TestError> {
+            Ok(Vec::new())
+        }
+
+        fn determine_status(
+            &self,
+            _req: &SyncRequest,
+            _result: HandlerResult<(), TestError>,
+        ) -> Value {
+            Value::Null
+        }
+
+        fn validate_admission(
+            &self,
+            request: &AdmissionRequest,
+        ) -> Result<AdmissionResponse, Error> {
+            if request.operation == Operation::Delete {
+                Ok(AdmissionResponse::deny("deletes are not allowed"))
+            } else {
+                Ok(AdmissionResponse::allow())
+            }
+        }
+    }
+
+    #[test]
+    fn admission_requests_are_forwarded_to_the_failable_handler() {
+        let handler = DefaultFailableHandler::wrap(DeniesDeletes);
+        let mut request = AdmissionRequest {
+            uid: "admission-uid".to_owned(),
+            operation: Operation::Delete,
+            name: Some("parent".to_owned()),
+            namespace: Some("ns".to_owned()),
+            object: Value::Null,
+            old_object: Value::Null,
+            user_info: Default::default(),
+            dry_run: false,
+        };
+
+        let response = Handler::validate(&handler, &request).unwrap();
+        assert_eq!(AdmissionResponse::deny("deletes are not allowed"), response);
+
+        request.operation = Operation::Create;
+        assert_eq!(
+            AdmissionResponse::allow(),
+            Handler::validate(&handler, &request).unwrap()
+        );
+        assert_eq!(
+            AdmissionResponse::allow(),
+            Handler::mutate(&handler, &request).unwrap()
+        );
+    }
 }
@@ -18,6 +18,10 @@ pub struct SyncRequest {
     /// The entire set of children related to this parent instance, as they exist in the cluster at the time.
     /// In the happy path, this will include all of the children that have been returned in a previous `SyncResponse`
     pub children: Vec<K8sResource>,
+    /// The related resources that map to this parent, as they exist in the cluster at the time. This will only include
+    /// resources with one of the related types from the `OperatorConfig`.
+    #[serde(default)]
+    pub related: Vec<K8sResource>,
 }
 
 impl Debug for SyncRequest {
@@ -44,7 +48,13 @@ impl SyncRequest {
     /// Returns a view of just the children of this request, which is useful for passing to a function that determines the current
     /// status. The returned view has a variety of functions for accessing individual children and groups of children.
     pub fn children(&self) -> RequestChildren {
-        RequestChildren(self)
+        RequestChildren(self.children.as_slice())
+    }
+
+    /// Returns a view of the related resources of this request. The returned view has all the same accessors as
+    /// the view returned from `children()`.
+    pub fn related(&self) -> RequestChildren<'_> {
+        RequestChildren(self.related.as_slice())
     }
 }
 
@@ -113,8 +123,7 @@ impl<'a, 'b, T: DeserializeOwned> IntoIterator for TypedView<'a, 'b, T> {
 /// as their input, which allows passing a variety of types, including `&ObjectId` and `(&str, &str)`.
 #[derive(Debug, Clone)]
 pub struct RawView<'a, 'b> {
-    // children: &'a RequestChildren<'a>,
-    req: &'a SyncRequest,
+    resources: &'a [K8sResource],
     type_ref: K8sTypeRef<'b>,
 }
 
@@ -133,11 +142,8 @@ impl<'a, 'b> RawView<'a, 'b> {
 
     /// Returns an iterator over all of the resources of this type
     pub fn iter(&self) -> RawIter<'a, 'b> {
-        // self.children.0.children.iter().filter(move |c| {
-        //     c.get_type_ref() == self.type_ref
-        // })
         RawIter {
-            inner: self.req.children.iter(),
+            inner: self.resources.iter(),
             type_ref: self.type_ref,
         }
     }
@@ -258,13 +264,13 @@ impl<'a, 'b, T: DeserializeOwned> Iterator for TypedIter<'a, 'b, T> {
 /// ```
 ///
 #[derive(Debug)]
-pub struct RequestChildren<'a>(&'a SyncRequest);
+pub struct RequestChildren<'a>(&'a [K8sResource]);
 impl<'a> RequestChildren<'a> {
     /// Provides a view of all the children with the given apiVersion/kind. The returned view provides a variety of functions
     /// to provide access to the matching subset of child resources
     pub fn of_type<'b>(&self, type_ref: impl Into<K8sTypeRef<'b>>) -> RawView<'a, 'b> {
         RawView {
-            req: self.0,
+            resources: self.0,
             type_ref: type_ref.into(),
         }
     }
@@ -280,7 +286,7 @@ impl<'a> RequestChildren<'a> {
 
     /// Returns an iterator over all of the children in the `SyncRequest`
     pub fn iter(&self) -> impl Iterator<Item = &K8sResource> {
-        self.0.children.iter()
+        self.0.iter()
     }
 
     /// Returns the matching resource if the request contains a resource with the
@@ -395,6 +401,19 @@ pub fn test_request() -> SyncRequest {
                 }
             }),
         ],
+        related: vec![resource!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "namespace": "foo",
+                "name": "bar-config",
+                "resourceVersion": "987",
+                "uid": "ghi789"
+            },
+            "data": {
+                "a": "b"
+            }
+        })],
     }
 }
 
@@ -516,6 +535,25 @@ pub mod test {
             .is_none());
     }
 
+    #[test]
+    fn related_resources_are_separate_from_children() {
+        let request = test_request();
+        let config_maps = request
+            .related()
+            .of_type(crate::k8s_types::core::v1::ConfigMap);
+
+        assert_eq!(1, config_maps.count());
+        assert_eq!(
+            "ghi789",
+            config_maps.get(("foo", "bar-config")).unwrap().uid()
+        );
+        assert_eq!(0, request.related().of_type(("v1", "Pod")).count());
+        assert!(request
+            .children()
+            .of_type(crate::k8s_types::core::v1::ConfigMap)
+            .is_empty());
+    }
+
     #[derive(Debug, Deserialize, PartialEq)]
     #[serde(rename_all = "camelCase")]
     struct MyMeta {
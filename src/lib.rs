@@ -69,8 +69,10 @@
 extern crate serde_derive;
 
 pub mod config;
+pub mod crd;
 pub mod handler;
 pub mod k8s_types;
+pub mod manifests;
 pub mod resource;
 pub mod runner;
 
@@ -79,8 +81,14 @@ pub use serde_json;
 pub use serde_yaml;
 
 pub mod prelude {
-    pub use crate::config::{ChildConfig, ClientConfig, OperatorConfig, UpdateStrategy};
-    pub use crate::handler::{FinalizeResponse, Handler, SyncRequest, SyncResponse};
+    pub use crate::config::{
+        AdoptionPolicy, ChildConfig, ChildScope, ClientConfig, LeaderElectionConfig, MergeKeys,
+        MergeStrategy, OperatorConfig, ParentRef, PropagationPolicy, RelatedConfig,
+        RetentionPolicy, UpdateStrategy, ValueFormat, ValueFormats, WebhookConfig,
+    };
+    pub use crate::handler::{
+        AdmissionRequest, AdmissionResponse, FinalizeResponse, Handler, SyncRequest, SyncResponse,
+    };
     pub use crate::k8s_types::{self, K8sType};
     pub use crate::resource::K8sResource;
     pub use crate::runner::run_operator;
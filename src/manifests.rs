@@ -0,0 +1,493 @@
+//! Generates the Kubernetes manifests that are needed to deploy an operator. The RBAC rules are derived from the
+//! `OperatorConfig`, so the permissions always match the requests that the runner will actually make.
+//!
+//! ```rust
+//! use roperator::config::{ChildConfig, OperatorConfig};
+//! use roperator::k8s_types::core::v1::Pod;
+//! use roperator::manifests::{generate_manifests, ManifestOptions};
+//! use roperator::prelude::K8sType;
+//!
+//! static PARENT_TYPE: &K8sType = &K8sType {
+//!    api_version: "example.com/v1",
+//!    kind: "Foo",
+//!    plural_kind: "foos",
+//! };
+//!
+//! let config = OperatorConfig::new("foo-operator", PARENT_TYPE)
+//!     .with_child(Pod, ChildConfig::recreate());
+//! let manifests = generate_manifests(&config, &ManifestOptions::new("example/foo-operator:1.0", "operators"));
+//! assert_eq!("ClusterRole", manifests.role["kind"]);
+//! println!("{}", manifests.to_yaml().unwrap());
+//! ```
+use crate::config::{ChildScope, OperatorConfig};
+use crate::k8s_types::apiextensions_k8s_io::v1::CustomResourceDefinition;
+use crate::k8s_types::apps::v1::Deployment;
+use crate::k8s_types::coordination_k8s_io::v1::Lease;
+use crate::k8s_types::core::v1::{Event, Namespace, ServiceAccount};
+use crate::k8s_types::rbac_authorization_k8s_io::v1::{
+    ClusterRole, ClusterRoleBinding, Role, RoleBinding,
+};
+use crate::k8s_types::K8sType;
+
+use serde_json::{json, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+const PARENT_VERBS: &[&str] = &["get", "list", "watch", "update", "patch"];
+const CHILD_VERBS: &[&str] = &[
+    "get", "list", "watch", "create", "update", "patch", "delete",
+];
+const RELATED_VERBS: &[&str] = &["get", "list", "watch"];
+const EVENT_VERBS: &[&str] = &["create", "patch"];
+const LEASE_VERBS: &[&str] = &["get", "create", "update"];
+const CRD_VERBS: &[&str] = &["get", "create", "update"];
+const NAMESPACE_VERBS: &[&str] = &["list", "watch"];
+
+/// Options for the parts of the manifests that can't be derived from the `OperatorConfig`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestOptions {
+    /// The container image of the operator
+    pub image: String,
+    /// The namespace that the operator's ServiceAccount and Deployment are created in
+    pub namespace: String,
+    /// The number of replicas of the Deployment. Defaults to 1, and should only be higher if leader election is enabled
+    pub replicas: u32,
+}
+
+impl ManifestOptions {
+    pub fn new(image: impl Into<String>, namespace: impl Into<String>) -> ManifestOptions {
+        ManifestOptions {
+            image: image.into(),
+            namespace: namespace.into(),
+            replicas: 1,
+        }
+    }
+
+    pub fn replicas(mut self, replicas: u32) -> Self {
+        self.replicas = replicas;
+        self
+    }
+}
+
+/// The manifests for deploying an operator. Each one is a plain JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifests {
+    pub service_account: Value,
+    /// A `Role` if the operator is constrained to specific namespaces, otherwise a `ClusterRole`. If there are multiple
+    /// namespaces, then this is the `Role` for the first one.
+    pub role: Value,
+    /// Binds the `role` to the ServiceAccount
+    pub role_binding: Value,
+    /// Any other roles and bindings that are needed. This includes a `Role` for each additional namespace that the
+    /// operator is constrained to. Leases are always granted by a `Role` in the lease namespace, and a namespaced
+    /// operator gets a `ClusterRole` for installing its CRD and for any cluster-scoped children.
+    pub extra_rbac: Vec<Value>,
+    pub deployment: Value,
+}
+
+impl Manifests {
+    /// Returns all of the manifests, in the order that they should be applied
+    pub fn all(&self) -> Vec<&Value> {
+        let mut all = vec![&self.service_account, &self.role, &self.role_binding];
+        all.extend(self.extra_rbac.iter());
+        all.push(&self.deployment);
+        all
+    }
+
+    /// Returns all of the manifests as a multi-document yaml stream, suitable for `kubectl apply -f`
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        let mut yaml = String::new();
+        for manifest in self.all() {
+            yaml.push_str(serde_yaml::to_string(manifest)?.as_str());
+            yaml.push('\n');
+        }
+        Ok(yaml)
+    }
+}
+
+/// Returns the ServiceAccount, RBAC, and Deployment manifests for the operator with the given config
+pub fn generate_manifests(config: &OperatorConfig, options: &ManifestOptions) -> Manifests {
+    let name = config.operator_name.as_str();
+
+    let mut rules = Rules::default();
+    let parent_types = std::iter::once(config.parent).chain(config.storage_version);
+    for parent_type in parent_types {
+        rules.add(parent_type, "", PARENT_VERBS);
+        rules.add(parent_type, "/status", PARENT_VERBS);
+    }
+    // rules for resources outside of the operator's namespaces
+    let mut cluster_rules = Rules::default();
+    for (child_type, child_config) in config.child_types.iter() {
+        match child_config.scope {
+            ChildScope::SameNamespace => rules.add(child_type, "", CHILD_VERBS),
+            _ => cluster_rules.add(child_type, "", CHILD_VERBS),
+        }
+    }
+    if config.crd.is_some() {
+        cluster_rules.add(CustomResourceDefinition, "", CRD_VERBS);
+    }
+    for related_type in config.related_types.keys() {
+        rules.add(related_type, "", RELATED_VERBS);
+    }
+    if config.record_events {
+        rules.add(Event, "", EVENT_VERBS);
+    }
+
+    // Operators that use a namespace selector need to watch Namespaces, and they can't know ahead of time which
+    // namespaces they'll need access to, so they get the same ClusterRole as a cluster-wide operator
+    let fixed_namespaces = if config.namespace_selector.is_some() {
+        rules.add(Namespace, "", NAMESPACE_VERBS);
+        Vec::new()
+    } else {
+        config.fixed_namespaces()
+    };
+    let mut extra_rbac = Vec::new();
+    let (role, role_binding) = match fixed_namespaces.split_first() {
+        Some((namespace, other_namespaces)) => {
+            if !cluster_rules.0.is_empty() {
+                let cluster_role_name = format!("{}-cluster", name);
+                extra_rbac.push(rbac_role(
+                    ClusterRole,
+                    cluster_role_name.as_str(),
+                    None,
+                    cluster_rules,
+                ));
+                extra_rbac.push(rbac_role_binding(
+                    ClusterRoleBinding,
+                    ClusterRole,
+                    cluster_role_name.as_str(),
+                    None,
+                    options,
+                    name,
+                ));
+            }
+            for other in other_namespaces {
+                extra_rbac.push(rbac_role(Role, name, Some(other), rules.clone()));
+                extra_rbac.push(rbac_role_binding(
+                    RoleBinding,
+                    Role,
+                    name,
+                    Some(other),
+                    options,
+                    name,
+                ));
+            }
+            (
+                rbac_role(Role, name, Some(namespace), rules),
+                rbac_role_binding(RoleBinding, Role, name, Some(namespace), options, name),
+            )
+        }
+        None => {
+            rules.merge(cluster_rules);
+            (
+                rbac_role(ClusterRole, name, None, rules),
+                rbac_role_binding(ClusterRoleBinding, ClusterRole, name, None, options, name),
+            )
+        }
+    };
+
+    if let Some(leader_election) = config.leader_election.as_ref() {
+        let mut lease_rules = Rules::default();
+        lease_rules.add(Lease, "", LEASE_VERBS);
+        let lease_role_name = format!("{}-leader-election", name);
+        let namespace = leader_election.lease_namespace.as_str();
+        extra_rbac.push(rbac_role(
+            Role,
+            lease_role_name.as_str(),
+            Some(namespace),
+            lease_rules,
+        ));
+        extra_rbac.push(rbac_role_binding(
+            RoleBinding,
+            Role,
+            lease_role_name.as_str(),
+            Some(namespace),
+            options,
+            name,
+        ));
+    }
+
+    Manifests {
+        service_account: json!({
+            "apiVersion": ServiceAccount.api_version,
+            "kind": ServiceAccount.kind,
+            "metadata": {
+                "namespace": options.namespace,
+                "name": name,
+            },
+        }),
+        role,
+        role_binding,
+        extra_rbac,
+        deployment: deployment(config, options),
+    }
+}
+
+/// Accumulates RBAC rules, combining the resources that have the same api group and verbs into a single rule
+#[derive(Debug, Default, Clone)]
+struct Rules(BTreeMap<(String, &'static [&'static str]), BTreeSet<String>>);
+
+impl Rules {
+    fn add(&mut self, k8s_type: &K8sType, subresource: &str, verbs: &'static [&'static str]) {
+        self.0
+            .entry((k8s_type.group().to_owned(), verbs))
+            .or_default()
+            .insert(format!("{}{}", k8s_type.plural_kind, subresource));
+    }
+
+    fn merge(&mut self, other: Rules) {
+        for (key, resources) in other.0 {
+            self.0.entry(key).or_default().extend(resources);
+        }
+    }
+
+    fn into_json(self) -> Value {
+        let rules = self
+            .0
+            .into_iter()
+            .map(|((group, verbs), resources)| {
+                json!({
+                    "apiGroups": [group],
+                    "resources": resources,
+                    "verbs": verbs,
+                })
+            })
+            .collect::<Vec<_>>();
+        Value::Array(rules)
+    }
+}
+
+fn rbac_role(k8s_type: &K8sType, name: &str, namespace: Option<&str>, rules: Rules) -> Value {
+    json!({
+        "apiVersion": k8s_type.api_version,
+        "kind": k8s_type.kind,
+        "metadata": metadata(name, namespace),
+        "rules": rules.into_json(),
+    })
+}
+
+fn rbac_role_binding(
+    k8s_type: &K8sType,
+    role_type: &K8sType,
+    role_name: &str,
+    namespace: Option<&str>,
+    options: &ManifestOptions,
+    service_account_name: &str,
+) -> Value {
+    json!({
+        "apiVersion": k8s_type.api_version,
+        "kind": k8s_type.kind,
+        "metadata": metadata(role_name, namespace),
+        "roleRef": {
+            "apiGroup": role_type.group(),
+            "kind": role_type.kind,
+            "name": role_name,
+        },
+        "subjects": [
+            {
+                "kind": ServiceAccount.kind,
+                "namespace": options.namespace,
+                "name": service_account_name,
+            }
+        ],
+    })
+}
+
+fn metadata(name: &str, namespace: Option<&str>) -> Value {
+    match namespace {
+        Some(ns) => json!({ "namespace": ns, "name": name }),
+        None => json!({ "name": name }),
+    }
+}
+
+fn deployment(config: &OperatorConfig, options: &ManifestOptions) -> Value {
+    let name = config.operator_name.as_str();
+    let mut ports = Vec::new();
+    if config.expose_health || config.expose_metrics {
+        ports.push(json!({ "name": "http", "containerPort": config.server_port }));
+    }
+    if let Some(webhook) = config.webhook.as_ref() {
+        ports.push(json!({ "name": "webhook", "containerPort": webhook.port }));
+    }
+    let mut container = json!({
+        "name": name,
+        "image": options.image,
+        "ports": ports,
+    });
+    if config.expose_health {
+        container["livenessProbe"] = json!({
+            "httpGet": {
+                "path": "/health",
+                "port": config.server_port,
+            },
+        });
+    }
+
+    let labels = json!({ "app.kubernetes.io/name": name });
+    json!({
+        "apiVersion": Deployment.api_version,
+        "kind": Deployment.kind,
+        "metadata": {
+            "namespace": options.namespace,
+            "name": name,
+            "labels": labels,
+        },
+        "spec": {
+            "replicas": options.replicas,
+            "selector": {
+                "matchLabels": labels,
+            },
+            "template": {
+                "metadata": {
+                    "labels": labels,
+                },
+                "spec": {
+                    "serviceAccountName": name,
+                    "containers": [container],
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ChildConfig, LeaderElectionConfig, RelatedConfig};
+    use crate::k8s_types::core::v1::{ConfigMap, Pod, Secret};
+
+    static PARENT_TYPE: &K8sType = &K8sType {
+        api_version: "example.com/v1",
+        kind: "Foo",
+        plural_kind: "foos",
+    };
+
+    #[test]
+    fn cluster_wide_operator_gets_cluster_role() {
+        let config = OperatorConfig::new("foo-operator", PARENT_TYPE)
+            .with_child(Pod, ChildConfig::recreate())
+            .with_child(ConfigMap, ChildConfig::replace())
+            .with_related(Secret, RelatedConfig::new(|_| Vec::new()))
+            .install_crd(json!({}));
+        let manifests = generate_manifests(&config, &ManifestOptions::new("foo:1.0", "ops"));
+
+        let expected_role = json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "ClusterRole",
+            "metadata": {"name": "foo-operator"},
+            "rules": [
+                {
+                    "apiGroups": [""],
+                    "resources": ["events"],
+                    "verbs": ["create", "patch"],
+                },
+                {
+                    "apiGroups": [""],
+                    "resources": ["secrets"],
+                    "verbs": ["get", "list", "watch"],
+                },
+                {
+                    "apiGroups": [""],
+                    "resources": ["configmaps", "pods"],
+                    "verbs": ["get", "list", "watch", "create", "update", "patch", "delete"],
+                },
+                {
+                    "apiGroups": ["apiextensions.k8s.io"],
+                    "resources": ["customresourcedefinitions"],
+                    "verbs": ["get", "create", "update"],
+                },
+                {
+                    "apiGroups": ["example.com"],
+                    "resources": ["foos", "foos/status"],
+                    "verbs": ["get", "list", "watch", "update", "patch"],
+                },
+            ],
+        });
+        assert_eq!(expected_role, manifests.role);
+        assert_eq!("ClusterRoleBinding", manifests.role_binding["kind"]);
+        assert_eq!(
+            json!([{"kind": "ServiceAccount", "namespace": "ops", "name": "foo-operator"}]),
+            manifests.role_binding["subjects"]
+        );
+        assert!(manifests.extra_rbac.is_empty());
+
+        let container = &manifests.deployment["spec"]["template"]["spec"]["containers"][0];
+        assert_eq!("foo:1.0", container["image"]);
+        assert_eq!(8080, container["livenessProbe"]["httpGet"]["port"]);
+        assert_eq!(4, manifests.all().len());
+    }
+
+    #[test]
+    fn operator_gets_role_in_each_namespace() {
+        let config = OperatorConfig::new("foo-operator", PARENT_TYPE)
+            .within_namespaces(vec!["a", "b"])
+            .with_child(Pod, ChildConfig::recreate());
+        let manifests = generate_manifests(&config, &ManifestOptions::new("foo:1.0", "ops"));
+        assert_eq!("Role", manifests.role["kind"]);
+        assert_eq!("a", manifests.role["metadata"]["namespace"]);
+        assert_eq!(2, manifests.extra_rbac.len());
+        assert_eq!(manifests.role["rules"], manifests.extra_rbac[0]["rules"]);
+        assert_eq!("b", manifests.extra_rbac[1]["metadata"]["namespace"]);
+
+        let config = config.namespace_selector("tenant=true");
+        let manifests = generate_manifests(&config, &ManifestOptions::new("foo:1.0", "ops"));
+        assert_eq!("ClusterRole", manifests.role["kind"]);
+        assert!(manifests.extra_rbac.is_empty());
+        let namespace_rule = json!({
+            "apiGroups": [""],
+            "resources": ["namespaces"],
+            "verbs": ["list", "watch"],
+        });
+        assert!(manifests.role["rules"]
+            .as_array()
+            .unwrap()
+            .contains(&namespace_rule));
+    }
+
+    #[test]
+    fn namespaced_operator_gets_role_and_extra_rbac() {
+        let config = OperatorConfig::new("foo-operator", PARENT_TYPE)
+            .within_namespace("foos")
+            .with_child(Pod, ChildConfig::recreate())
+            .with_child(Namespace, ChildConfig::recreate().cluster_scoped())
+            .record_events(false)
+            .expose_health(false)
+            .install_crd(json!({}))
+            .leader_election(LeaderElectionConfig::new("foo-lease", "ops"));
+        let manifests =
+            generate_manifests(&config, &ManifestOptions::new("foo:1.0", "ops").replicas(2));
+
+        assert_eq!("Role", manifests.role["kind"]);
+        assert_eq!("foos", manifests.role["metadata"]["namespace"]);
+        assert_eq!(2, manifests.role["rules"].as_array().unwrap().len());
+        assert_eq!("RoleBinding", manifests.role_binding["kind"]);
+
+        let kinds = manifests
+            .extra_rbac
+            .iter()
+            .map(|r| r["kind"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["ClusterRole", "ClusterRoleBinding", "Role", "RoleBinding"],
+            kinds
+        );
+        assert_eq!("ops", manifests.extra_rbac[2]["metadata"]["namespace"]);
+        let cluster_rules = json!([
+            {
+                "apiGroups": [""],
+                "resources": ["namespaces"],
+                "verbs": ["get", "list", "watch", "create", "update", "patch", "delete"],
+            },
+            {
+                "apiGroups": ["apiextensions.k8s.io"],
+                "resources": ["customresourcedefinitions"],
+                "verbs": ["get", "create", "update"],
+            },
+        ]);
+        assert_eq!(cluster_rules, manifests.extra_rbac[0]["rules"]);
+
+        assert_eq!(2, manifests.deployment["spec"]["replicas"]);
+        let container = &manifests.deployment["spec"]["template"]["spec"]["containers"][0];
+        assert!(container.get("livenessProbe").is_none());
+    }
+}
@@ -26,7 +26,7 @@ use std::io::Read;
 use std::sync::Arc;
 use std::time::Instant;
 
-pub use self::request::{MergeStrategy, Patch};
+pub use self::request::{DeleteOptions, Patch};
 
 lazy_static! {
     static ref NEWLINE_REGEX: Regex = Regex::new("([\\r\\n]+)").unwrap();
@@ -37,6 +37,8 @@ pub enum Error {
     Io(hyper::error::Error),
     Serde(serde_json::Error),
     Http(http::StatusCode),
+    /// An error response that included a `Status` from the api server
+    Api(ApiError),
 }
 
 impl std::error::Error for Error {
@@ -45,6 +47,7 @@ impl std::error::Error for Error {
             Error::Io(e) => Some(e as &(dyn std::error::Error + 'static)),
             Error::Serde(e) => Some(e as &(dyn std::error::Error + 'static)),
             Error::Http(_) => None,
+            Error::Api(e) => Some(e as &(dyn std::error::Error + 'static)),
         }
     }
 }
@@ -61,6 +64,20 @@ impl Error {
     pub fn is_http_status(&self, code: u16) -> bool {
         match self {
             Error::Http(ref status) => status.as_u16() == code,
+            Error::Api(ref api_error) => api_error.code == code,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the request was rejected because it would have changed an immutable field, such as the
+    /// `clusterIP` of a Service or the `template` of a Job
+    pub fn is_immutable_field(&self) -> bool {
+        match self {
+            Error::Api(ref api_error) => {
+                api_error.code == 422
+                    && (api_error.message.contains("field is immutable")
+                        || api_error.message.contains("Forbidden: updates to"))
+            }
             _ => false,
         }
     }
@@ -72,6 +89,7 @@ impl std::fmt::Display for Error {
             Error::Io(ref e) => write!(f, "Io Error: {}", e),
             Error::Serde(ref e) => write!(f, "(De)Serialization error: {}", e),
             Error::Http(ref e) => write!(f, "Http Error: {}", e),
+            Error::Api(ref e) => write!(f, "{}", e),
         }
     }
 }
@@ -198,8 +216,15 @@ impl Client {
         k8s_type: &K8sType,
         namespace: Option<&str>,
         label_selector: Option<&str>,
+        field_selector: Option<&str>,
     ) -> Result<ObjectList<Value>, Error> {
-        let req = request::list_request(&self.0.config, k8s_type, label_selector, namespace)?;
+        let req = request::list_request(
+            &self.0.config,
+            k8s_type,
+            label_selector,
+            field_selector,
+            namespace,
+        )?;
         self.get_response_body(req).await
     }
 
@@ -209,12 +234,14 @@ impl Client {
         namespace: Option<&str>,
         resource_version: Option<&str>,
         label_selector: Option<&str>,
+        field_selector: Option<&str>,
     ) -> Result<LineDeserializer<WatchEvent>, Error> {
         let req = request::watch_request(
             &self.0.config,
             k8s_type,
             resource_version,
             label_selector,
+            field_selector,
             None,
             namespace,
         )?;
@@ -235,15 +262,18 @@ impl Client {
         &self,
         k8s_type: &K8sType,
         id: &ObjectIdRef<'_>,
+        options: &DeleteOptions,
     ) -> Result<(), Error> {
         log::info!("Deleting resouce '{}' with type: {}", id, k8s_type);
-        let req = request::delete_request(&self.0.config, k8s_type, id)?;
+        let req = request::delete_request(&self.0.config, k8s_type, id, options)?;
         let response = self.get_response(req).await?;
 
         match response.status().as_u16() {
             200..=299 | 404 | 409 => {
                 // 404 means that something else must have already deleted the resource, which is fine by us
-                // 409 status is returned when the object is already in the process of being deleted, again fine by us
+                // 409 status is returned when the object is already in the process of being deleted, again fine by us.
+                // It's also returned when a precondition fails, which means that the resource we meant to delete is
+                // already gone and has been replaced by a new one, which we'll see the next time the parent is synced
                 Ok(())
             }
             other => {
@@ -259,7 +289,6 @@ impl Client {
     }
 
     /// gets the requested resource by name and converts a 404 response into a None value
-    #[cfg(feature = "testkit")]
     pub async fn get_resource(
         &self,
         k8s_type: &K8sType,
@@ -314,7 +343,10 @@ impl Client {
                     body.len()
                 );
             }
-            Err(Error::http(status))
+            match serde_json::from_slice::<ApiError>(body.as_ref()) {
+                Ok(api_error) => Err(Error::Api(api_error)),
+                Err(_) => Err(Error::http(status)),
+            }
         }
     }
 
@@ -1,4 +1,4 @@
-use crate::config::{ClientConfig, Credentials};
+use crate::config::{ClientConfig, Credentials, MergeStrategy, PropagationPolicy};
 use crate::k8s_types::K8sType;
 use crate::resource::{K8sResource, ObjectIdRef};
 use crate::runner::client::Error;
@@ -8,31 +8,32 @@ use hyper::Body;
 use serde_json::Value;
 use url::Url;
 
-#[allow(dead_code)]
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum MergeStrategy {
-    Json,
-    JsonMerge,
-    StrategicMerge,
-}
-
-impl MergeStrategy {
-    fn content_type(self) -> &'static str {
-        match self {
-            MergeStrategy::Json => "application/json-patch+json",
-            MergeStrategy::JsonMerge => "application/merge-patch+json",
-            MergeStrategy::StrategicMerge => "application/strategic-merge-patch+json",
-        }
-    }
-}
-
 #[derive(Debug, PartialEq, Clone)]
 pub struct Patch {
     merge_strategy: MergeStrategy,
     value: Value,
+    field_manager: Option<String>,
 }
 
 impl Patch {
+    pub fn new(merge_strategy: MergeStrategy, value: Value) -> Patch {
+        Patch {
+            merge_strategy,
+            value,
+            field_manager: None,
+        }
+    }
+
+    /// Creates a server-side apply patch for the given desired resource. Conflicts with other field
+    /// managers will be forced, so that the given `field_manager` takes ownership of every field in `resource`
+    pub fn apply(resource: Value, field_manager: &str) -> Patch {
+        Patch {
+            value: resource,
+            merge_strategy: MergeStrategy::Apply,
+            field_manager: Some(field_manager.to_owned()),
+        }
+    }
+
     pub fn remove_finalizer(resource: &K8sResource, finalizer: &str) -> Patch {
         let finalizers = resource
             .as_ref()
@@ -56,6 +57,7 @@ impl Patch {
         Patch {
             value: patch,
             merge_strategy: MergeStrategy::JsonMerge,
+            field_manager: None,
         }
     }
 
@@ -78,7 +80,84 @@ impl Patch {
         Patch {
             value,
             merge_strategy: MergeStrategy::JsonMerge,
+            field_manager: None,
+        }
+    }
+
+    /// Removes the ownerReference to the given parent and the given labels from the resource, so that it's no
+    /// longer a child of that parent
+    pub fn release_child(resource: &K8sResource, parent_uid: &str, labels: &[&str]) -> Patch {
+        let mut value = serde_json::json!({
+            "metadata": {
+                "resourceVersion": resource.resource_version(),
+                "labels": labels
+                    .iter()
+                    .map(|label| ((*label).to_owned(), Value::Null))
+                    .collect::<serde_json::Map<String, Value>>(),
+            }
+        });
+        if let Some(refs) = resource
+            .as_ref()
+            .pointer("/metadata/ownerReferences")
+            .and_then(Value::as_array)
+        {
+            let remaining = refs
+                .iter()
+                .filter(|owner| owner.pointer("/uid").and_then(Value::as_str) != Some(parent_uid))
+                .cloned()
+                .collect::<Vec<_>>();
+            value["metadata"]["ownerReferences"] = Value::Array(remaining);
+        }
+        Patch {
+            value,
+            merge_strategy: MergeStrategy::JsonMerge,
+            field_manager: None,
+        }
+    }
+}
+
+/// The options that are sent in the body of a DELETE request
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DeleteOptions {
+    propagation_policy: Option<PropagationPolicy>,
+    grace_period_seconds: Option<u32>,
+    uid: Option<String>,
+}
+
+impl DeleteOptions {
+    pub fn new(
+        propagation_policy: Option<PropagationPolicy>,
+        grace_period_seconds: Option<u32>,
+    ) -> DeleteOptions {
+        DeleteOptions {
+            propagation_policy,
+            grace_period_seconds,
+            uid: None,
+        }
+    }
+
+    /// Adds a precondition that the resource must have the given uid, so that a resource that's been re-created
+    /// with the same name is never deleted by mistake
+    pub fn uid_precondition(mut self, uid: impl Into<String>) -> Self {
+        self.uid = Some(uid.into());
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        let mut options = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "DeleteOptions",
+        });
+        if let Some(policy) = self.propagation_policy {
+            options["propagationPolicy"] = policy.as_str().into();
+        }
+        if let Some(seconds) = self.grace_period_seconds {
+            options["gracePeriodSeconds"] = seconds.into();
         }
+        if let Some(uid) = self.uid.as_ref() {
+            options["preconditions"] = serde_json::json!({ "uid": uid });
+        }
+        options
     }
 }
 
@@ -88,7 +167,14 @@ pub fn patch_request(
     id: &ObjectIdRef<'_>,
     patch: &Patch,
 ) -> Result<Request<Body>, Error> {
-    let url = make_url(client_config, k8s_type, id.namespace(), Some(id.name()));
+    let mut url = make_url(client_config, k8s_type, id.namespace(), Some(id.name()));
+    if let Some(field_manager) = patch.field_manager.as_ref() {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("fieldManager", field_manager);
+        if patch.merge_strategy == MergeStrategy::Apply {
+            query.append_pair("force", "true");
+        }
+    }
     let header_value = patch.merge_strategy.content_type();
     let builder =
         make_req(url, Method::PATCH, client_config).header(header::CONTENT_TYPE, header_value);
@@ -97,7 +183,6 @@ pub fn patch_request(
     Ok(req)
 }
 
-#[cfg(feature = "testkit")]
 pub fn get_request(
     client_config: &ClientConfig,
     k8s_type: &K8sType,
@@ -160,10 +245,13 @@ pub fn delete_request(
     client_config: &ClientConfig,
     k8s_type: &K8sType,
     id: &ObjectIdRef<'_>,
+    options: &DeleteOptions,
 ) -> Result<Request<Body>, Error> {
     let url = make_url(client_config, k8s_type, id.namespace(), Some(id.name()));
+    let as_vec = serde_json::to_vec(&options.to_json())?;
     let req = make_req(url, Method::DELETE, client_config)
-        .body(Body::empty())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(as_vec))
         .unwrap();
     Ok(req)
 }
@@ -173,6 +261,7 @@ pub fn watch_request(
     k8s_type: &K8sType,
     resource_version: Option<&str>,
     label_selector: Option<&str>,
+    field_selector: Option<&str>,
     timeout_seconds: Option<u32>,
     namespace: Option<&str>,
 ) -> Result<Request<Body>, Error> {
@@ -186,6 +275,9 @@ pub fn watch_request(
         if let Some(selector) = label_selector {
             query.append_pair("labelSelector", selector);
         }
+        if let Some(selector) = field_selector {
+            query.append_pair("fieldSelector", selector);
+        }
         if let Some(timeout) = timeout_seconds {
             let as_str = format!("{}", timeout);
             query.append_pair("timeoutSeconds", &as_str);
@@ -202,12 +294,18 @@ pub fn list_request(
     client_config: &ClientConfig,
     k8s_type: &K8sType,
     label_selector: Option<&str>,
+    field_selector: Option<&str>,
     namespace: Option<&str>,
 ) -> Result<Request<Body>, Error> {
     let mut url = make_url(client_config, k8s_type, namespace, None);
-    if let Some(selector) = label_selector {
+    if label_selector.is_some() || field_selector.is_some() {
         let mut query = url.query_pairs_mut();
-        query.append_pair("labelSelector", selector);
+        if let Some(selector) = label_selector {
+            query.append_pair("labelSelector", selector);
+        }
+        if let Some(selector) = field_selector {
+            query.append_pair("fieldSelector", selector);
+        }
     }
     let req = make_req(url, Method::GET, client_config)
         .body(Body::empty())
@@ -270,3 +368,100 @@ fn make_url(
     }
     url
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::k8s_types::apps::v1::Deployment;
+
+    fn test_client_config() -> ClientConfig {
+        ClientConfig {
+            api_server_endpoint: "https://kubernetes.test".to_owned(),
+            credentials: Credentials::base64_bearer_token("dG9rZW4="),
+            ca_data: None,
+            user_agent: "test-operator".to_owned(),
+            verify_ssl_certs: true,
+            impersonate: None,
+            impersonate_groups: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn apply_patch_request_sets_field_manager_and_content_type() {
+        let desired = serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {
+                "namespace": "foo",
+                "name": "bar",
+            }
+        });
+        let patch = Patch::apply(desired, "my-operator");
+        let id = ObjectIdRef::new("foo", "bar");
+        let req = patch_request(&test_client_config(), Deployment, &id, &patch)
+            .expect("failed to create request");
+
+        assert_eq!(&Method::PATCH, req.method());
+        assert_eq!(
+            "https://kubernetes.test/apis/apps/v1/namespaces/foo/deployments/bar?fieldManager=my-operator&force=true",
+            req.uri().to_string()
+        );
+        assert_eq!(
+            "application/apply-patch+yaml",
+            req.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+    }
+
+    #[test]
+    fn list_and_watch_requests_include_selectors() {
+        let req = list_request(
+            &test_client_config(),
+            Deployment,
+            Some("shard=a"),
+            Some("metadata.name!=foo"),
+            Some("ns"),
+        )
+        .expect("failed to create request");
+        assert_eq!(
+            "https://kubernetes.test/apis/apps/v1/namespaces/ns/deployments?labelSelector=shard%3Da&fieldSelector=metadata.name%21%3Dfoo",
+            req.uri().to_string()
+        );
+
+        let req = watch_request(
+            &test_client_config(),
+            Deployment,
+            Some("5"),
+            None,
+            Some("metadata.name=foo"),
+            None,
+            None,
+        )
+        .expect("failed to create request");
+        assert_eq!(
+            "https://kubernetes.test/apis/apps/v1/deployments?watch=true&resourceVersion=5&fieldSelector=metadata.name%3Dfoo",
+            req.uri().to_string()
+        );
+    }
+
+    #[test]
+    fn delete_options_include_policy_grace_period_and_uid_precondition() {
+        let options = DeleteOptions::new(Some(PropagationPolicy::Foreground), Some(30))
+            .uid_precondition("1234");
+        let expected = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "DeleteOptions",
+            "propagationPolicy": "Foreground",
+            "gracePeriodSeconds": 30,
+            "preconditions": {
+                "uid": "1234",
+            },
+        });
+        assert_eq!(expected, options.to_json());
+
+        let expected = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "DeleteOptions",
+        });
+        assert_eq!(expected, DeleteOptions::default().to_json());
+    }
+}
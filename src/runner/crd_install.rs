@@ -0,0 +1,233 @@
+//! Creates or updates the parent `CustomResourceDefinition` when the operator starts, and then waits for the
+//! api server to report that it's `Established`. The informers can't start until then, since the api server
+//! returns a 404 for any requests for resources of a CRD that isn't established yet.
+//!
+//! Every replica does this when it starts, so the CRD is only replaced if it's different from the existing one, and
+//! a conflict with another replica is retried using the CRD that the other replica wrote.
+use crate::k8s_types::apiextensions_k8s_io::v1::CustomResourceDefinition;
+use crate::resource::ObjectIdRef;
+use crate::runner::client::{self, Client};
+use crate::runner::reconcile::compare::compare_values;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const ESTABLISHED_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_CONFLICT_ATTEMPTS: usize = 5;
+
+/// Injected into webhook conversion configs by tools like cert-manager, so it's kept if the CRD doesn't set one
+const CA_BUNDLE_POINTER: &str = "/spec/conversion/webhook/clientConfig/caBundle";
+
+pub(crate) async fn install_crd(client: &Client, crd: &Value) -> Result<(), Error> {
+    let name = crd
+        .pointer("/metadata/name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("CustomResourceDefinition is missing metadata.name"))?;
+    let id = ObjectIdRef::new("", name);
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match create_or_update(client, &id, crd).await {
+            Err(ref err) if err.is_http_status(409) && attempts < MAX_CONFLICT_ATTEMPTS => {
+                log::info!(
+                    "CustomResourceDefinition: {} was modified concurrently, retrying",
+                    name
+                );
+            }
+            result => {
+                result?;
+                break;
+            }
+        }
+    }
+
+    let start = Instant::now();
+    loop {
+        let current = client.get_resource(CustomResourceDefinition, &id).await?;
+        if current.as_ref().map(is_established).unwrap_or(false) {
+            log::info!(
+                "CustomResourceDefinition: {} is established after {}ms",
+                name,
+                crate::runner::duration_to_millis(start.elapsed())
+            );
+            return Ok(());
+        }
+        if start.elapsed() >= ESTABLISHED_TIMEOUT {
+            anyhow::bail!(
+                "Timed out waiting for CustomResourceDefinition: {} to be established",
+                name
+            );
+        }
+        tokio::time::delay_for(POLL_INTERVAL).await;
+    }
+}
+
+async fn create_or_update(
+    client: &Client,
+    id: &ObjectIdRef<'_>,
+    crd: &Value,
+) -> Result<(), client::Error> {
+    match client.get_resource(CustomResourceDefinition, id).await? {
+        Some(ref existing) if compare_values(existing, crd).is_empty() => {
+            log::info!("CustomResourceDefinition: {} is up to date", id.name());
+        }
+        Some(existing) => {
+            let mut updated = crd.clone();
+            updated["metadata"]["resourceVersion"] = existing
+                .pointer("/metadata/resourceVersion")
+                .cloned()
+                .unwrap_or(Value::Null);
+            if let Some(ca_bundle) = existing.pointer(CA_BUNDLE_POINTER) {
+                if updated
+                    .pointer("/spec/conversion/webhook/clientConfig")
+                    .is_some()
+                    && updated.pointer(CA_BUNDLE_POINTER).is_none()
+                {
+                    updated["spec"]["conversion"]["webhook"]["clientConfig"]["caBundle"] =
+                        ca_bundle.clone();
+                }
+            }
+            client
+                .replace_resource(CustomResourceDefinition, id, &updated)
+                .await?;
+            log::info!("Updated CustomResourceDefinition: {}", id.name());
+        }
+        None => {
+            client
+                .create_resource(CustomResourceDefinition, crd)
+                .await?;
+            log::info!("Created CustomResourceDefinition: {}", id.name());
+        }
+    }
+    Ok(())
+}
+
+fn is_established(crd: &Value) -> bool {
+    crd.pointer("/status/conditions")
+        .and_then(Value::as_array)
+        .map(|conditions| {
+            conditions.iter().any(|condition| {
+                condition.pointer("/type").and_then(Value::as_str) == Some("Established")
+                    && condition.pointer("/status").and_then(Value::as_str) == Some("True")
+            })
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn crd_is_established_only_when_condition_is_true() {
+        let crd = |status: &str| {
+            json!({
+                "status": {
+                    "conditions": [
+                        {"type": "NamesAccepted", "status": "True"},
+                        {"type": "Established", "status": status},
+                    ]
+                }
+            })
+        };
+        assert!(is_established(&crd("True")));
+        assert!(!is_established(&crd("False")));
+        assert!(!is_established(&json!({})));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn installs_crd_before_starting_operator() {
+        use crate::crd::{CrdBuilder, CrdVersion};
+        use crate::handler::SyncRequest;
+        use crate::k8s_types::apiextensions_k8s_io::v1::CustomResourceDefinition;
+        use crate::runner::testkit::fixture::*;
+
+        #[derive(Deserialize)]
+        struct ParentSpec {
+            #[allow(dead_code)]
+            replicas: i32,
+        }
+
+        let crd = CrdBuilder::new()
+            .version(CrdVersion::from_types::<ParentSpec, Value>(PARENT_TYPE).unwrap())
+            .build()
+            .unwrap();
+        let operator_config = operator_config().install_crd(crd.clone());
+        let mut testkit = start(operator_config, |_: &SyncRequest| Ok(response(Vec::new())));
+        let actual = testkit
+            .get_resource_from_api_server(
+                CustomResourceDefinition,
+                &ObjectIdRef::new("", "parents.example.com"),
+            )
+            .unwrap()
+            .expect("crd was not created");
+        assert_eq!(crd["spec"], actual["spec"]);
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn crd_is_only_replaced_when_it_changes_and_keeps_injected_ca_bundle() {
+        use crate::runner::metrics::Metrics;
+        use crate::runner::testkit::FakeApiServer;
+
+        let server = FakeApiServer::start().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = Client::new(
+            server.client_config("test"),
+            Metrics::new().client_metrics(),
+        )
+        .unwrap();
+        let id = ObjectIdRef::new("", "parents.example.com");
+        let mut crd = json!({
+            "apiVersion": "apiextensions.k8s.io/v1",
+            "kind": "CustomResourceDefinition",
+            "metadata": { "name": "parents.example.com" },
+            "spec": {
+                "group": "example.com",
+                "scope": "Namespaced",
+                "names": { "kind": "Parent", "plural": "parents" },
+                "conversion": {
+                    "strategy": "Webhook",
+                    "webhook": {
+                        "clientConfig": { "service": { "namespace": "ns", "name": "operator" } },
+                        "conversionReviewVersions": ["v1"],
+                    },
+                },
+            },
+        });
+        let get_crd = |runtime: &mut tokio::runtime::Runtime| {
+            runtime
+                .block_on(client.get_resource(CustomResourceDefinition, &id))
+                .unwrap()
+                .expect("crd does not exist")
+        };
+
+        runtime.block_on(install_crd(&client, &crd)).unwrap();
+        // simulate cert-manager injecting the ca bundle after the crd was created
+        let mut injected = get_crd(&mut runtime);
+        injected["spec"]["conversion"]["webhook"]["clientConfig"]["caBundle"] = json!("abc");
+        runtime
+            .block_on(client.replace_resource(CustomResourceDefinition, &id, &injected))
+            .unwrap();
+        let injected = get_crd(&mut runtime);
+
+        runtime.block_on(install_crd(&client, &crd)).unwrap();
+        let unchanged = get_crd(&mut runtime);
+        assert_eq!(
+            injected.pointer("/metadata/resourceVersion"),
+            unchanged.pointer("/metadata/resourceVersion")
+        );
+
+        crd["spec"]["scope"] = json!("Cluster");
+        runtime.block_on(install_crd(&client, &crd)).unwrap();
+        let replaced = get_crd(&mut runtime);
+        assert_eq!(Some(&json!("Cluster")), replaced.pointer("/spec/scope"));
+        assert_eq!(Some(&json!("abc")), replaced.pointer(CA_BUNDLE_POINTER));
+    }
+}
@@ -0,0 +1,435 @@
+//! Records Kubernetes Events against parent resources, so that the outcomes of syncs and finalizes show up in
+//! `kubectl describe`. This follows the same basic approach as the client-go event recorder. Identical events for
+//! the same parent are aggregated into a single Event by incrementing its `count`, and each parent gets a token
+//! bucket that limits how many events may be recorded for it, so that a parent that fails repeatedly can't flood
+//! the api server with Events.
+use crate::config::MergeStrategy;
+use crate::k8s_types::core::v1::Event;
+use crate::resource::{K8sResource, ObjectIdRef};
+use crate::runner::client::{self, Client, Patch};
+use crate::runner::format_micro_time;
+
+use serde_json::json;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub(crate) const NORMAL: &str = "Normal";
+pub(crate) const WARNING: &str = "Warning";
+
+/// The maximum number of events that may be recorded for a single parent in a burst
+const BURST_SIZE: u32 = 25;
+/// How often a parent gets another token after it has used up its burst
+const REFILL_INTERVAL: Duration = Duration::from_secs(300);
+/// The maximum number of distinct events that we'll remember for aggregation
+const MAX_CACHED_EVENTS: usize = 4096;
+
+pub(crate) struct EventRecorder {
+    client: Client,
+    component: String,
+    enabled: bool,
+    correlator: Mutex<EventCorrelator>,
+}
+
+impl Debug for EventRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventRecorder")
+            .field("component", &self.component)
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+impl EventRecorder {
+    pub fn new(client: Client, component: String, enabled: bool) -> EventRecorder {
+        EventRecorder {
+            client,
+            component,
+            enabled,
+            correlator: Mutex::new(EventCorrelator::default()),
+        }
+    }
+
+    pub async fn normal(&self, parent: &K8sResource, reason: &str, message: impl Into<String>) {
+        self.record(parent, NORMAL, reason, message.into()).await
+    }
+
+    pub async fn warning(&self, parent: &K8sResource, reason: &str, message: impl Into<String>) {
+        self.record(parent, WARNING, reason, message.into()).await
+    }
+
+    /// Creates or updates the Event. Failures are only logged, since events are informational and should never
+    /// cause a sync to fail.
+    async fn record(
+        &self,
+        parent: &K8sResource,
+        event_type: &'static str,
+        reason: &str,
+        message: String,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let key = EventKey {
+            parent_uid: parent.uid().to_owned(),
+            event_type,
+            reason: reason.to_owned(),
+            message,
+        };
+        let now = SystemTime::now();
+        let timestamp = format_micro_time(now);
+        let action = {
+            let mut correlator = self.correlator.lock().unwrap();
+            correlator.observe(&key, Instant::now(), || event_name(parent, now), &timestamp)
+        };
+        let result = match action {
+            EventAction::Drop => {
+                log::debug!(
+                    "Dropping event: {} for parent: {} because of rate limiting",
+                    key.reason,
+                    parent.get_object_id()
+                );
+                return;
+            }
+            EventAction::Create(record) => self.create(parent, &key, &record, &timestamp).await,
+            EventAction::Update(record) => {
+                let patch = Patch::new(
+                    MergeStrategy::JsonMerge,
+                    json!({
+                        "count": record.count,
+                        "lastTimestamp": timestamp,
+                    }),
+                );
+                let id = ObjectIdRef::new(record.namespace.as_str(), record.name.as_str());
+                match self.client.patch_resource(Event, &id, &patch).await {
+                    // the event may have been deleted by the api server since we last saw it
+                    Err(ref err) if err.is_http_status(404) => {
+                        self.create(parent, &key, &record, &timestamp).await
+                    }
+                    other => other,
+                }
+            }
+        };
+        if let Err(err) = result {
+            log::warn!(
+                "Failed to record event: {} for parent: {}, err: {}",
+                key.reason,
+                parent.get_object_id(),
+                err
+            );
+        }
+    }
+
+    async fn create(
+        &self,
+        parent: &K8sResource,
+        key: &EventKey,
+        record: &EventRecord,
+        timestamp: &str,
+    ) -> Result<(), client::Error> {
+        let event = json!({
+            "apiVersion": Event.api_version,
+            "kind": Event.kind,
+            "metadata": {
+                "name": record.name,
+                "namespace": record.namespace,
+            },
+            "involvedObject": {
+                "apiVersion": parent.api_version(),
+                "kind": parent.kind(),
+                "name": parent.name(),
+                "namespace": parent.namespace(),
+                "uid": parent.uid(),
+                "resourceVersion": parent.resource_version(),
+            },
+            "reason": key.reason,
+            "message": key.message,
+            "type": key.event_type,
+            "count": record.count,
+            "firstTimestamp": record.first_timestamp,
+            "lastTimestamp": timestamp,
+            "source": {
+                "component": self.component,
+            },
+            "reportingComponent": self.component,
+        });
+        self.client.create_resource(Event, &event).await
+    }
+}
+
+/// Events for cluster scoped parents get created in the default namespace, same as kubectl does
+fn event_name(parent: &K8sResource, now: SystemTime) -> (String, String) {
+    let nanos = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let namespace = parent.namespace().unwrap_or("default").to_owned();
+    (namespace, format!("{}.{:x}", parent.name(), nanos))
+}
+
+/// Events are considered to be the same if all of these fields are the same
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EventKey {
+    parent_uid: String,
+    event_type: &'static str,
+    reason: String,
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct EventRecord {
+    namespace: String,
+    name: String,
+    count: u64,
+    first_timestamp: String,
+    last_seen: Instant,
+}
+
+#[derive(Debug, PartialEq)]
+enum EventAction {
+    Create(EventRecord),
+    Update(EventRecord),
+    Drop,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: u32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: BURST_SIZE,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refills = (elapsed.as_secs() / REFILL_INTERVAL.as_secs()) as u32;
+        if refills > 0 {
+            self.tokens = self.tokens.saturating_add(refills).min(BURST_SIZE);
+            self.last_refill += REFILL_INTERVAL * refills;
+        }
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Decides whether each event should be created, aggregated into an existing Event, or dropped. This doesn't do
+/// any I/O, so that the api requests can be made without holding the lock.
+#[derive(Debug, Default)]
+struct EventCorrelator {
+    events: HashMap<EventKey, EventRecord>,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+impl EventCorrelator {
+    fn observe(
+        &mut self,
+        key: &EventKey,
+        now: Instant,
+        new_name: impl FnOnce() -> (String, String),
+        timestamp: &str,
+    ) -> EventAction {
+        let bucket = self
+            .buckets
+            .entry(key.parent_uid.clone())
+            .or_insert_with(|| TokenBucket::new(now));
+        if !bucket.try_take(now) {
+            return EventAction::Drop;
+        }
+
+        if let Some(record) = self.events.get_mut(key) {
+            record.count += 1;
+            record.last_seen = now;
+            return EventAction::Update(record.clone());
+        }
+
+        if self.events.len() >= MAX_CACHED_EVENTS {
+            self.evict_oldest();
+        }
+        let (namespace, name) = new_name();
+        let record = EventRecord {
+            namespace,
+            name,
+            count: 1,
+            first_timestamp: timestamp.to_owned(),
+            last_seen: now,
+        };
+        self.events.insert(key.clone(), record.clone());
+        EventAction::Create(record)
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .events
+            .iter()
+            .min_by_key(|(_, record)| record.last_seen)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.events.remove(&key);
+            // only keep the rate limiter for parents that still have cached events, so neither map grows unbounded
+            let parent_uid = key.parent_uid;
+            if !self.events.keys().any(|k| k.parent_uid == parent_uid) {
+                self.buckets.remove(&parent_uid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(parent_uid: &str, message: &str) -> EventKey {
+        EventKey {
+            parent_uid: parent_uid.to_owned(),
+            event_type: WARNING,
+            reason: "SyncFailed".to_owned(),
+            message: message.to_owned(),
+        }
+    }
+
+    fn name(n: &str) -> impl FnOnce() -> (String, String) {
+        let n = n.to_owned();
+        move || ("ns".to_owned(), n)
+    }
+
+    #[test]
+    fn identical_events_are_aggregated() {
+        let mut correlator = EventCorrelator::default();
+        let now = Instant::now();
+        let first = correlator.observe(&key("a", "oops"), now, name("first"), "t1");
+        match first {
+            EventAction::Create(ref record) => {
+                assert_eq!("first", record.name);
+                assert_eq!(1, record.count);
+            }
+            other => panic!("expected create, got: {:?}", other),
+        }
+
+        let second = correlator.observe(&key("a", "oops"), now, name("second"), "t2");
+        match second {
+            EventAction::Update(ref record) => {
+                assert_eq!("first", record.name);
+                assert_eq!(2, record.count);
+                assert_eq!("t1", record.first_timestamp);
+            }
+            other => panic!("expected update, got: {:?}", other),
+        }
+
+        // a different message is a different event
+        let third = correlator.observe(&key("a", "different"), now, name("third"), "t3");
+        match third {
+            EventAction::Create(ref record) => assert_eq!("third", record.name),
+            other => panic!("expected create, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn events_are_rate_limited_per_parent() {
+        let mut correlator = EventCorrelator::default();
+        let now = Instant::now();
+        for i in 0..BURST_SIZE {
+            let action = correlator.observe(&key("a", "oops"), now, name("a"), "t");
+            assert_ne!(EventAction::Drop, action, "event {} was dropped", i);
+        }
+        assert_eq!(
+            EventAction::Drop,
+            correlator.observe(&key("a", "oops"), now, name("a"), "t")
+        );
+        // other parents have their own limit
+        assert_ne!(
+            EventAction::Drop,
+            correlator.observe(&key("b", "oops"), now, name("b"), "t")
+        );
+
+        let later = now + REFILL_INTERVAL;
+        assert_ne!(
+            EventAction::Drop,
+            correlator.observe(&key("a", "oops"), later, name("a"), "t")
+        );
+        assert_eq!(
+            EventAction::Drop,
+            correlator.observe(&key("a", "oops"), later, name("a"), "t")
+        );
+    }
+
+    #[test]
+    fn oldest_event_is_evicted_when_cache_is_full() {
+        let mut correlator = EventCorrelator::default();
+        let start = Instant::now();
+        for i in 0..MAX_CACHED_EVENTS {
+            let now = start + Duration::from_millis(i as u64);
+            let uid = format!("uid-{}", i);
+            correlator.observe(&key(&uid, "oops"), now, name(&uid), "t");
+        }
+        assert_eq!(MAX_CACHED_EVENTS, correlator.events.len());
+
+        let now = start + Duration::from_secs(60);
+        correlator.observe(&key("new", "oops"), now, name("new"), "t");
+        assert_eq!(MAX_CACHED_EVENTS, correlator.events.len());
+        assert!(!correlator.events.contains_key(&key("uid-0", "oops")));
+        assert!(!correlator.buckets.contains_key("uid-0"));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn records_events_for_child_changes_and_sync_failures() {
+        use crate::config::ChildConfig;
+        use crate::handler::SyncRequest;
+        use crate::k8s_types::core::v1::ConfigMap;
+        use crate::resource::ObjectIdRef;
+        use crate::runner::testkit::fixture::*;
+        use serde_json::{json, Value};
+
+        let operator_config = operator_config().with_child(ConfigMap, ChildConfig::replace());
+        let handler = |req: &SyncRequest| {
+            if req.parent.name() == "bad" {
+                return Err(anyhow::anyhow!("the handler failed"));
+            }
+            let child = v1_resource("ConfigMap", req.parent.namespace(), req.parent.name());
+            Ok(response(vec![child]))
+        };
+        let mut testkit = start(operator_config, handler);
+        testkit
+            .create_resource(PARENT_TYPE, &parent("ns", "good"))
+            .unwrap();
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("ns", "good"),
+            TIMEOUT,
+        );
+        testkit
+            .create_resource(PARENT_TYPE, &parent("ns", "bad"))
+            .unwrap();
+
+        let has_event = |events: &[Value], parent: &str, reason: &str| {
+            events.iter().any(|event| {
+                event.pointer("/involvedObject/name") == Some(&json!(parent))
+                    && event.pointer("/reason") == Some(&json!(reason))
+            })
+        };
+        eventually(&mut testkit, "events to be recorded", |testkit| {
+            let events = list_events(testkit, "ns");
+            has_event(&events, "good", "AddedFinalizer")
+                && has_event(&events, "good", "CreatedChild")
+                && has_event(&events, "bad", "SyncFailed")
+        });
+        // repeated failures are aggregated into a single Event
+        let failures = list_events(&mut testkit, "ns")
+            .iter()
+            .filter(|e| e.pointer("/reason") == Some(&json!("SyncFailed")))
+            .count();
+        assert_eq!(1, failures);
+    }
+}
@@ -1,20 +1,19 @@
+use crate::config::{ParentRef, RelatedConfig};
 use crate::k8s_types::K8sType;
-use crate::resource::{InvalidResourceError, K8sResource, ObjectId};
+use crate::resource::{InvalidResourceError, K8sResource, ObjectId, ObjectIdRef};
 use anyhow::Error;
 
-#[cfg(feature = "testkit")]
-use crate::resource::ObjectIdRef;
-
 use crate::runner::client::{ApiError, Client, Error as ClientError, ObjectList, WatchEvent};
 use crate::runner::metrics::WatcherMetrics;
 use crate::runner::resource_map::{IdSet, ResourceMap};
 
+use futures::future::{abortable, AbortHandle, BoxFuture, FutureExt};
 use serde_json::Value;
 use tokio::runtime::Handle;
 use tokio::sync::mpsc::{error::SendError, Sender};
 use tokio::sync::{Mutex, MutexGuard};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Debug, Display};
 use std::sync::Arc;
 use std::time::Duration;
@@ -37,8 +36,11 @@ impl LabelToIdIndex {
 impl ReverseIndex for LabelToIdIndex {
     type Value = IdSet;
 
-    fn get_key<'a, 'b>(&'a self, res: &'b K8sResource) -> Option<&'b str> {
+    fn get_keys(&self, res: &K8sResource) -> Vec<String> {
         res.get_label_value(self.label_name.as_str())
+            .map(String::from)
+            .into_iter()
+            .collect()
     }
 
     fn insert(&mut self, key: &str, res: &K8sResource) {
@@ -83,8 +85,8 @@ impl UidToIdIndex {
 impl ReverseIndex for UidToIdIndex {
     type Value = ObjectId;
 
-    fn get_key<'a, 'b>(&'a self, res: &'b K8sResource) -> Option<&'b str> {
-        Some(res.uid())
+    fn get_keys(&self, res: &K8sResource) -> Vec<String> {
+        vec![res.uid().to_owned()]
     }
 
     fn insert(&mut self, key: &str, value: &K8sResource) {
@@ -109,10 +111,91 @@ impl ReverseIndex for UidToIdIndex {
     }
 }
 
+/// Indexes related resources by the parents that they map to. Parents that are mapped using a `ParentRef::Uid` are
+/// indexed by their uid, and those mapped using a `ParentRef::Id` are indexed by their "namespace/name". The two are
+/// distinguishable because uids never contain a '/'.
+pub struct RelatedIndex {
+    config: RelatedConfig,
+    entries: HashMap<String, IdSet>,
+}
+
+impl RelatedIndex {
+    pub fn new(config: RelatedConfig) -> RelatedIndex {
+        RelatedIndex {
+            config,
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl Debug for RelatedIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RelatedIndex")
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+impl ReverseIndex for RelatedIndex {
+    type Value = IdSet;
+
+    fn get_keys(&self, res: &K8sResource) -> Vec<String> {
+        let mut keys = self
+            .config
+            .map_to_parents(res)
+            .into_iter()
+            .map(|parent| match parent {
+                ParentRef::Uid(uid) => uid,
+                ParentRef::Id(id) => id.to_string(),
+            })
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    fn insert(&mut self, key: &str, res: &K8sResource) {
+        let id = res.get_object_id();
+        let set = self
+            .entries
+            .entry(key.to_owned())
+            .or_insert_with(IdSet::new);
+        if !set.contains(&id) {
+            set.insert(id.to_owned());
+        }
+    }
+
+    fn remove_one(&mut self, key: &str, id: &ObjectId) {
+        let is_empty = self
+            .entries
+            .get_mut(key)
+            .map(|set| {
+                set.remove(id);
+                set.len() == 0
+            })
+            .unwrap_or(false);
+        if is_empty {
+            self.entries.remove(key);
+        }
+    }
+
+    fn remove_all(&mut self, key: &str) -> Option<IdSet> {
+        self.entries.remove(key)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn lookup<'a, 'b>(&'a self, key: &'b str) -> Option<&'a IdSet> {
+        self.entries.get(key)
+    }
+}
+
 pub trait ReverseIndex: Send + 'static {
     type Value: std::fmt::Debug + 'static;
 
-    fn get_key<'a, 'b>(&'a self, res: &'b K8sResource) -> Option<&'b str>;
+    fn get_keys(&self, res: &K8sResource) -> Vec<String>;
     fn insert(&mut self, key: &str, res: &K8sResource);
     fn remove_one(&mut self, key: &str, id: &ObjectId);
     fn remove_all(&mut self, key: &str) -> Option<Self::Value>;
@@ -125,7 +208,8 @@ struct CacheAndIndex<I: ReverseIndex> {
     cache: ResourceMap,
     index: I,
     error: Option<Error>,
-    is_initialized: bool,
+    /// The namespaces whose watches haven't finished seeding the cache yet. `None` is used for a watch of all namespaces
+    uninitialized: HashSet<Option<String>>,
 }
 
 impl<I: ReverseIndex> CacheAndIndex<I> {
@@ -134,23 +218,37 @@ impl<I: ReverseIndex> CacheAndIndex<I> {
             error: Some(MonitorBackendErr::StateUnininitialized.into_boxed_error()),
             cache: ResourceMap::new(),
             index,
-            is_initialized: false,
+            uninitialized: HashSet::new(),
         }
     }
 
-    fn add(&mut self, resource: K8sResource) {
-        if let Some(key) = self.index.get_key(&resource) {
+    /// Adds the resource to the cache and index. Returns all the index keys for the resource, including any
+    /// keys from the previous version of the resource that no longer apply to it.
+    fn add(&mut self, resource: K8sResource) -> Vec<String> {
+        let mut keys = self.index.get_keys(&resource);
+        for key in keys.iter() {
             self.index.insert(key, &resource);
         }
-        self.cache.insert(resource);
+        let id = resource.get_object_id().to_owned();
+        if let Some(previous) = self.cache.insert(resource) {
+            for key in self.index.get_keys(&previous) {
+                if !keys.contains(&key) {
+                    self.index.remove_one(&key, &id);
+                    keys.push(key);
+                }
+            }
+        }
+        keys
     }
 
-    fn remove(&mut self, id: &ObjectId, resource: &K8sResource) {
-        let key = self.index.get_key(resource);
-        if let Some(k) = key {
-            self.index.remove_one(k, &id);
+    /// Removes the resource from the cache and index, and returns its index keys
+    fn remove(&mut self, id: &ObjectId, resource: &K8sResource) -> Vec<String> {
+        let keys = self.index.get_keys(resource);
+        for key in keys.iter() {
+            self.index.remove_one(key, &id);
         }
         self.cache.remove(id);
+        keys
     }
 
     fn clear_all(&mut self) {
@@ -161,6 +259,24 @@ impl<I: ReverseIndex> CacheAndIndex<I> {
         }
     }
 
+    /// Removes all the resources in the given namespace from the cache and index, or clears everything if the
+    /// namespace is `None`
+    fn clear_namespace(&mut self, namespace: Option<&str>) {
+        let namespace = match namespace {
+            Some(ns) => ns,
+            None => return self.clear_all(),
+        };
+        for resource in self.cache.remove_namespace(namespace) {
+            let id = resource.get_object_id().to_owned();
+            for key in self.index.get_keys(&resource) {
+                self.index.remove_one(&key, &id);
+            }
+        }
+        if let Some(err) = self.error.take() {
+            log::info!("Clearing previous_error: {}", err);
+        }
+    }
+
     fn resource_count(&self) -> usize {
         self.cache.len()
     }
@@ -177,7 +293,7 @@ impl CacheAndIndex<UidToIdIndex> {
     }
 }
 
-impl CacheAndIndex<LabelToIdIndex> {
+impl<I: ReverseIndex<Value = IdSet>> CacheAndIndex<I> {
     pub fn get_all_resources_by_index_key(&self, key: &str) -> Vec<K8sResource> {
         let mut results = Vec::new();
         if let Some(ids) = self.index.lookup(key) {
@@ -193,7 +309,7 @@ impl CacheAndIndex<LabelToIdIndex> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EventType {
     Created,
     Updated,
@@ -218,10 +334,13 @@ pub struct ResourceMessage {
 pub struct ResourceState<'a, I: ReverseIndex>(MutexGuard<'a, CacheAndIndex<I>>);
 
 impl<'a, I: ReverseIndex> ResourceState<'a, I> {
-    #[cfg(feature = "testkit")]
     pub fn get_by_id(&self, id: &ObjectIdRef<'_>) -> Option<K8sResource> {
         self.0.cache.get_copy(id)
     }
+
+    pub fn get_all_ids(&self) -> Vec<ObjectId> {
+        self.0.cache.iter().map(|id| id.to_owned()).collect()
+    }
 }
 
 impl<'a> ResourceState<'a, UidToIdIndex> {
@@ -230,15 +349,39 @@ impl<'a> ResourceState<'a, UidToIdIndex> {
     }
 }
 
-impl<'a> ResourceState<'a, LabelToIdIndex> {
+impl<'a, I: ReverseIndex<Value = IdSet>> ResourceState<'a, I> {
     pub fn get_all_resources_by_index_key(&self, key: &str) -> Vec<K8sResource> {
         self.0.get_all_resources_by_index_key(key)
     }
 }
 
-#[derive(Debug, Clone)]
+/// A cache of resources of a single type, which is kept up to date by a separate watch of each namespace
+#[derive(Debug)]
 pub struct ResourceMonitor<I: ReverseIndex> {
     cache_and_index: Arc<Mutex<CacheAndIndex<I>>>,
+    backends: Arc<MonitorBackends>,
+}
+
+impl<I: ReverseIndex> Clone for ResourceMonitor<I> {
+    fn clone(&self) -> Self {
+        ResourceMonitor {
+            cache_and_index: self.cache_and_index.clone(),
+            backends: self.backends.clone(),
+        }
+    }
+}
+
+/// Everything that's needed to start the watch for a namespace, along with the watches that are currently running
+#[derive(Debug)]
+struct MonitorBackends {
+    executor: Handle,
+    k8s_type: &'static K8sType,
+    client: Client,
+    sender: Sender<ResourceMessage>,
+    label_selector: Option<String>,
+    field_selector: Option<String>,
+    metrics: WatcherMetrics,
+    running: std::sync::Mutex<HashMap<Option<String>, AbortHandle>>,
 }
 
 impl<I: ReverseIndex> ResourceMonitor<I> {
@@ -246,12 +389,79 @@ impl<I: ReverseIndex> ResourceMonitor<I> {
         let mut lock = self.cache_and_index.lock().await;
         if let Some(err) = lock.error.take() {
             Err(err)
-        } else if !lock.is_initialized {
+        } else if !lock.uninitialized.is_empty() {
             Err(MonitorBackendErr::StateUnininitialized.into_boxed_error())
         } else {
             Ok(ResourceState(lock))
         }
     }
+
+    /// Starts watching the resources in the given namespace, or in all namespaces if it's `None`. Does nothing if
+    /// the namespace is already being watched.
+    pub async fn start_namespace(&self, namespace: Option<String>) {
+        let backend = ResourceMonitorBackend {
+            metrics: self.backends.metrics.clone(),
+            cache_and_index: self.cache_and_index.clone(),
+            client: self.backends.client.clone(),
+            k8s_type: self.backends.k8s_type,
+            sender: self.backends.sender.clone(),
+            label_selector: self.backends.label_selector.clone(),
+            field_selector: self.backends.field_selector.clone(),
+            namespace: namespace.clone(),
+        };
+        let (future, abort_handle) = abortable(backend.run());
+        {
+            let mut running = self.backends.running.lock().unwrap();
+            if running.contains_key(&namespace) {
+                return;
+            }
+            running.insert(namespace.clone(), abort_handle);
+        }
+        // the namespace must be marked as uninitialized before the backend starts, so that nobody reads from the
+        // cache before it contains the resources from the new namespace
+        self.cache_and_index
+            .lock()
+            .await
+            .uninitialized
+            .insert(namespace);
+        self.backends.executor.spawn(future.map(|_| ()));
+    }
+
+    /// Stops watching the resources in the given namespace, and removes them from the cache
+    pub async fn stop_namespace(&self, namespace: &str) {
+        let key = Some(namespace.to_owned());
+        let abort_handle = self.backends.running.lock().unwrap().remove(&key);
+        if let Some(handle) = abort_handle {
+            log::info!(
+                "Stopping monitor for resources of type: {:?} in namespace: {}",
+                self.backends.k8s_type,
+                namespace
+            );
+            handle.abort();
+            let mut lock = self.cache_and_index.lock().await;
+            lock.clear_namespace(Some(namespace));
+            lock.uninitialized.remove(&key);
+            self.backends
+                .metrics
+                .set_resource_count(lock.resource_count());
+        }
+    }
+}
+
+/// Allows the namespaces of monitors with different index types to be started and stopped together
+pub trait NamespacedMonitor: Send + Sync {
+    fn start_namespace(&self, namespace: Option<String>) -> BoxFuture<'_, ()>;
+    fn stop_namespace<'a>(&'a self, namespace: &'a str) -> BoxFuture<'a, ()>;
+}
+
+impl<I: ReverseIndex> NamespacedMonitor for ResourceMonitor<I> {
+    fn start_namespace(&self, namespace: Option<String>) -> BoxFuture<'_, ()> {
+        ResourceMonitor::start_namespace(self, namespace).boxed()
+    }
+
+    fn stop_namespace<'a>(&'a self, namespace: &'a str) -> BoxFuture<'a, ()> {
+        ResourceMonitor::stop_namespace(self, namespace).boxed()
+    }
 }
 
 #[derive(Debug)]
@@ -340,10 +550,10 @@ impl<T> From<SendError<T>> for MonitorBackendErr {
     }
 }
 
-pub fn start_child_monitor(
+pub async fn start_child_monitor(
     executor: Handle,
     label_name: String,
-    namespace: Option<String>,
+    namespaces: &[Option<String>],
     k8s_type: &'static K8sType,
     client: Client,
     sender: Sender<ResourceMessage>,
@@ -354,17 +564,46 @@ pub fn start_child_monitor(
         executor,
         index,
         k8s_type,
-        namespace,
+        namespaces,
         Some(label_name),
+        None,
         client,
         sender,
         watcher_metrics,
     )
+    .await
 }
 
-pub fn start_parent_monitor(
+pub async fn start_related_monitor(
     executor: Handle,
-    namespace: Option<String>,
+    config: RelatedConfig,
+    namespaces: &[Option<String>],
+    k8s_type: &'static K8sType,
+    client: Client,
+    sender: Sender<ResourceMessage>,
+    watcher_metrics: WatcherMetrics,
+) -> ResourceMonitor<RelatedIndex> {
+    let label_selector = config.label_selector.clone();
+    start_monitor(
+        executor,
+        RelatedIndex::new(config),
+        k8s_type,
+        namespaces,
+        label_selector,
+        None,
+        client,
+        sender,
+        watcher_metrics,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn start_parent_monitor(
+    executor: Handle,
+    namespaces: &[Option<String>],
+    label_selector: Option<String>,
+    field_selector: Option<String>,
     k8s_type: &'static K8sType,
     client: Client,
     sender: Sender<ResourceMessage>,
@@ -374,43 +613,67 @@ pub fn start_parent_monitor(
         executor,
         UidToIdIndex::new(),
         k8s_type,
-        namespace,
+        namespaces,
+        label_selector,
+        field_selector,
+        client,
+        sender,
+        watcher_metrics,
+    )
+    .await
+}
+
+/// Starts a monitor of the Namespaces that match the given label selector
+pub async fn start_namespace_monitor(
+    executor: Handle,
+    label_selector: String,
+    client: Client,
+    sender: Sender<ResourceMessage>,
+    watcher_metrics: WatcherMetrics,
+) -> ResourceMonitor<UidToIdIndex> {
+    start_monitor(
+        executor,
+        UidToIdIndex::new(),
+        crate::k8s_types::core::v1::Namespace,
+        &[None],
+        Some(label_selector),
         None,
         client,
         sender,
         watcher_metrics,
     )
+    .await
 }
 
 #[allow(clippy::too_many_arguments)]
-fn start_monitor<I: ReverseIndex>(
+async fn start_monitor<I: ReverseIndex>(
     executor: Handle,
     index: I,
     k8s_type: &'static K8sType,
-    namespace: Option<String>,
+    namespaces: &[Option<String>],
     label_selector: Option<String>,
+    field_selector: Option<String>,
     client: Client,
     sender: Sender<ResourceMessage>,
     watcher_metrics: WatcherMetrics,
 ) -> ResourceMonitor<I> {
-    let cache_and_index = Arc::new(Mutex::new(CacheAndIndex::new(index)));
-    let frontend = ResourceMonitor {
-        cache_and_index: cache_and_index.clone(),
+    let monitor = ResourceMonitor {
+        cache_and_index: Arc::new(Mutex::new(CacheAndIndex::new(index))),
+        backends: Arc::new(MonitorBackends {
+            executor,
+            k8s_type,
+            client,
+            sender,
+            label_selector,
+            field_selector,
+            metrics: watcher_metrics,
+            running: std::sync::Mutex::new(HashMap::new()),
+        }),
     };
-
-    let backend = ResourceMonitorBackend {
-        metrics: watcher_metrics,
-        cache_and_index,
-        client,
-        k8s_type,
-        sender,
-        label_selector,
-        namespace,
-    };
-    executor.spawn(Box::pin(async move {
-        backend.run().await;
-    }));
-    frontend
+    for namespace in namespaces {
+        monitor.start_namespace(namespace.clone()).await;
+    }
+    monitor
 }
 
 struct ResourceMonitorBackend<I: ReverseIndex> {
@@ -420,15 +683,18 @@ struct ResourceMonitorBackend<I: ReverseIndex> {
     k8s_type: &'static K8sType,
     sender: Sender<ResourceMessage>,
     label_selector: Option<String>,
+    field_selector: Option<String>,
     namespace: Option<String>,
 }
 
 impl<I: ReverseIndex> ResourceMonitorBackend<I> {
     async fn run(mut self) {
         log::debug!(
-            "Starting monitoring resources of type: {:?} with selector: {:?}",
+            "Starting monitoring resources of type: {:?} in namespace: {:?} with selector: {:?}, field selector: {:?}",
             self.k8s_type,
-            self.label_selector
+            self.namespace,
+            self.label_selector,
+            self.field_selector
         );
 
         loop {
@@ -468,7 +734,7 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
         );
         let mut lock = self.cache_and_index.lock().await;
         lock.error = Some(error.into_boxed_error());
-        lock.is_initialized = false;
+        lock.uninitialized.insert(self.namespace.clone());
 
         if !is_http_410 {
             self.metrics.error();
@@ -522,6 +788,7 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
                 self.namespace.as_ref().map(String::as_str),
                 Some(resource_version),
                 self.label_selector.as_ref().map(String::as_str),
+                self.field_selector.as_ref().map(String::as_str),
             )
             .await?;
 
@@ -558,28 +825,23 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
         let resource_version = resource.resource_version().to_owned();
 
         let resource_id = resource.get_object_id().to_owned();
-        let resource_type = self.k8s_type;
         let mut cache_and_index = self.cache_and_index.lock().await;
-        let index_key = cache_and_index.index.get_key(&resource).map(String::from);
 
-        match event_type {
-            EventType::Deleted => {
-                cache_and_index.remove(&resource_id, &resource);
-            }
-            _ => {
-                cache_and_index.add(resource);
-            }
-        }
+        let index_keys = match event_type {
+            EventType::Deleted => cache_and_index.remove(&resource_id, &resource),
+            _ => cache_and_index.add(resource),
+        };
 
         self.metrics
             .set_resource_count(cache_and_index.resource_count());
-        let to_send = ResourceMessage {
+        send_messages(
+            &mut self.sender,
+            self.k8s_type,
             event_type,
-            resource_type,
             resource_id,
-            index_key,
-        };
-        self.sender.send(to_send).await?;
+            index_keys,
+        )
+        .await?;
         Ok(resource_version)
     }
 
@@ -591,8 +853,8 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
         );
         // lock the cache now and hold it until we're done, so that consumers don't get an inconsistent view of it
         let mut cache_and_index = self.cache_and_index.lock().await;
-        cache_and_index.is_initialized = false;
-        cache_and_index.clear_all();
+        cache_and_index.uninitialized.insert(self.namespace.clone());
+        cache_and_index.clear_namespace(self.namespace.as_ref().map(String::as_str));
 
         self.metrics.request_started();
         let list = self
@@ -601,6 +863,7 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
                 &*self.k8s_type,
                 self.namespace.as_ref().map(String::as_str),
                 self.label_selector.as_ref().map(String::as_str),
+                self.field_selector.as_ref().map(String::as_str),
             )
             .await?;
         // safe unwrap since RawApi can only fail when setting the request body, but it's hard coded to an empty veec
@@ -615,24 +878,22 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
         for mut object in items {
             self.add_metadata_to_list_object(&mut object)?;
             let resource = K8sResource::from_value(object)?;
-            let index_key = cache_and_index.index.get_key(&resource).map(String::from);
             let event_type = get_update_event_type(resource.as_ref());
-            let resource_type = self.k8s_type;
             let resource_id = resource.get_object_id().to_owned();
-            let message = ResourceMessage {
+            let index_keys = cache_and_index.add(resource);
+            send_messages(
+                &mut self.sender,
+                self.k8s_type,
                 event_type,
-                resource_type,
                 resource_id,
-                index_key,
-            };
-
-            cache_and_index.add(resource);
-            self.sender.send(message).await?;
+                index_keys,
+            )
+            .await?;
         }
         self.metrics
             .set_resource_count(cache_and_index.resource_count());
         // set the initialization flag, which will allow the frontend to read from the cache
-        cache_and_index.is_initialized = true;
+        cache_and_index.uninitialized.remove(&self.namespace);
         // drop the cache_and_index lock when we exit this function, which allows consumers to read from it
         Ok(resource_version)
     }
@@ -659,6 +920,36 @@ impl<I: ReverseIndex> ResourceMonitorBackend<I> {
     }
 }
 
+/// Sends a message for each of the index keys, or a single message without an index key if there are none
+async fn send_messages(
+    sender: &mut Sender<ResourceMessage>,
+    resource_type: &'static K8sType,
+    event_type: EventType,
+    resource_id: ObjectId,
+    index_keys: Vec<String>,
+) -> Result<(), MonitorBackendErr> {
+    if index_keys.is_empty() {
+        let message = ResourceMessage {
+            event_type,
+            resource_type,
+            resource_id,
+            index_key: None,
+        };
+        sender.send(message).await?;
+        return Ok(());
+    }
+    for index_key in index_keys {
+        let message = ResourceMessage {
+            event_type: event_type.clone(),
+            resource_type,
+            resource_id: resource_id.clone(),
+            index_key: Some(index_key),
+        };
+        sender.send(message).await?;
+    }
+    Ok(())
+}
+
 fn get_update_event_type(resource: &Value) -> EventType {
     if is_finalizing(resource) {
         EventType::Finalizing
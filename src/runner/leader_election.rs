@@ -0,0 +1,309 @@
+//! Leader election using a `coordination.k8s.io/v1` Lease. This follows the same basic approach as
+//! the client-go leader election package. The elector never tries to parse the `renewTime` of the Lease,
+//! since that would require the clocks of all the replicas to be in sync. Instead, it tracks the time
+//! when it _observed_ each change to the Lease record, and only considers the Lease expired once the
+//! record has been unchanged for the whole `leaseDurationSeconds`.
+use crate::config::LeaderElectionConfig;
+use crate::k8s_types::coordination_k8s_io::v1::Lease;
+use crate::resource::ObjectId;
+use crate::runner::client::{self, Client};
+use crate::runner::{format_micro_time, RuntimeConfig};
+
+use serde_json::{json, Value};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The parts of the Lease spec that are relevant for leader election
+#[derive(Debug, Clone, PartialEq)]
+struct LeaseRecord {
+    holder_identity: Option<String>,
+    lease_duration: Duration,
+    acquire_time: Option<String>,
+    renew_time: Option<String>,
+    lease_transitions: i64,
+}
+
+impl LeaseRecord {
+    fn from_lease(lease: &Value) -> LeaseRecord {
+        let get_str = |pointer: &str| {
+            lease
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+        };
+        let lease_duration = lease
+            .pointer("/spec/leaseDurationSeconds")
+            .and_then(Value::as_u64)
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        let lease_transitions = lease
+            .pointer("/spec/leaseTransitions")
+            .and_then(Value::as_i64)
+            .unwrap_or(0);
+        LeaseRecord {
+            holder_identity: get_str("/spec/holderIdentity"),
+            lease_duration,
+            acquire_time: get_str("/spec/acquireTime"),
+            renew_time: get_str("/spec/renewTime"),
+            lease_transitions,
+        }
+    }
+
+    /// returns the record that should be written in order for `identity` to acquire or renew the Lease
+    fn next(&self, config: &LeaderElectionConfig, now: &str) -> LeaseRecord {
+        let is_renewal = self.holder_identity.as_ref() == Some(&config.identity);
+        let (acquire_time, lease_transitions) = if is_renewal {
+            (self.acquire_time.clone(), self.lease_transitions)
+        } else {
+            (Some(now.to_owned()), self.lease_transitions + 1)
+        };
+        LeaseRecord {
+            holder_identity: Some(config.identity.clone()),
+            lease_duration: config.lease_duration,
+            acquire_time,
+            renew_time: Some(now.to_owned()),
+            lease_transitions,
+        }
+    }
+
+    fn to_spec(&self) -> Value {
+        json!({
+            "holderIdentity": self.holder_identity,
+            "leaseDurationSeconds": self.lease_duration.as_secs(),
+            "acquireTime": self.acquire_time,
+            "renewTime": self.renew_time,
+            "leaseTransitions": self.lease_transitions,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ObservedLease {
+    record: LeaseRecord,
+    observed_at: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct LeaderElector {
+    client: Client,
+    config: LeaderElectionConfig,
+    runtime_config: Arc<RuntimeConfig>,
+    is_leader: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    observed: Option<ObservedLease>,
+}
+
+impl LeaderElector {
+    pub(crate) fn new(
+        client: Client,
+        config: LeaderElectionConfig,
+        runtime_config: Arc<RuntimeConfig>,
+        is_leader: Arc<AtomicBool>,
+        running: Arc<AtomicBool>,
+    ) -> LeaderElector {
+        LeaderElector {
+            client,
+            config,
+            runtime_config,
+            is_leader,
+            running,
+            observed: None,
+        }
+    }
+
+    /// Tries to acquire and then continuously renew the Lease until the operator is shutdown
+    pub(crate) async fn run(mut self) {
+        log::info!(
+            "Starting leader election for Lease: {}/{} with identity: '{}'",
+            self.config.lease_namespace,
+            self.config.lease_name,
+            self.config.identity
+        );
+        let mut last_renewal: Option<Instant> = None;
+        while self.running.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            match self.try_acquire_or_renew(now).await {
+                Ok(true) => {
+                    last_renewal = Some(now);
+                    self.set_leader(true);
+                }
+                Ok(false) => {
+                    self.set_leader(false);
+                }
+                Err(err) => {
+                    log::warn!(
+                        "Failed to acquire or renew Lease: {}/{}: {}",
+                        self.config.lease_namespace,
+                        self.config.lease_name,
+                        err
+                    );
+                    let deadline_exceeded = last_renewal
+                        .map(|t| t.elapsed() > self.config.renew_deadline)
+                        .unwrap_or(true);
+                    if deadline_exceeded {
+                        self.set_leader(false);
+                    }
+                }
+            }
+            tokio::time::delay_for(self.config.retry_period).await;
+        }
+        self.set_leader(false);
+    }
+
+    fn set_leader(&self, is_leader: bool) {
+        let was_leader = self.is_leader.swap(is_leader, Ordering::SeqCst);
+        if was_leader != is_leader {
+            if is_leader {
+                log::info!(
+                    "Acquired leadership of Lease: {}/{} as '{}'",
+                    self.config.lease_namespace,
+                    self.config.lease_name,
+                    self.config.identity
+                );
+            } else {
+                log::warn!(
+                    "Lost leadership of Lease: {}/{} as '{}'",
+                    self.config.lease_namespace,
+                    self.config.lease_name,
+                    self.config.identity
+                );
+            }
+            self.runtime_config.metrics.set_is_leader(is_leader);
+        }
+    }
+
+    async fn try_acquire_or_renew(&mut self, now: Instant) -> Result<bool, client::Error> {
+        let lease_id = ObjectId::new(
+            self.config.lease_namespace.clone(),
+            self.config.lease_name.clone(),
+        );
+        let id = lease_id.as_id_ref();
+        let now_str = format_micro_time(SystemTime::now());
+
+        match self.client.get_resource(Lease, &id).await? {
+            None => {
+                let record = LeaseRecord {
+                    holder_identity: None,
+                    lease_duration: self.config.lease_duration,
+                    acquire_time: None,
+                    renew_time: None,
+                    lease_transitions: -1,
+                }
+                .next(&self.config, now_str.as_str());
+                let lease = json!({
+                    "apiVersion": Lease.api_version,
+                    "kind": Lease.kind,
+                    "metadata": {
+                        "namespace": self.config.lease_namespace,
+                        "name": self.config.lease_name,
+                    },
+                    "spec": record.to_spec(),
+                });
+                self.client.create_resource(Lease, &lease).await?;
+                self.observe(record, now);
+                Ok(true)
+            }
+            Some(mut lease) => {
+                let record = LeaseRecord::from_lease(&lease);
+                self.observe(record.clone(), now);
+                if !self.can_acquire(now) {
+                    log::debug!(
+                        "Lease: {}/{} is held by: {:?}",
+                        self.config.lease_namespace,
+                        self.config.lease_name,
+                        record.holder_identity
+                    );
+                    return Ok(false);
+                }
+                let new_record = record.next(&self.config, now_str.as_str());
+                // the existing resourceVersion is left in the metadata, so that the update will fail
+                // if another replica has modified the Lease since we read it
+                lease["spec"] = new_record.to_spec();
+                self.client.replace_resource(Lease, &id, &lease).await?;
+                self.observe(new_record, now);
+                Ok(true)
+            }
+        }
+    }
+
+    fn observe(&mut self, record: LeaseRecord, now: Instant) {
+        let changed = self
+            .observed
+            .as_ref()
+            .map(|o| o.record != record)
+            .unwrap_or(true);
+        if changed {
+            self.observed = Some(ObservedLease {
+                record,
+                observed_at: now,
+            });
+        }
+    }
+
+    /// Returns true if the lease is either unheld, held by us, or if the holder hasn't renewed it
+    /// within the lease duration, as measured by our own clock
+    fn can_acquire(&self, now: Instant) -> bool {
+        let observed = match self.observed.as_ref() {
+            Some(o) => o,
+            None => return true,
+        };
+        match observed.record.holder_identity.as_ref() {
+            None => true,
+            Some(holder) if *holder == self.config.identity => true,
+            Some(_) => observed.observed_at + observed.record.lease_duration <= now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn next_record_increments_transitions_only_when_holder_changes() {
+        let config = LeaderElectionConfig::new("lease", "ns").identity("me");
+        let existing = LeaseRecord {
+            holder_identity: Some("other".to_owned()),
+            lease_duration: Duration::from_secs(30),
+            acquire_time: Some("then".to_owned()),
+            renew_time: Some("then".to_owned()),
+            lease_transitions: 3,
+        };
+        let acquired = existing.next(&config, "now");
+        assert_eq!(Some("me".to_owned()), acquired.holder_identity);
+        assert_eq!(Some("now".to_owned()), acquired.acquire_time);
+        assert_eq!(4, acquired.lease_transitions);
+        assert_eq!(config.lease_duration, acquired.lease_duration);
+
+        let renewed = acquired.next(&config, "later");
+        assert_eq!(Some("now".to_owned()), renewed.acquire_time);
+        assert_eq!(Some("later".to_owned()), renewed.renew_time);
+        assert_eq!(4, renewed.lease_transitions);
+    }
+
+    #[test]
+    fn lease_record_is_parsed_from_lease_spec() {
+        let lease = json!({
+            "apiVersion": "coordination.k8s.io/v1",
+            "kind": "Lease",
+            "metadata": { "namespace": "ns", "name": "lease" },
+            "spec": {
+                "holderIdentity": "other",
+                "leaseDurationSeconds": 15,
+                "renewTime": "2020-02-29T12:34:56.123456Z",
+                "leaseTransitions": 2,
+            }
+        });
+        let expected = LeaseRecord {
+            holder_identity: Some("other".to_owned()),
+            lease_duration: Duration::from_secs(15),
+            acquire_time: None,
+            renew_time: Some("2020-02-29T12:34:56.123456Z".to_owned()),
+            lease_transitions: 2,
+        };
+        assert_eq!(expected, LeaseRecord::from_lease(&lease));
+    }
+}
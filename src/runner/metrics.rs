@@ -18,6 +18,8 @@ pub struct Metrics {
     watcher_requests_by_type: IntCounterVec,
     watcher_errors_by_type: IntCounterVec,
     watch_events_by_type: IntCounterVec,
+    immutable_field_recreates_by_type: IntCounterVec,
+    is_leader: IntGauge,
 }
 
 impl Debug for Metrics {
@@ -121,6 +123,25 @@ impl Metrics {
             .register(Box::new(watch_events_by_type.clone()))
             .unwrap();
 
+        let immutable_field_recreate_opts = Opts::new(
+            "immutable_field_recreates",
+            "number of children that were deleted to be re-created because an update changed an immutable field",
+        )
+        .variable_label("apiVersion")
+        .variable_label("kind");
+        let immutable_field_recreates_by_type =
+            IntCounterVec::new(immutable_field_recreate_opts, API_VERSION_AND_KIND).unwrap();
+        registry
+            .register(Box::new(immutable_field_recreates_by_type.clone()))
+            .unwrap();
+
+        let is_leader_opts = Opts::new(
+            "is_leader",
+            "1 if this operator is currently allowed to sync parents, otherwise 0",
+        );
+        let is_leader = IntGauge::with_opts(is_leader_opts).unwrap();
+        registry.register(Box::new(is_leader.clone())).unwrap();
+
         Metrics {
             registry,
             api_server_request_times,
@@ -131,6 +152,8 @@ impl Metrics {
             watcher_requests_by_type,
             watcher_errors_by_type,
             watch_events_by_type,
+            immutable_field_recreates_by_type,
+            is_leader,
         }
     }
 
@@ -171,6 +194,16 @@ impl Metrics {
             .inc();
     }
 
+    pub fn child_recreated_for_immutable_field(&self, k8s_type: &K8sType) {
+        self.immutable_field_recreates_by_type
+            .with_label_values(&[k8s_type.api_version, k8s_type.kind])
+            .inc();
+    }
+
+    pub fn set_is_leader(&self, is_leader: bool) {
+        self.is_leader.set(if is_leader { 1 } else { 0 });
+    }
+
     pub fn encode_as_text(&self) -> Result<Vec<u8>, prometheus::Error> {
         use prometheus::Encoder;
         let encoder = prometheus::TextEncoder::new();
@@ -195,6 +228,7 @@ impl ClientMetrics {
     }
 }
 
+#[derive(Clone)]
 pub struct WatcherMetrics {
     watcher_requests: IntCounter,
     watcher_errors: IntCounter,
@@ -1,59 +1,125 @@
 mod client;
+mod crd_install;
+mod events;
 mod informer;
+mod leader_election;
 mod metrics;
+mod namespace_watcher;
 pub(crate) mod reconcile;
 pub(crate) mod resource_map;
 mod server;
+mod sync_queue;
+mod webhook;
 
 #[cfg(feature = "testkit")]
 pub mod testkit;
 
-#[cfg(feature = "testkit")]
-use crate::resource::ObjectIdRef;
-
-use crate::config::{ClientConfig, OperatorConfig, UpdateStrategy};
-use crate::handler::{Handler, SyncRequest};
+use crate::config::{
+    AdoptionPolicy, ChildScope, ClientConfig, MergeKeys, OperatorConfig, PropagationPolicy,
+    RetentionPolicy, UpdateStrategy, ValueFormats,
+};
+use crate::handler::{AsyncHandler, SyncRequest};
 use crate::k8s_types::K8sType;
-use crate::resource::{K8sResource, K8sTypeRef, ObjectId};
+use crate::resource::{K8sResource, K8sTypeRef, ObjectId, ObjectIdRef};
+use crate::runner::events::EventRecorder;
 use crate::runner::informer::{
-    EventType, LabelToIdIndex, ResourceMessage, ResourceMonitor, UidToIdIndex,
+    EventType, LabelToIdIndex, NamespacedMonitor, RelatedIndex, ResourceMessage, ResourceMonitor,
+    UidToIdIndex,
 };
+use crate::runner::leader_election::LeaderElector;
 use crate::runner::reconcile::SyncHandler;
+use crate::runner::sync_queue::SyncQueue;
 use anyhow::Error;
 use backoff::{backoff::Backoff, ExponentialBackoff};
-use client::Client;
+use client::{Client, DeleteOptions};
 use metrics::Metrics;
 
 use tokio::runtime::{self, Runtime};
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Notify;
 
-use std::collections::{HashMap, HashSet};
-use std::fmt::{self, Display};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// A handle to a potentially running operator, which allows for shutting it down
+/// A handle to a potentially running operator, which allows for shutting it down. Dropping the handle will
+/// shutdown the operator immediately, unless a graceful shutdown has already been started.
 pub struct OperatorHandle {
     running: Arc<AtomicBool>,
+    shutdown: Arc<ShutdownSignal>,
 }
 
 impl std::ops::Drop for OperatorHandle {
     fn drop(&mut self) {
-        self.running.store(false, Ordering::Relaxed);
+        if !self.shutdown.is_requested() {
+            self.shutdown_now_inner();
+        }
     }
 }
 
 impl OperatorHandle {
+    /// Stops the operator immediately, without waiting for any in-progress syncs to complete
     pub fn shutdown_now(self) {
-        self.running.store(false, Ordering::Relaxed);
+        self.shutdown_now_inner();
+    }
+
+    /// Starts a graceful shutdown of the operator. No new syncs will be started, and the operator will wait up
+    /// to the `shutdown_timeout` for in-progress syncs to complete before stopping. This returns immediately,
+    /// and `is_active` will return false once the operator has stopped.
+    pub fn shutdown(&self) {
+        self.shutdown.request();
     }
 
     pub fn is_active(&self) -> bool {
         self.running.load(Ordering::Relaxed)
     }
+
+    fn shutdown_now_inner(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        // wakes up the operator so that it notices that it's no longer running
+        self.shutdown.request();
+    }
+}
+
+/// Used to request a graceful shutdown of the operator, and to wake it up if it's waiting for events
+#[derive(Debug, Default)]
+pub(crate) struct ShutdownSignal {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+impl ShutdownSignal {
+    pub(crate) fn request(&self) {
+        if !self.requested.swap(true, Ordering::SeqCst) {
+            self.notify.notify();
+        }
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Returns once a shutdown has been requested
+    async fn requested(&self) {
+        while !self.is_requested() {
+            self.notify.notified().await;
+        }
+    }
 }
 
+/// Returned from `run_operator` when the operator was shutdown gracefully, for example because it received a
+/// SIGTERM. Callers can check for this using `error.is::<ShutdownRequested>()`.
+#[derive(Debug)]
+pub struct ShutdownRequested;
+impl Display for ShutdownRequested {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Operator was shutdown gracefully")
+    }
+}
+impl std::error::Error for ShutdownRequested {}
+
 #[derive(Debug)]
 pub struct UnexpectedShutdownError;
 impl Display for UnexpectedShutdownError {
@@ -63,8 +129,9 @@ impl Display for UnexpectedShutdownError {
 }
 impl std::error::Error for UnexpectedShutdownError {}
 
-/// Starts the operator and blocks the current thread indefinitely until the operator shuts down due to an error.
-pub fn run_operator(config: OperatorConfig, handler: impl Handler) -> Error {
+/// Starts the operator and blocks the current thread indefinitely until the operator shuts down. The returned
+/// error will be a `ShutdownRequested` if the operator was shutdown gracefully.
+pub fn run_operator(config: OperatorConfig, handler: impl AsyncHandler) -> Error {
     let client_config = {
         let user_agent = config.operator_name.as_str();
         let result = ClientConfig::from_service_account(user_agent).or_else(|_| {
@@ -79,11 +146,28 @@ pub fn run_operator(config: OperatorConfig, handler: impl Handler) -> Error {
     run_operator_with_client_config(config, client_config, handler)
 }
 
-/// Starts the operator and blocks the current thread indefinitely until the operator shuts down due to an error.
+/// Starts the operator and blocks the current thread indefinitely until the operator shuts down. The returned
+/// error will be a `ShutdownRequested` if the operator was shutdown gracefully.
 pub fn run_operator_with_client_config(
     config: OperatorConfig,
     client_config: ClientConfig,
-    handler: impl Handler,
+    handler: impl AsyncHandler,
+) -> Error {
+    run_until_stopped(
+        config,
+        client_config,
+        handler,
+        Arc::new(ShutdownSignal::default()),
+    )
+}
+
+/// Blocks the current thread running the operator until it stops, either because the `shutdown` was requested or
+/// because of an unexpected error
+fn run_until_stopped(
+    config: OperatorConfig,
+    client_config: ClientConfig,
+    handler: impl AsyncHandler,
+    shutdown: Arc<ShutdownSignal>,
 ) -> Error {
     let handler = Arc::new(handler);
     let metrics = Metrics::new();
@@ -97,13 +181,19 @@ pub fn run_operator_with_client_config(
     };
     let running = Arc::new(AtomicBool::new(true));
     let executor = runtime.handle().clone();
+    let signal = shutdown.clone();
     runtime.block_on(async move {
-        run_with_client(executor, metrics, running, config, client, handler).await;
+        run_with_client(executor, metrics, running, signal, config, client, handler).await;
     });
     log::warn!("Operator stopped, shutting down runtime");
-    runtime.shutdown_timeout(Duration::from_secs(30));
-    // return an error here, since the operator will never exit under normal circumstances
-    Error::new(UnexpectedShutdownError)
+    // any in-progress syncs have already been drained, so there's no need to wait long for the remaining tasks
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    if shutdown.is_requested() {
+        Error::new(ShutdownRequested)
+    } else {
+        // return an error here, since the operator will never exit under normal circumstances
+        Error::new(UnexpectedShutdownError)
+    }
 }
 
 /// Starts the operator asynchronously using the provided runtime. This function will return immediately with a
@@ -113,18 +203,23 @@ pub fn start_operator_with_runtime(
     runtime: &Runtime,
     config: OperatorConfig,
     client_config: ClientConfig,
-    handler: impl Handler,
+    handler: impl AsyncHandler,
 ) -> Result<OperatorHandle, Error> {
     let handler = Arc::new(handler);
     let metrics = Metrics::new();
     let client = Client::new(client_config, metrics.client_metrics())?;
     let running = Arc::new(AtomicBool::new(true));
+    let shutdown = Arc::new(ShutdownSignal::default());
     let handle = OperatorHandle {
         running: running.clone(),
+        shutdown: shutdown.clone(),
     };
     let executor = runtime.handle().clone();
     runtime.spawn(async move {
-        run_with_client(executor, metrics, running.clone(), config, client, handler).await;
+        run_with_client(
+            executor, metrics, running, shutdown, config, client, handler,
+        )
+        .await;
     });
     Ok(handle)
 }
@@ -133,6 +228,23 @@ pub fn start_operator_with_runtime(
 pub(crate) struct ChildRuntimeConfig {
     update_strategy: UpdateStrategy,
     child_type: &'static K8sType,
+    scope: ChildScope,
+    propagation_policy: Option<PropagationPolicy>,
+    grace_period_seconds: Option<u32>,
+    retention_policy: RetentionPolicy,
+    adoption_policy: AdoptionPolicy,
+    merge_keys: MergeKeys,
+    value_formats: ValueFormats,
+    ignored_paths: Vec<String>,
+    recreate_on_immutable_field: bool,
+    record_last_applied: bool,
+}
+
+impl ChildRuntimeConfig {
+    /// Returns the options for deleting the child with the given uid
+    pub(crate) fn delete_options(&self, uid: &str) -> DeleteOptions {
+        DeleteOptions::new(self.propagation_policy, self.grace_period_seconds).uid_precondition(uid)
+    }
 }
 
 #[derive(Debug)]
@@ -142,8 +254,13 @@ pub(crate) struct RuntimeConfig {
     pub parent_type: &'static K8sType,
     pub correlation_label_name: String,
     pub controller_label_name: String,
+    pub last_applied_annotation_name: String,
     pub operator_name: String,
+    pub field_manager: String,
     pub max_error_backoff: Duration,
+    pub max_concurrent_syncs: usize,
+    pub shutdown_timeout: Duration,
+    pub events: EventRecorder,
 }
 
 impl RuntimeConfig {
@@ -167,15 +284,36 @@ async fn run_with_client(
     executor: runtime::Handle,
     metrics: Metrics,
     running: Arc<AtomicBool>,
+    shutdown: Arc<ShutdownSignal>,
     config: OperatorConfig,
     client: Client,
-    handler: Arc<dyn Handler>,
+    handler: Arc<dyn AsyncHandler>,
 ) {
     log::debug!("Starting operator with configuration: {:?}", config);
     let server_port = config.server_port;
     let expose_metrics = config.expose_metrics;
     let expose_health = config.expose_health;
-    let mut state = create_operator_state(executor.clone(), metrics, running, config, client).await;
+    if config.handle_signals {
+        executor.spawn(shutdown_on_signal(shutdown.clone()));
+    }
+    // webhooks are served by every replica, regardless of leader election, since the api server may send them anywhere
+    if let Some(webhook_config) = config.webhook.clone() {
+        executor.spawn(webhook::start(
+            webhook_config,
+            config.parent,
+            config.conversions.clone(),
+            handler.clone(),
+        ));
+    }
+    warn_if_conversion_is_missing(&config);
+    if let Some(crd) = config.crd.as_ref() {
+        if let Err(err) = crd_install::install_crd(&client, crd).await {
+            log::error!("Failed to install CustomResourceDefinition: {}", err);
+            return;
+        }
+    }
+    let mut state =
+        create_operator_state(executor.clone(), metrics, running, shutdown, config, client).await;
     if expose_metrics || expose_health {
         let server_future = server::start(
             executor,
@@ -185,27 +323,111 @@ async fn run_with_client(
             expose_health,
         );
         let operator_future = state.run(handler);
-        futures_util::future::join(server_future, operator_future).await;
+        // the server never stops on its own, so it gets dropped as soon as the operator stops. If the server fails,
+        // then the operator keeps running without it.
+        let result =
+            futures_util::future::select(Box::pin(operator_future), Box::pin(server_future)).await;
+        if let futures_util::future::Either::Right((_, operator_future)) = result {
+            operator_future.await;
+        }
     } else {
         state.run(handler).await;
     }
 }
 
+/// Parents that are stored in a different version than the one we watch can only be read if the api server can
+/// convert them, so we log a warning for configurations that are obviously missing something
+fn warn_if_conversion_is_missing(config: &OperatorConfig) {
+    let storage_version = match config.storage_version {
+        Some(v) if v.api_version != config.parent.api_version => v,
+        _ => return,
+    };
+    let watched_version = config.parent.api_version;
+    if config.webhook.is_none() {
+        log::warn!(
+            "storage_version is '{}', but the webhook server is disabled so conversions will not be served",
+            storage_version.api_version
+        );
+    }
+    for &(from, to) in &[
+        (storage_version.api_version, watched_version),
+        (watched_version, storage_version.api_version),
+    ] {
+        if config.get_conversion(from, to).is_none() {
+            log::warn!("No conversion is configured from '{}' to '{}'", from, to);
+        }
+    }
+}
+
+/// Requests a graceful shutdown when the process receives either a SIGTERM or SIGINT
+async fn shutdown_on_signal(shutdown: Arc<ShutdownSignal>) {
+    let result = wait_for_signal().await;
+    match result {
+        Ok(signal) => log::info!("Received {}, starting graceful shutdown", signal),
+        Err(err) => {
+            log::error!("Failed to listen for shutdown signals: {}", err);
+            return;
+        }
+    }
+    shutdown.request();
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> Result<&'static str, std::io::Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let result =
+        futures_util::future::select(Box::pin(sigterm.recv()), Box::pin(sigint.recv())).await;
+    match result {
+        futures_util::future::Either::Left(_) => Ok("SIGTERM"),
+        futures_util::future::Either::Right(_) => Ok("SIGINT"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> Result<&'static str, std::io::Error> {
+    tokio::signal::ctrl_c().await?;
+    Ok("ctrl-c")
+}
+
 async fn create_operator_state(
     executor: runtime::Handle,
     metrics: Metrics,
     running: Arc<AtomicBool>,
+    shutdown: Arc<ShutdownSignal>,
     config: OperatorConfig,
     client: Client,
 ) -> OperatorState {
+    // `None` means a single watch of all namespaces
+    let fixed_namespaces = config.fixed_namespaces();
+    let initial_namespaces = if config.is_cluster_wide() {
+        vec![None]
+    } else {
+        fixed_namespaces
+            .iter()
+            .cloned()
+            .map(Some)
+            .collect::<Vec<_>>()
+    };
     let OperatorConfig {
         parent,
         child_types,
-        namespace,
+        related_types,
+        namespace_selector,
+        parent_label_selector,
+        parent_field_selector,
         operator_name,
         tracking_label_name,
         ownership_label_name,
+        last_applied_annotation_name,
+        field_manager,
         max_error_backoff,
+        max_concurrent_syncs,
+        shutdown_timeout,
+        record_events,
+        leader_election,
         ..
     } = config;
 
@@ -214,12 +436,15 @@ async fn create_operator_state(
     let parent_metrics = metrics.watcher_metrics(parent);
     let parent_monitor = informer::start_parent_monitor(
         executor.clone(),
-        namespace.clone(),
+        &initial_namespaces,
+        parent_label_selector,
+        parent_field_selector,
         parent,
         client.clone(),
         tx.clone(),
         parent_metrics,
-    );
+    )
+    .await;
 
     let mut child_runtime_config = HashMap::with_capacity(4);
     let mut children = HashMap::with_capacity(4);
@@ -229,33 +454,116 @@ async fn create_operator_state(
         let runtime_conf = ChildRuntimeConfig {
             child_type,
             update_strategy: child_conf.update_strategy,
+            scope: child_conf.scope,
+            propagation_policy: child_conf.propagation_policy,
+            grace_period_seconds: child_conf.grace_period_seconds,
+            retention_policy: child_conf.retention_policy,
+            adoption_policy: child_conf.adoption_policy,
+            merge_keys: child_conf.merge_keys,
+            value_formats: child_conf.value_formats,
+            ignored_paths: child_conf.ignored_paths,
+            recreate_on_immutable_field: child_conf.recreate_on_immutable_field,
+            record_last_applied: child_conf.record_last_applied,
         };
         child_runtime_config.insert(child_type, runtime_conf);
+        // children outside of the parent's namespace can only be watched across the whole cluster
+        let child_namespaces = match child_conf.scope {
+            ChildScope::SameNamespace => initial_namespaces.as_slice(),
+            ChildScope::Cluster | ChildScope::AnyNamespace => &[None],
+        };
         let child_monitor = informer::start_child_monitor(
             executor.clone(),
             tracking_label_name.clone(),
-            namespace.clone(),
+            child_namespaces,
             child_type,
             client.clone(),
             tx.clone(),
             child_metrics,
-        );
+        )
+        .await;
         children.insert(child_type, child_monitor);
     }
+
+    let mut related = HashMap::with_capacity(related_types.len());
+    for (related_type, related_conf) in related_types {
+        let related_metrics = metrics.watcher_metrics(related_type);
+        let related_monitor = informer::start_related_monitor(
+            executor.clone(),
+            related_conf,
+            &initial_namespaces,
+            related_type,
+            client.clone(),
+            tx.clone(),
+            related_metrics,
+        )
+        .await;
+        related.insert(related_type, related_monitor);
+    }
+    if let Some(selector) = namespace_selector {
+        let mut monitors: Vec<Arc<dyn NamespacedMonitor>> = vec![Arc::new(parent_monitor.clone())];
+        monitors.extend(
+            children
+                .iter()
+                .filter(|(child_type, _)| {
+                    child_runtime_config[*child_type].scope == ChildScope::SameNamespace
+                })
+                .map(|(_, m)| Arc::new(m.clone()) as Arc<dyn NamespacedMonitor>),
+        );
+        monitors.extend(
+            related
+                .values()
+                .map(|m| Arc::new(m.clone()) as Arc<dyn NamespacedMonitor>),
+        );
+        namespace_watcher::start(
+            executor.clone(),
+            client.clone(),
+            selector,
+            fixed_namespaces,
+            monitors,
+            metrics.watcher_metrics(crate::k8s_types::core::v1::Namespace),
+        )
+        .await;
+    }
+    let events = EventRecorder::new(client.clone(), operator_name.clone(), record_events);
     let runtime_config = Arc::new(RuntimeConfig {
         metrics,
         child_types: child_runtime_config,
         parent_type: parent,
         correlation_label_name: tracking_label_name,
         controller_label_name: ownership_label_name,
+        last_applied_annotation_name,
         operator_name,
+        field_manager,
         max_error_backoff,
+        // a limit of 0 would mean that nothing could ever be synced
+        max_concurrent_syncs: max_concurrent_syncs.max(1),
+        shutdown_timeout,
+        events,
     });
 
+    // Without leader election, we're always the leader. Otherwise we'll wait to acquire the Lease
+    // before syncing anything, but the informers are already started so the caches will be ready
+    let is_leader = Arc::new(AtomicBool::new(leader_election.is_none()));
+    if let Some(leader_election_config) = leader_election {
+        let elector = LeaderElector::new(
+            client.clone(),
+            leader_election_config,
+            runtime_config.clone(),
+            is_leader.clone(),
+            running.clone(),
+        );
+        executor.spawn(elector.run());
+    } else {
+        runtime_config.metrics.set_is_leader(true);
+    }
+
     OperatorState {
         running,
+        shutdown,
+        is_leader,
         parents: parent_monitor,
         children,
+        related,
         sender: tx,
         receiver: rx,
         parent_states: HashMap::new(),
@@ -265,7 +573,7 @@ async fn create_operator_state(
     }
 }
 
-type HandlerRef = Arc<dyn Handler>;
+type HandlerRef = Arc<dyn AsyncHandler>;
 
 #[derive(Debug)]
 struct InProgressUpdate {
@@ -384,8 +692,11 @@ impl ParentState {
 #[derive(Debug)]
 struct OperatorState {
     running: Arc<AtomicBool>,
+    shutdown: Arc<ShutdownSignal>,
+    is_leader: Arc<AtomicBool>,
     parents: ResourceMonitor<UidToIdIndex>,
     children: HashMap<&'static K8sType, ResourceMonitor<LabelToIdIndex>>,
+    related: HashMap<&'static K8sType, ResourceMonitor<RelatedIndex>>,
     sender: Sender<ResourceMessage>,
     receiver: Receiver<ResourceMessage>,
     parent_states: HashMap<String, ParentState>,
@@ -396,8 +707,8 @@ struct OperatorState {
 
 impl OperatorState {
     async fn run(&mut self, handler: HandlerRef) {
-        let mut parent_ids_to_sync = HashSet::with_capacity(16);
-        while self.running.load(Ordering::Relaxed) {
+        let mut parent_ids_to_sync = SyncQueue::new();
+        while self.running.load(Ordering::Relaxed) && !self.shutdown.is_requested() {
             let timeout = if parent_ids_to_sync.is_empty() {
                 Duration::from_secs(3600)
             } else {
@@ -406,12 +717,44 @@ impl OperatorState {
             self.run_once(&mut parent_ids_to_sync, &handler, timeout)
                 .await;
         }
+        if self.running.load(Ordering::Relaxed) {
+            self.drain().await;
+        }
         log::info!("Shutting down operator");
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Waits for all in-progress syncs to complete, for up to the `shutdown_timeout`. No new syncs will be started
+    async fn drain(&mut self) {
+        let deadline = Instant::now() + self.runtime_config.shutdown_timeout;
+        log::info!(
+            "Waiting up to {}ms for {} in-progress syncs to complete",
+            duration_to_millis(self.runtime_config.shutdown_timeout),
+            self.in_flight_sync_count()
+        );
+        // parents that are triggered while we're draining are ignored
+        let mut ignored = SyncQueue::new();
+        while self.in_flight_sync_count() > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                log::warn!(
+                    "Timed out waiting for {} in-progress syncs to complete",
+                    self.in_flight_sync_count()
+                );
+                return;
+            }
+            match tokio::time::timeout(remaining, self.receiver.recv()).await {
+                Ok(Some(message)) => self.handle_received_message(message, &mut ignored),
+                Ok(None) => return,
+                Err(_) => {}
+            }
+        }
+        log::info!("All in-progress syncs have completed");
     }
 
     async fn run_once(
         &mut self,
-        parent_ids_to_sync: &mut HashSet<String>,
+        parent_ids_to_sync: &mut SyncQueue,
         handler: &HandlerRef,
         timeout: Duration,
     ) {
@@ -421,24 +764,52 @@ impl OperatorState {
         );
         self.get_parent_uids_to_update(parent_ids_to_sync, timeout)
             .await;
-        if !self.running.load(Ordering::Relaxed) {
+        if !self.running.load(Ordering::Relaxed) || self.shutdown.is_requested() {
             // getting the uids to update can take quite a while, so we'll do an extra check to see
             // if the operator has been shutdown in the meantime
             return;
         }
+        if !self.is_leader.load(Ordering::Relaxed) {
+            // we keep track of the parents that need to be synced, so that we can sync them as soon
+            // as we become the leader
+            log::debug!(
+                "Not syncing {} parents because this operator is not the leader",
+                parent_ids_to_sync.len()
+            );
+            return;
+        }
 
+        let max_concurrent_syncs = self.runtime_config.max_concurrent_syncs;
+        let mut in_flight = self.in_flight_sync_count();
         let mut synced_parents = Vec::new();
         for parent_uid in parent_ids_to_sync.iter() {
+            if in_flight >= max_concurrent_syncs {
+                // the remaining parents keep their place in the queue, and will be synced as soon as
+                // the in-progress syncs complete
+                log::debug!(
+                    "Reached max of {} concurrent syncs, with {} parents still waiting",
+                    max_concurrent_syncs,
+                    parent_ids_to_sync.len() - synced_parents.len()
+                );
+                break;
+            }
             if !self.is_update_in_progress(parent_uid) {
-                let result = self.sync_parent(parent_uid.as_str(), handler.clone()).await;
-                if let Err(err) = result {
-                    log::error!(
-                        "Cannot sync parent with uid: {} due to error: {:?}",
-                        parent_uid,
-                        err
-                    );
-                } else {
-                    synced_parents.push(parent_uid.clone());
+                match self.sync_parent(parent_uid.as_str(), handler.clone()).await {
+                    Ok(started) => {
+                        // a parent that was deleted in the meantime is dropped from the queue, but it doesn't
+                        // take up one of the concurrent syncs
+                        if started {
+                            in_flight += 1;
+                        }
+                        synced_parents.push(parent_uid.clone());
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Cannot sync parent with uid: {} due to error: {:?}",
+                            parent_uid,
+                            err
+                        );
+                    }
                 }
             }
         }
@@ -460,6 +831,13 @@ impl OperatorState {
             .any(ParentState::is_update_in_progress)
     }
 
+    fn in_flight_sync_count(&self) -> usize {
+        self.parent_states
+            .values()
+            .filter(|state| state.is_update_in_progress())
+            .count()
+    }
+
     fn is_update_in_progress(&self, parent_uid: &str) -> bool {
         self.parent_states
             .get(parent_uid)
@@ -467,12 +845,14 @@ impl OperatorState {
             .unwrap_or(false)
     }
 
-    async fn sync_parent(&mut self, parent_uid: &str, handler: HandlerRef) -> Result<(), Error> {
+    /// Starts syncing the parent with the given uid, and returns whether a sync was actually started. Returns false if
+    /// the parent has been deleted since it was queued.
+    async fn sync_parent(&mut self, parent_uid: &str, handler: HandlerRef) -> Result<bool, Error> {
         let parent = match self.get_parent(parent_uid).await? {
             Some(p) => p,
             None => {
                 log::info!("Cannot sync parent with uid: '{}' because resource has been subsequently deleted", parent_uid);
-                return Ok(());
+                return Ok(false);
             }
         };
 
@@ -496,7 +876,7 @@ impl OperatorState {
             parent_index_key: parent_uid.to_owned(),
         };
         handler.start_sync();
-        Ok(())
+        Ok(true)
     }
 
     fn get_or_create_parent_state<'a, 'b>(
@@ -514,7 +894,12 @@ impl OperatorState {
 
     async fn create_sync_request(&self, parent: K8sResource) -> Result<SyncRequest, Error> {
         let children = self.get_all_children(parent.uid()).await?;
-        Ok(SyncRequest { parent, children })
+        let related = self.get_all_related(&parent).await?;
+        Ok(SyncRequest {
+            parent,
+            children,
+            related,
+        })
     }
 
     #[cfg(feature = "testkit")]
@@ -543,14 +928,85 @@ impl OperatorState {
         Ok(request_children)
     }
 
+    async fn get_all_related(&self, parent: &K8sResource) -> Result<Vec<K8sResource>, Error> {
+        // related resources may map to the parent by either uid or id, or both
+        let uid = parent.uid();
+        let id = parent.get_object_id().to_string();
+        let mut request_related = Vec::new();
+
+        for related_monitor in self.related.values() {
+            let lock = related_monitor.lock_state().await?;
+            let mut related_of_type = lock.get_all_resources_by_index_key(uid);
+            for resource in lock.get_all_resources_by_index_key(id.as_str()) {
+                if !related_of_type.contains(&resource) {
+                    related_of_type.push(resource);
+                }
+            }
+            request_related.extend(related_of_type);
+        }
+        Ok(request_related)
+    }
+
+    /// Messages for related resources may be indexed by the namespace and name of the parent instead of the uid,
+    /// so this replaces the index key with the uid of the parent. Returns `None` if the message is for a related
+    /// resource that doesn't map to any existing parent.
+    async fn resolve_related_parent(
+        &self,
+        mut message: ResourceMessage,
+    ) -> Option<ResourceMessage> {
+        if !self.related.contains_key(message.resource_type) {
+            return Some(message);
+        }
+        let index_key = match message.index_key.take() {
+            Some(key) => key,
+            None => {
+                log::trace!(
+                    "Ignoring related resource {} {} that does not map to any parents",
+                    message.resource_type,
+                    message.resource_id
+                );
+                return None;
+            }
+        };
+        // uids never contain a '/', so this key must be a parent uid
+        let separator = match index_key.find('/') {
+            Some(idx) => idx,
+            None => {
+                message.index_key = Some(index_key);
+                return Some(message);
+            }
+        };
+        let parent_id = ObjectIdRef::new(&index_key[..separator], &index_key[(separator + 1)..]);
+        let parent_uid = match self.parents.lock_state().await {
+            Ok(parents) => parents
+                .get_by_id(&parent_id)
+                .map(|parent| parent.uid().to_owned()),
+            Err(err) => {
+                log::warn!(
+                    "Unable to lookup parent: {} for related resource: {} due to error: {:?}",
+                    parent_id,
+                    message.resource_id,
+                    err
+                );
+                None
+            }
+        };
+        if parent_uid.is_none() {
+            log::debug!(
+                "Related resource {} {} maps to parent: {}, which does not exist",
+                message.resource_type,
+                message.resource_id,
+                parent_id
+            );
+        }
+        message.index_key = Some(parent_uid?);
+        Some(message)
+    }
+
     /// Tries to receive a whole batch of messages, so that we can consolidate them by parent id.
     /// The `max_timeout` is treated as a soft limit, which may be exceeded by a bit in case there are
     /// tons of messages to process.
-    async fn get_parent_uids_to_update(
-        &mut self,
-        to_sync: &mut HashSet<String>,
-        max_timeout: Duration,
-    ) {
+    async fn get_parent_uids_to_update(&mut self, to_sync: &mut SyncQueue, max_timeout: Duration) {
         let starting_to_sync_len = to_sync.len();
         let start_time = Instant::now();
         let mut first_receive_time = start_time;
@@ -565,7 +1021,9 @@ impl OperatorState {
             }
             total_messages += 1;
             log::trace!("Received: {:?}", message);
-            self.handle_received_message(message, to_sync);
+            if let Some(message) = self.resolve_related_parent(message).await {
+                self.handle_received_message(message, to_sync);
+            }
 
             // if we've been receiving messages for a while, then we'll use a super short timeout so that
             // we can start syncing as soon as possible
@@ -593,7 +1051,7 @@ impl OperatorState {
         );
     }
 
-    fn handle_received_message(&mut self, message: ResourceMessage, to_sync: &mut HashSet<String>) {
+    fn handle_received_message(&mut self, message: ResourceMessage, to_sync: &mut SyncQueue) {
         self.runtime_config.metrics.watch_event_received();
         if message.index_key.is_none() {
             // TODO: change resourceMessage so that index_key is not an Option
@@ -696,7 +1154,16 @@ impl OperatorState {
     }
 
     async fn recv_next(&mut self, timeout: Duration) -> Option<ResourceMessage> {
-        match tokio::time::timeout(timeout, self.receiver.recv()).await {
+        let shutdown = self.shutdown.clone();
+        let recv = tokio::time::timeout(timeout, self.receiver.recv());
+        let result =
+            futures_util::future::select(Box::pin(recv), Box::pin(shutdown.requested())).await;
+        let recv_result = match result {
+            futures_util::future::Either::Left((recv_result, _)) => recv_result,
+            // stop receiving messages as soon as a shutdown is requested
+            futures_util::future::Either::Right(_) => return None,
+        };
+        match recv_result {
             Err(_) => None,
             Ok(Some(val)) => Some(val),
             Ok(None) => {
@@ -717,10 +1184,310 @@ pub(crate) fn duration_to_millis(duration: Duration) -> u64 {
     millis
 }
 
+/// Formats the time as a Kubernetes `MicroTime`, which is RFC 3339 with microsecond precision
+pub(crate) fn format_micro_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86_400) as i64;
+    let secs_of_day = secs % 86_400;
+
+    // converts days since the epoch into a civil date. See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let mut result = String::with_capacity(27);
+    write!(
+        result,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+    .unwrap();
+    result
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn formats_micro_time() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_582_979_696_123_456);
+        assert_eq!("2020-02-29T12:34:56.123456Z", format_micro_time(time));
+        assert_eq!("1970-01-01T00:00:00.000000Z", format_micro_time(UNIX_EPOCH));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn graceful_shutdown_waits_for_in_progress_syncs() {
+        use crate::config::ChildConfig;
+        use crate::k8s_types::core::v1::ConfigMap;
+        use crate::runner::testkit::fixture::*;
+        use crate::runner::testkit::FakeApiServer;
+
+        let server = FakeApiServer::start().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let client = Client::new(
+            server.client_config("test"),
+            Metrics::new().client_metrics(),
+        )
+        .unwrap();
+        runtime
+            .block_on(client.create_resource(PARENT_TYPE, &parent("ns", "parent")))
+            .unwrap();
+
+        let sync_started = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let sync_started = sync_started.clone();
+            move |req: &SyncRequest| {
+                sync_started.store(true, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(500));
+                let child = v1_resource("ConfigMap", Some("ns"), req.parent.name());
+                Ok(response(vec![child]))
+            }
+        };
+        let config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace())
+            .expose_metrics(false)
+            .expose_health(false);
+        let handle = start_operator_with_runtime(
+            &runtime,
+            config,
+            server.client_config(OPERATOR_NAME),
+            handler,
+        )
+        .unwrap();
+
+        wait_for(&|| sync_started.load(Ordering::SeqCst));
+        handle.shutdown();
+        wait_for(&|| !handle.is_active());
+
+        let child = runtime
+            .block_on(client.get_resource(ConfigMap, &ObjectIdRef::new("ns", "parent")))
+            .unwrap();
+        assert!(child.is_some());
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn operator_returns_after_shutdown_with_server_exposed() {
+        use crate::runner::testkit::fixture::*;
+        use crate::runner::testkit::FakeApiServer;
+
+        let server = FakeApiServer::start().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let client = Client::new(
+            server.client_config("test"),
+            Metrics::new().client_metrics(),
+        )
+        .unwrap();
+        runtime
+            .block_on(client.create_resource(PARENT_TYPE, &parent("ns", "parent")))
+            .unwrap();
+
+        let sync_started = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let sync_started = sync_started.clone();
+            move |_: &SyncRequest| {
+                sync_started.store(true, Ordering::SeqCst);
+                Ok(response(Vec::new()))
+            }
+        };
+        // bind to an ephemeral port first so the test doesn't depend on the default port being free
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let config = operator_config()
+            .server_port(port)
+            .expose_metrics(true)
+            .expose_health(true);
+        let shutdown = Arc::new(ShutdownSignal::default());
+        let (tx, rx) = std::sync::mpsc::channel();
+        {
+            let client_config = server.client_config(OPERATOR_NAME);
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || {
+                let error = run_until_stopped(config, client_config, handler, shutdown);
+                tx.send(error).ok();
+            });
+        }
+
+        wait_for(&|| sync_started.load(Ordering::SeqCst));
+        shutdown.request();
+        let error = rx
+            .recv_timeout(TIMEOUT)
+            .expect("operator did not return after shutdown");
+        assert!(error.is::<ShutdownRequested>());
+    }
+
+    #[cfg(feature = "testkit")]
+    fn wait_for(condition: &dyn Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(
+                start.elapsed() < crate::runner::testkit::fixture::TIMEOUT,
+                "timed out"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn related_resources_trigger_sync_of_mapped_parents() {
+        use crate::config::{ChildConfig, ParentRef, RelatedConfig};
+        use crate::k8s_types::core::v1::{ConfigMap, Secret};
+        use crate::runner::testkit::fixture::*;
+        use serde_json::{json, Value};
+
+        // maps each Secret to the parent that's named in its annotation
+        let related_config = RelatedConfig::new(|secret| {
+            secret
+                .as_ref()
+                .pointer("/metadata/annotations/example.com~1parent")
+                .and_then(Value::as_str)
+                .map(|name| {
+                    let namespace = secret.namespace().unwrap_or("").to_owned();
+                    ParentRef::Id(ObjectId::new(namespace, name.to_owned()))
+                })
+                .into_iter()
+                .collect()
+        });
+        let operator_config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace())
+            .with_related(Secret, related_config);
+        let handler = |req: &SyncRequest| {
+            let mut child = v1_resource("ConfigMap", req.parent.namespace(), "child");
+            child["data"] = req
+                .related()
+                .of_type(Secret)
+                .first()
+                .and_then(|secret| secret.as_ref().get("data").cloned())
+                .unwrap_or_default();
+            Ok(response(vec![child]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        let mut secret = v1_resource("Secret", Some("ns"), "secret");
+        secret["metadata"]["annotations"] = json!({"example.com/parent": "parent"});
+        secret["data"] = json!({"foo": "one"});
+        testkit.create_resource(Secret, &secret).unwrap();
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        let child_id = ObjectIdRef::new("ns", "child");
+        testkit.assert_resource_eq_eventually(
+            ConfigMap,
+            &child_id,
+            json!({"data": {"foo": "one"}}),
+            TIMEOUT,
+        );
+
+        secret["data"]["foo"] = "two".into();
+        testkit
+            .replace_resource(Secret, &ObjectIdRef::new("ns", "secret"), secret)
+            .unwrap();
+        testkit.assert_resource_eq_eventually(
+            ConfigMap,
+            &child_id,
+            json!({"data": {"foo": "two"}}),
+            TIMEOUT,
+        );
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn parent_syncs_are_limited_to_max_concurrent_syncs() {
+        use crate::config::ChildConfig;
+        use crate::k8s_types::core::v1::ConfigMap;
+        use crate::runner::testkit::fixture::*;
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Mutex;
+
+        let operator_config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace())
+            .max_concurrent_syncs(2);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(Mutex::new(0));
+        let handler = {
+            let max_in_flight = max_in_flight.clone();
+            move |req: &SyncRequest| {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                {
+                    let mut max = max_in_flight.lock().unwrap();
+                    *max = current.max(*max);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                let child = v1_resource("ConfigMap", req.parent.namespace(), req.parent.name());
+                Ok(response(vec![child]))
+            }
+        };
+        let mut testkit = start(operator_config, handler);
+
+        let names = ["one", "two", "three", "four", "five"];
+        for name in names.iter() {
+            testkit
+                .create_resource(PARENT_TYPE, &parent("ns", name))
+                .unwrap();
+        }
+        for name in names.iter() {
+            testkit.assert_resource_exists_eventually(
+                ConfigMap,
+                &ObjectIdRef::new("ns", name),
+                TIMEOUT,
+            );
+        }
+        let max_in_flight = *max_in_flight.lock().unwrap();
+        assert!(max_in_flight > 0 && max_in_flight <= 2);
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn parent_selectors_limit_which_parents_are_synced() {
+        use crate::config::ChildConfig;
+        use crate::k8s_types::core::v1::ConfigMap;
+        use crate::runner::testkit::fixture::*;
+        use serde_json::json;
+
+        let operator_config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace())
+            .parent_label_selector("shard=a")
+            .parent_field_selector("metadata.name!=skipped");
+        let handler = |req: &SyncRequest| {
+            let child = v1_resource("ConfigMap", req.parent.namespace(), req.parent.name());
+            Ok(response(vec![child]))
+        };
+        let mut testkit = start(operator_config, handler);
+        for (name, shard) in &[("other-shard", "b"), ("skipped", "a"), ("matching", "a")] {
+            let mut parent = parent("ns", name);
+            parent["metadata"]["labels"] = json!({ "shard": shard });
+            testkit.create_resource(PARENT_TYPE, &parent).unwrap();
+        }
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("ns", "matching"),
+            TIMEOUT,
+        );
+        testkit.reconcile(Duration::from_millis(200)).ok();
+        for name in &["other-shard", "skipped"] {
+            let child = get_resource(&mut testkit, ConfigMap, "ns", name);
+            assert!(child.is_none(), "unexpected child: {}", name);
+        }
+    }
+
     #[test]
     fn parent_state_backoff_increases_exponentially() {
         let parent_id = ObjectId::new("foo".to_owned(), "bar".to_owned());
@@ -0,0 +1,146 @@
+//! Starts and stops the per-namespace watches of every informer as Namespaces that match the
+//! `OperatorConfig::namespace_selector` are created, deleted, or relabeled.
+use crate::runner::client::Client;
+use crate::runner::informer::{self, NamespacedMonitor, ResourceMessage};
+use crate::runner::metrics::WatcherMetrics;
+
+use tokio::runtime::Handle;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Starts watching the Namespaces that match the `label_selector`. The `fixed_namespaces` are always watched, so
+/// they're never stopped, even if they stop matching the selector.
+pub(crate) async fn start(
+    executor: Handle,
+    client: Client,
+    label_selector: String,
+    fixed_namespaces: Vec<String>,
+    monitors: Vec<Arc<dyn NamespacedMonitor>>,
+    watcher_metrics: WatcherMetrics,
+) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<ResourceMessage>(64);
+    let namespace_monitor = informer::start_namespace_monitor(
+        executor.clone(),
+        label_selector,
+        client,
+        tx,
+        watcher_metrics,
+    )
+    .await;
+
+    executor.spawn(async move {
+        let mut watched = HashSet::new();
+        // The messages just tell us that something changed. We always compare the whole set of matching Namespaces
+        // with what's being watched, so that nothing is missed if the namespace monitor needs to re-list.
+        while rx.recv().await.is_some() {
+            let matching = match namespace_monitor.lock_state().await {
+                Ok(state) => state
+                    .get_all_ids()
+                    .into_iter()
+                    .map(|id| id.name)
+                    .collect::<HashSet<_>>(),
+                Err(err) => {
+                    log::debug!("Namespace cache is not ready: {}", err);
+                    continue;
+                }
+            };
+            for namespace in matching.difference(&watched) {
+                log::info!("Starting to watch namespace: {}", namespace);
+                for monitor in monitors.iter() {
+                    monitor.start_namespace(Some(namespace.clone())).await;
+                }
+            }
+            for namespace in watched.difference(&matching) {
+                if fixed_namespaces.contains(namespace) {
+                    continue;
+                }
+                log::info!("Stopping watch of namespace: {}", namespace);
+                for monitor in monitors.iter() {
+                    monitor.stop_namespace(namespace.as_str()).await;
+                }
+            }
+            watched = matching;
+        }
+        log::info!("Ending namespace watcher");
+    });
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod test {
+    use crate::config::ChildConfig;
+    use crate::handler::SyncRequest;
+    use crate::k8s_types::core::v1::{ConfigMap, Namespace};
+    use crate::resource::ObjectIdRef;
+    use crate::runner::testkit::fixture::*;
+    use serde_json::{json, Value};
+
+    use std::time::Duration;
+
+    fn namespace(name: &str, tenant: &str) -> Value {
+        let mut namespace = v1_resource("Namespace", None, name);
+        namespace["metadata"]["labels"] = json!({ "tenant": tenant });
+        namespace
+    }
+
+    #[test]
+    fn watches_fixed_namespaces_and_namespaces_matching_selector() {
+        let operator_config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace())
+            .within_namespaces(vec!["fixed"])
+            .namespace_selector("tenant=true");
+        let handler = |req: &SyncRequest| {
+            let child = v1_resource("ConfigMap", req.parent.namespace(), req.parent.name());
+            Ok(response(vec![child]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit
+            .create_resource(Namespace, &namespace("tenant-a", "true"))
+            .unwrap();
+        testkit
+            .create_resource(Namespace, &namespace("tenant-b", "false"))
+            .unwrap();
+        for ns in &["tenant-b", "fixed", "tenant-a"] {
+            testkit
+                .create_resource(PARENT_TYPE, &parent(ns, "parent"))
+                .unwrap();
+        }
+        for ns in &["fixed", "tenant-a"] {
+            testkit.assert_resource_exists_eventually(
+                ConfigMap,
+                &ObjectIdRef::new(ns, "parent"),
+                TIMEOUT,
+            );
+        }
+        assert!(get_resource(&mut testkit, ConfigMap, "tenant-b", "parent").is_none());
+
+        // relabeling the namespaces should stop the watch of tenant-a and start the watch of tenant-b
+        for (name, tenant) in &[("tenant-a", "false"), ("tenant-b", "true")] {
+            testkit
+                .replace_resource(
+                    Namespace,
+                    &ObjectIdRef::new("", name),
+                    namespace(name, tenant),
+                )
+                .unwrap();
+        }
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("tenant-b", "parent"),
+            TIMEOUT,
+        );
+        for ns in &["tenant-a", "fixed"] {
+            testkit
+                .create_resource(PARENT_TYPE, &parent(ns, "late"))
+                .unwrap();
+        }
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("fixed", "late"),
+            TIMEOUT,
+        );
+        testkit.reconcile(Duration::from_millis(200)).ok();
+        assert!(get_resource(&mut testkit, ConfigMap, "tenant-a", "late").is_none());
+    }
+}
@@ -1,14 +1,27 @@
 #![allow(clippy::ptr_arg)]
+mod values;
 
+use crate::config::{MergeKeys, ValueFormat, ValueFormats};
+
+use lazy_static::lazy_static;
 use serde_json::Value;
 
 use std::fmt::{self, Display, Write};
 
 type JsonObject = serde_json::Map<String, Value>;
 
+lazy_static! {
+    static ref DEFAULT_MERGE_KEYS: MergeKeys = MergeKeys::default();
+    static ref DEFAULT_VALUE_FORMATS: ValueFormats = ValueFormats::default();
+    static ref NAME_KEY: Vec<String> = vec!["name".to_owned()];
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Diff<'a> {
     pub path: String,
+    /// A JSON pointer (RFC 6901) to the location of this diff within the _desired_ value. Array
+    /// indexes always refer to the position in the desired array, even for associative arrays.
+    pub pointer: String,
     pub existing: &'a Value,
     pub desired: &'a Value,
 }
@@ -36,10 +49,172 @@ impl<'a> Diffs<'a> {
         self.0.len()
     }
 
+    pub fn append(&mut self, mut other: Diffs<'a>) {
+        self.0.append(&mut other.0);
+    }
+
+    /// Returns true if any of the diffs is for items that were removed from an array. Only `removed_values_with`
+    /// returns diffs where both values are whole arrays, since `compare_values` always compares the items.
+    pub fn removes_array_items(&self) -> bool {
+        self.0
+            .iter()
+            .any(|diff| diff.existing.is_array() && diff.desired.is_array())
+    }
+
+    /// Removes the diffs at, or within, any of the `ignored_paths`. Each one is a JSON pointer, where a `*` matches
+    /// any part of a single segment
+    pub fn remove_ignored(&mut self, ignored_paths: &[String]) {
+        if ignored_paths.is_empty() {
+            return;
+        }
+        let patterns = ignored_paths
+            .iter()
+            .map(|path| pointer_segments(path))
+            .collect::<Vec<_>>();
+        self.0.retain(|diff| {
+            let segments = pointer_segments(diff.pointer.as_str());
+            !patterns
+                .iter()
+                .any(|pattern| is_within(pattern.as_slice(), segments.as_slice()))
+        });
+    }
+
     #[cfg(feature = "testkit")]
     pub fn into_vec(self) -> Vec<Diff<'a>> {
         self.0
     }
+
+    /// Returns a JSON merge patch that includes every value from `desired` that's referenced by one
+    /// of the diffs. Arrays can only be replaced as a whole by a merge patch, so any diff within an
+    /// array will result in the entire desired array being included. The same value works as a
+    /// strategic merge patch, which will merge arrays that have a patch merge key. Values that were
+    /// removed from `desired` are set to `null`, which removes them.
+    pub fn to_merge_patch(&self, desired: &Value) -> Value {
+        let mut patch = Value::Object(JsonObject::new());
+        for diff in self.0.iter() {
+            add_to_merge_patch(&mut patch, desired, diff.pointer.as_str());
+        }
+        patch
+    }
+
+    /// Returns a JSON patch (RFC 6902) consisting of `add` operations for each of the diffs. Just
+    /// like with `to_merge_patch`, diffs within arrays will result in the whole array being replaced,
+    /// since the array indexes of the existing value may not match those in `desired`. Values that
+    /// were removed from `desired` get a `remove` operation instead.
+    pub fn to_json_patch(&self, desired: &Value) -> Value {
+        let mut pointers: Vec<String> = Vec::with_capacity(self.0.len());
+        for diff in self.0.iter() {
+            let pointer = truncate_at_array(desired, diff.pointer.as_str());
+            if !pointers.contains(&pointer) {
+                pointers.push(pointer);
+            }
+        }
+        let ops = pointers
+            .into_iter()
+            .map(|pointer| {
+                let mut op = JsonObject::new();
+                match desired.pointer(pointer.as_str()).cloned() {
+                    Some(value) => {
+                        op.insert("op".to_owned(), Value::String("add".to_owned()));
+                        op.insert("path".to_owned(), Value::String(pointer));
+                        op.insert("value".to_owned(), value);
+                    }
+                    None => {
+                        op.insert("op".to_owned(), Value::String("remove".to_owned()));
+                        op.insert("path".to_owned(), Value::String(pointer));
+                    }
+                }
+                Value::Object(op)
+            })
+            .collect();
+        Value::Array(ops)
+    }
+}
+
+fn add_to_merge_patch(patch: &mut Value, desired: &Value, pointer: &str) {
+    if pointer.is_empty() {
+        *patch = desired.clone();
+        return;
+    }
+    let mut desired_node = desired;
+    let mut patch_node = patch;
+    let segments = pointer_segments(pointer);
+    for (i, segment) in segments.iter().enumerate() {
+        let patch_obj = match patch_node.as_object_mut() {
+            Some(obj) => obj,
+            // a parent of this value was already included in the patch, so there's nothing left to add
+            None => return,
+        };
+        let next_desired = match desired_node.get(segment.as_str()) {
+            Some(v) => v,
+            // the value was removed from desired, so a null will remove it
+            None => {
+                patch_obj.insert(segment.clone(), Value::Null);
+                return;
+            }
+        };
+        if i == segments.len() - 1 || !next_desired.is_object() {
+            patch_obj.insert(segment.clone(), next_desired.clone());
+            return;
+        }
+        patch_node = patch_obj
+            .entry(segment.clone())
+            .or_insert_with(|| Value::Object(JsonObject::new()));
+        desired_node = next_desired;
+    }
+}
+
+fn truncate_at_array(desired: &Value, pointer: &str) -> String {
+    let mut result = String::with_capacity(pointer.len());
+    let mut node = desired;
+    for segment in pointer_segments(pointer) {
+        match node.get(segment.as_str()) {
+            Some(next) if node.is_object() => {
+                push_pointer_segment(&mut result, segment.as_str());
+                if next.is_array() {
+                    break;
+                }
+                node = next;
+            }
+            // the value was removed from desired
+            None if node.is_object() => {
+                push_pointer_segment(&mut result, segment.as_str());
+                break;
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Returns true if the `pointer` is at or within the location that's matched by the `pattern`
+fn is_within(pattern: &[String], pointer: &[String]) -> bool {
+    pattern.len() <= pointer.len()
+        && pattern
+            .iter()
+            .zip(pointer.iter())
+            .all(|(p, s)| glob_matches(p.as_bytes(), s.as_bytes()))
+}
+
+fn glob_matches(pattern: &[u8], value: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => value.is_empty(),
+        Some((b'*', rest)) => (0..=value.len()).any(|i| glob_matches(rest, &value[i..])),
+        Some((c, rest)) => value.first() == Some(c) && glob_matches(rest, &value[1..]),
+    }
+}
+
+fn pointer_segments(pointer: &str) -> Vec<String> {
+    pointer
+        .split('/')
+        .skip(1)
+        .map(|s| s.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+fn push_pointer_segment(pointer: &mut String, segment: &str) {
+    pointer.push('/');
+    pointer.push_str(segment.replace('~', "~0").replace('/', "~1").as_str());
 }
 
 impl<'a> Display for Diffs<'a> {
@@ -97,31 +272,350 @@ enum Segment<'a> {
 /// Kubernetes sometimes uses associative arrays for things like environment variables, which makes
 /// comparison of arrays difficult because the order is not relevant. At least for now, we use a
 /// fairly simple way of detective whether a given array should be compared as an associative array
-/// or a regular one (regular arrays simply check if all the elements are equal in order). If _all_
-/// the items in the desired array are objects that contain a `name` field with a String value, then
-/// we'll consider the array to be associative.
+/// or a regular one (regular arrays simply check if all the elements are equal in order). The
+/// default `MergeKeys` hold the keys for arrays in the builtin Kubernetes types, such as `ports` or
+/// `volumeMounts`. For any other field, if _all_ the items in the desired array are objects that
+/// contain a `name` field, then we'll consider the array to be associative.
+///
+/// Scalar values in fields with a known `ValueFormat` are compared by what they represent, so that
+/// a desired `cpu: "1"` is equal to an existing `cpu: "1000m"`. The default `ValueFormats` cover
+/// the builtin Kubernetes types.
 pub fn compare_values<'a>(existing: &'a Value, desired: &'a Value) -> Diffs<'a> {
+    compare_values_with(
+        existing,
+        desired,
+        &DEFAULT_MERGE_KEYS,
+        &DEFAULT_VALUE_FORMATS,
+    )
+}
+
+/// The same as `compare_values`, except that associative arrays are identified using the given `merge_keys`, and
+/// scalar values are compared using the given `value_formats`
+pub fn compare_values_with<'a>(
+    existing: &'a Value,
+    desired: &'a Value,
+    merge_keys: &MergeKeys,
+    value_formats: &ValueFormats,
+) -> Diffs<'a> {
+    let hints = Hints {
+        merge_keys,
+        value_formats,
+    };
+    let mut diffs = Vec::new();
+    let mut path = Vec::with_capacity(8);
+    compare(&mut diffs, &mut path, &hints, existing, desired);
+    Diffs(diffs)
+}
+
+/// Returns diffs for the values that were included in the `last_applied` state, but were since removed from `desired`,
+/// and are still present in `existing`. A removed object field results in a diff at that field, which isn't present in
+/// `desired`. A removed array item results in a diff for the whole array, since arrays can only be replaced.
+pub fn removed_values_with<'a>(
+    last_applied: &Value,
+    existing: &'a Value,
+    desired: &'a Value,
+    merge_keys: &MergeKeys,
+) -> Diffs<'a> {
     let mut diffs = Vec::new();
     let mut path = Vec::with_capacity(8);
-    compare(&mut diffs, &mut path, existing, desired);
+    find_removed(
+        &mut diffs,
+        &mut path,
+        merge_keys,
+        last_applied,
+        existing,
+        desired,
+    );
     Diffs(diffs)
 }
 
+/// Copies the values at the `ignored_paths` from `existing` into `desired`, and removes those that aren't in `existing`.
+/// This keeps ignored fields as they are when the whole desired value is sent, or a whole array from it is, which is the
+/// case for replacing or applying a child, or for merge patches that change an array. Array items are matched up the
+/// same way that they are when comparing values.
+pub fn preserve_ignored(
+    existing: &Value,
+    desired: &mut Value,
+    ignored_paths: &[String],
+    merge_keys: &MergeKeys,
+) {
+    for path in ignored_paths {
+        let pattern = pointer_segments(path.as_str());
+        preserve_at(existing, desired, pattern.as_slice(), "", merge_keys);
+    }
+}
+
+/// Removes the values at the `ignored_paths` from `desired`. This is used instead of `preserve_ignored` for server-side
+/// apply, where sending a field would make the operator's field manager take ownership of it.
+pub fn remove_ignored_values(desired: &mut Value, ignored_paths: &[String]) {
+    for path in ignored_paths {
+        let pattern = pointer_segments(path.as_str());
+        remove_at(desired, pattern.as_slice());
+    }
+}
+
+fn remove_at(desired: &mut Value, pattern: &[String]) {
+    let (segment, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let matches = |key: &str| glob_matches(segment.as_bytes(), key.as_bytes());
+    match desired {
+        Value::Object(ref mut desired_map) => {
+            if rest.is_empty() {
+                let keys = desired_map
+                    .keys()
+                    .filter(|key| matches(key.as_str()))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in keys {
+                    desired_map.remove(key.as_str());
+                }
+            } else {
+                for (key, value) in desired_map.iter_mut() {
+                    if matches(key.as_str()) {
+                        remove_at(value, rest);
+                    }
+                }
+            }
+        }
+        Value::Array(ref mut desired_items) => {
+            if rest.is_empty() {
+                let mut i = 0;
+                desired_items.retain(|_| {
+                    i += 1;
+                    !matches((i - 1).to_string().as_str())
+                });
+            } else {
+                for (i, item) in desired_items.iter_mut().enumerate() {
+                    if matches(i.to_string().as_str()) {
+                        remove_at(item, rest);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn preserve_at(
+    existing: &Value,
+    desired: &mut Value,
+    pattern: &[String],
+    field: &str,
+    merge_keys: &MergeKeys,
+) {
+    let (segment, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let matches = |key: &str| glob_matches(segment.as_bytes(), key.as_bytes());
+    match desired {
+        Value::Object(ref mut desired_map) => {
+            let mut keys = desired_map
+                .keys()
+                .filter(|key| matches(key.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+            if let Some(existing_map) = existing.as_object() {
+                for key in existing_map.keys() {
+                    if matches(key.as_str()) && !desired_map.contains_key(key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+            for key in keys {
+                let existing_value = existing.get(key.as_str());
+                if rest.is_empty() {
+                    match existing_value {
+                        Some(value) => desired_map.insert(key, value.clone()),
+                        None => desired_map.remove(key.as_str()),
+                    };
+                    continue;
+                }
+                let existing_value = match existing_value {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let added = !desired_map.contains_key(key.as_str());
+                if added {
+                    // only objects can be added to, since arrays are matched up with the desired items
+                    if !existing_value.is_object() {
+                        continue;
+                    }
+                    desired_map.insert(key.clone(), Value::Object(JsonObject::new()));
+                }
+                let desired_value = desired_map.get_mut(key.as_str()).unwrap();
+                preserve_at(
+                    existing_value,
+                    desired_value,
+                    rest,
+                    key.as_str(),
+                    merge_keys,
+                );
+                if added
+                    && desired_value
+                        .as_object()
+                        .map(|o| o.is_empty())
+                        .unwrap_or(false)
+                {
+                    // nothing was preserved inside of the object that we added
+                    desired_map.remove(key.as_str());
+                }
+            }
+        }
+        Value::Array(ref mut desired_items) => {
+            let existing_items = match existing.as_array() {
+                Some(items) => items,
+                None => return,
+            };
+            let key =
+                associative_key(merge_keys, field, desired_items.as_slice()).map(<[_]>::to_vec);
+            for (i, desired_item) in desired_items.iter_mut().enumerate() {
+                if !matches(i.to_string().as_str()) {
+                    continue;
+                }
+                let existing_item = match key {
+                    Some(ref key) => existing_items
+                        .iter()
+                        .find(|e| key_matches(key.as_slice(), e, desired_item)),
+                    None => existing_items.get(i),
+                };
+                if let Some(existing_item) = existing_item {
+                    if rest.is_empty() {
+                        *desired_item = existing_item.clone();
+                    } else {
+                        preserve_at(existing_item, desired_item, rest, field, merge_keys);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn find_removed<'a>(
+    diffs: &mut Vec<Diff<'a>>,
+    path: &mut Vec<Segment<'a>>,
+    merge_keys: &MergeKeys,
+    last_applied: &Value,
+    existing: &'a Value,
+    desired: &'a Value,
+) {
+    match (last_applied, existing, desired) {
+        (Value::Object(last_map), Value::Object(existing_map), Value::Object(desired_map)) => {
+            for (key, existing_val) in existing_map.iter() {
+                let last_val = match last_map.get(key) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                path.push(Segment::Key(key));
+                match desired_map.get(key) {
+                    Some(desired_val) => {
+                        find_removed(diffs, path, merge_keys, last_val, existing_val, desired_val)
+                    }
+                    None => diffs.push(diff(&*path, existing_val, &Value::Null)),
+                }
+                path.pop();
+            }
+        }
+        (Value::Array(last_items), Value::Array(existing_items), Value::Array(desired_items)) => {
+            let field = match path.last() {
+                Some(Segment::Key(key)) => *key,
+                _ => "",
+            };
+            let key = associative_key(merge_keys, field, desired_items);
+            let removed = match key {
+                Some(key) => last_items.iter().any(|last_item| {
+                    !desired_items.iter().any(|d| key_matches(key, d, last_item))
+                        && existing_items
+                            .iter()
+                            .any(|e| key_matches(key, e, last_item))
+                }),
+                None => {
+                    last_items.len() > desired_items.len()
+                        && existing_items.len() > desired_items.len()
+                }
+            };
+            if removed {
+                diffs.push(diff(&*path, existing, desired));
+                return;
+            }
+            // look for values that were removed from the items that are still desired
+            for (i, desired_item) in desired_items.iter().enumerate() {
+                let (last_item, existing_item) = match key {
+                    Some(key) => (
+                        last_items
+                            .iter()
+                            .find(|l| key_matches(key, l, desired_item)),
+                        existing_items
+                            .iter()
+                            .find(|e| key_matches(key, e, desired_item)),
+                    ),
+                    None => (last_items.get(i), existing_items.get(i)),
+                };
+                if let (Some(last_item), Some(existing_item)) = (last_item, existing_item) {
+                    path.push(Segment::Index(i));
+                    find_removed(
+                        diffs,
+                        path,
+                        merge_keys,
+                        last_item,
+                        existing_item,
+                        desired_item,
+                    );
+                    path.pop();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Hints<'k> {
+    merge_keys: &'k MergeKeys,
+    value_formats: &'k ValueFormats,
+}
+
+impl<'k> Hints<'k> {
+    /// Returns the format of the value at the given path, which is registered either for the field itself or for
+    /// the object that contains it
+    fn format_of(&self, path: &[Segment]) -> Option<ValueFormat> {
+        let mut keys = path.iter().rev().map(|segment| match segment {
+            Segment::Key(key) => Some(*key),
+            Segment::Index(_) => None,
+        });
+        let field = keys.next().and_then(|key| key)?;
+        self.value_formats.format_of(field).or_else(|| {
+            keys.next()
+                .and_then(|key| key)
+                .and_then(|parent| self.value_formats.format_of(parent))
+        })
+    }
+}
+
 fn compare<'a>(
     diffs: &mut Vec<Diff<'a>>,
     path: &mut Vec<Segment<'a>>,
+    hints: &Hints,
     superset: &'a Value,
     subset: &'a Value,
 ) {
     match (superset, subset) {
         (Value::Object(ref super_map), Value::Object(ref sub_map)) => {
-            compare_objects(diffs, path, super_map, sub_map);
+            compare_objects(diffs, path, hints, super_map, sub_map);
         }
         (Value::Array(ref super_array), Value::Array(ref sub_array)) => {
-            compare_arrays(diffs, path, super_array, sub_array);
+            compare_arrays(diffs, path, hints, super_array, sub_array);
         }
         (a, b) if a != b => {
-            diffs.push(diff(&*path, a, b));
+            let equivalent = hints
+                .format_of(path.as_slice())
+                .map(|format| values::values_equal(format, a, b))
+                .unwrap_or(false);
+            if !equivalent {
+                diffs.push(diff(&*path, a, b));
+            }
         }
         _ => {}
     }
@@ -130,37 +624,43 @@ fn compare<'a>(
 fn compare_objects<'a>(
     diffs: &mut Vec<Diff<'a>>,
     path: &mut Vec<Segment<'a>>,
+    hints: &Hints,
     existing: &'a JsonObject,
     desired: &'a JsonObject,
 ) {
     for (key, desired_val) in desired.iter() {
-        check_value(diffs, path, existing, key, desired_val);
+        check_value(diffs, path, hints, existing, key, desired_val);
     }
 }
 
 fn compare_arrays<'a>(
     diffs: &mut Vec<Diff<'a>>,
     path: &mut Vec<Segment<'a>>,
+    hints: &Hints,
     existing: &'a Vec<Value>,
     desired: &'a Vec<Value>,
 ) {
-    if is_associative(existing, desired) {
-        compare_associative_arrays(diffs, path, existing, desired);
-    } else {
-        compare_non_associative_arrays(diffs, path, existing, desired);
+    let field = match path.last() {
+        Some(Segment::Key(key)) => *key,
+        _ => "",
+    };
+    match associative_key(hints.merge_keys, field, desired) {
+        Some(key) => compare_associative_arrays(diffs, path, hints, key, existing, desired),
+        None => compare_non_associative_arrays(diffs, path, hints, existing, desired),
     }
 }
 
 fn compare_non_associative_arrays<'a>(
     diffs: &mut Vec<Diff<'a>>,
     path: &mut Vec<Segment<'a>>,
+    hints: &Hints,
     existing: &'a Vec<Value>,
     desired: &'a Vec<Value>,
 ) {
     for (i, desired_item) in desired.iter().enumerate() {
         path.push(Segment::Index(i));
         if existing.len() > i {
-            compare(diffs, path, &existing[i], desired_item);
+            compare(diffs, path, hints, &existing[i], desired_item);
         } else {
             diffs.push(diff(&*path, &Value::Null, desired_item));
         }
@@ -171,22 +671,16 @@ fn compare_non_associative_arrays<'a>(
 fn compare_associative_arrays<'a>(
     diffs: &mut Vec<Diff<'a>>,
     path: &mut Vec<Segment<'a>>,
+    hints: &Hints,
+    key: &[String],
     existing: &'a Vec<Value>,
     desired: &'a Vec<Value>,
 ) {
     for (i, desired_val) in desired.iter().enumerate() {
         path.push(Segment::Index(i));
-        // These are only safe unwraps because we check them in `is_associative`
-        let name = desired_val.get("name").unwrap().as_str().unwrap();
-
-        let existing_item = existing.iter().find(|e| {
-            e.get("name")
-                .and_then(Value::as_str)
-                .map(|item_name| item_name == name)
-                .unwrap_or(false)
-        });
+        let existing_item = existing.iter().find(|e| key_matches(key, e, desired_val));
         if let Some(existing_match) = existing_item {
-            compare(diffs, path, existing_match, desired_val);
+            compare(diffs, path, hints, existing_match, desired_val);
         } else {
             diffs.push(diff(&*path, &Value::Null, desired_val));
         }
@@ -194,17 +688,41 @@ fn compare_associative_arrays<'a>(
     }
 }
 
-fn is_associative(_existing: &[Value], desired: &[Value]) -> bool {
-    desired.iter().all(|v| {
-        v.as_object()
-            .map(|o| o.get("name").map(Value::is_string).unwrap_or(false))
-            .unwrap_or(false)
+/// Returns the key to use for comparing the given array as an associative array, or `None` if it should be compared
+/// by the position of each item. A key may be used if every desired item has a value for its first field.
+fn associative_key<'k>(
+    merge_keys: &'k MergeKeys,
+    field: &str,
+    desired: &[Value],
+) -> Option<&'k [String]> {
+    let has_key = |key: &[String]| {
+        desired.iter().all(|v| {
+            v.as_object()
+                .and_then(|o| o.get(key[0].as_str()))
+                .map(|value| !value.is_null())
+                .unwrap_or(false)
+        })
+    };
+    merge_keys
+        .keys_for(field)
+        .iter()
+        .map(Vec::as_slice)
+        .chain(std::iter::once(NAME_KEY.as_slice()))
+        .find(|key| !key.is_empty() && has_key(key))
+}
+
+/// Items match if every field of the key that's present in the desired item has the same value in the existing item
+fn key_matches(key: &[String], existing: &Value, desired: &Value) -> bool {
+    key.iter().all(|field| match desired.get(field.as_str()) {
+        Some(value) if !value.is_null() => existing.get(field.as_str()) == Some(value),
+        _ => true,
     })
 }
 
 fn check_value<'a>(
     diffs: &mut Vec<Diff<'a>>,
     path: &mut Vec<Segment<'a>>,
+    hints: &Hints,
     existing: &'a JsonObject,
     key: &'a str,
     value: &'a Value,
@@ -213,7 +731,7 @@ fn check_value<'a>(
 
     match existing.get(key) {
         Some(super_val) => {
-            compare(diffs, path, super_val, value);
+            compare(diffs, path, hints, super_val, value);
         }
         None => {
             diffs.push(diff(&*path, &Value::Null, value));
@@ -224,17 +742,23 @@ fn check_value<'a>(
 
 fn diff<'a>(path: &[Segment], existing: &'a Value, desired: &'a Value) -> Diff<'a> {
     let mut p = String::with_capacity(8);
+    let mut pointer = String::with_capacity(8);
     for s in path.iter() {
         p.push('.');
         match s {
-            Segment::Key(ref k) => p.push_str(k),
+            Segment::Key(ref k) => {
+                p.push_str(k);
+                push_pointer_segment(&mut pointer, k);
+            }
             Segment::Index(i) => {
                 write!(p, "{}", i).unwrap();
+                write!(pointer, "/{}", i).unwrap();
             }
         }
     }
     Diff {
         path: p,
+        pointer,
         existing,
         desired,
     }
@@ -273,16 +797,19 @@ mod test {
         let expected = vec![
             Diff {
                 path: ".key1.nested2".to_owned(),
+                pointer: "/key1/nested2".to_owned(),
                 existing: &existing2,
                 desired: &desired2,
             },
             Diff {
                 path: ".key3".to_owned(),
+                pointer: "/key3".to_owned(),
                 existing: &seven,
                 desired: &eight,
             },
             Diff {
                 path: ".newKey".to_owned(),
+                pointer: "/newKey".to_owned(),
                 existing: &Value::Null,
                 desired: &Value::Bool(true),
             },
@@ -332,11 +859,13 @@ mod test {
         let expected = vec![
             Diff {
                 path: ".nonAssociative.0.nonAssociative.2.different".to_owned(),
+                pointer: "/nonAssociative/0/nonAssociative/2/different".to_owned(),
                 existing: &e,
                 desired: &desired_val,
             },
             Diff {
                 path: ".associative.1.value".to_owned(),
+                pointer: "/associative/1/value".to_owned(),
                 existing: &value1existing,
                 desired: &value1desired,
             },
@@ -345,6 +874,366 @@ mod test {
         assert_all_diffs_present(expected, actual);
     }
 
+    #[test]
+    fn arrays_with_registered_merge_keys_are_compared_as_associative() {
+        let existing = json! {{
+            "ports": [
+                {"containerPort": 53, "protocol": "UDP", "hostPort": 1053},
+                {"containerPort": 53, "protocol": "TCP", "hostPort": 2053},
+                {"containerPort": 80, "protocol": "TCP"},
+            ],
+            "volumeMounts": [
+                {"name": "data", "mountPath": "/b", "subPath": "b"},
+                {"name": "data", "mountPath": "/a", "subPath": "a"},
+            ],
+            "tolerations": [
+                {"key": "b", "operator": "Exists", "effect": "NoSchedule"},
+                {"key": "a", "operator": "Exists", "effect": "NoSchedule"},
+            ],
+        }};
+        let desired = json! {{
+            "ports": [
+                {"containerPort": 80},
+                {"containerPort": 53, "protocol": "TCP", "hostPort": 2053},
+                {"containerPort": 53, "protocol": "UDP", "hostPort": 1053},
+            ],
+            "volumeMounts": [
+                {"name": "data", "mountPath": "/a", "subPath": "a"},
+                {"name": "data", "mountPath": "/b", "subPath": "b"},
+            ],
+            "tolerations": [
+                {"key": "a", "operator": "Exists", "effect": "NoSchedule"},
+                {"key": "b", "operator": "Exists", "effect": "NoSchedule"},
+            ],
+        }};
+        let diffs = compare_values(&existing, &desired);
+        assert!(diffs.is_empty(), "expected no diffs, got: {}", diffs);
+    }
+
+    #[test]
+    fn custom_merge_keys_take_precedence_over_name() {
+        let existing = json! {{
+            "rules": [
+                {"name": "x", "host": "b.com", "path": "/b"},
+                {"name": "x", "host": "a.com", "path": "/a"},
+            ],
+        }};
+        let desired = json! {{
+            "rules": [
+                {"name": "x", "host": "a.com", "path": "/a"},
+                {"name": "x", "host": "b.com", "path": "/new"},
+            ],
+        }};
+        let path_b = json!("/b");
+        let path_new = json!("/new");
+        let expected = vec![Diff {
+            path: ".rules.1.path".to_owned(),
+            pointer: "/rules/1/path".to_owned(),
+            existing: &path_b,
+            desired: &path_new,
+        }];
+        let merge_keys = MergeKeys::default().with_key("rules", &["host"]);
+        let actual =
+            compare_values_with(&existing, &desired, &merge_keys, &ValueFormats::default());
+        assert_all_diffs_present(expected, actual);
+
+        // without the key, both desired rules match the first existing rule with the same name
+        assert_eq!(3, compare_values(&existing, &desired).len());
+    }
+
+    #[test]
+    fn values_with_known_formats_are_compared_semantically() {
+        let existing = json! {{
+            "containers": [{
+                "name": "app",
+                "resources": {
+                    "limits": {"cpu": "1", "memory": "1Gi"},
+                    "requests": {"cpu": "250m", "memory": 1073741824},
+                },
+            }],
+            "ports": [{"port": 80, "targetPort": 8080}],
+            "renewBefore": "360h0m0s",
+            "replicas": 1,
+        }};
+        let desired = json! {{
+            "containers": [{
+                "name": "app",
+                "resources": {
+                    "limits": {"cpu": "1000m", "memory": 1073741824},
+                    "requests": {"cpu": "0.25", "memory": "1024Mi"},
+                },
+            }],
+            "ports": [{"port": 80, "targetPort": "8080"}],
+            "renewBefore": "15d",
+            "replicas": "1",
+        }};
+        let value_formats =
+            ValueFormats::default().with_format("renewBefore", ValueFormat::Duration);
+        let diffs = compare_values_with(&existing, &desired, &MergeKeys::default(), &value_formats);
+        // "15d" isn't a valid duration, and replicas don't have a format, so only those are different
+        let pointers = diffs
+            .0
+            .iter()
+            .map(|d| d.pointer.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["/renewBefore", "/replicas"], pointers);
+    }
+
+    #[test]
+    fn merge_patch_includes_only_changed_values_and_whole_arrays() {
+        let existing = json! {{
+            "metadata": {
+                "name": "foo",
+                "labels": { "a": "b" },
+            },
+            "spec": {
+                "replicas": 1,
+                "template": "same",
+                "containers": [
+                    {"name": "c1", "image": "old"},
+                    {"name": "c2", "image": "same"},
+                ]
+            }
+        }};
+        let desired = json! {{
+            "metadata": {
+                "name": "foo",
+                "labels": { "a": "b", "c": "d/e" },
+            },
+            "spec": {
+                "replicas": 2,
+                "template": "same",
+                "containers": [
+                    {"name": "c2", "image": "same"},
+                    {"name": "c1", "image": "new"},
+                ]
+            }
+        }};
+        let diffs = compare_values(&existing, &desired);
+        let expected = json! {{
+            "metadata": {
+                "labels": { "c": "d/e" },
+            },
+            "spec": {
+                "replicas": 2,
+                "containers": [
+                    {"name": "c2", "image": "same"},
+                    {"name": "c1", "image": "new"},
+                ]
+            }
+        }};
+        assert_eq!(expected, diffs.to_merge_patch(&desired));
+    }
+
+    #[test]
+    fn json_patch_adds_changed_values_and_replaces_whole_arrays() {
+        let existing = json! {{
+            "metadata": {
+                "annotations": { "x": "y" },
+            },
+            "spec": {
+                "ports": [ {"name": "a", "port": 80} ],
+            }
+        }};
+        let desired = json! {{
+            "metadata": {
+                "annotations": { "x": "y", "foo/bar": "baz" },
+            },
+            "spec": {
+                "ports": [ {"name": "a", "port": 8080}, {"name": "b", "port": 90} ],
+            }
+        }};
+        let diffs = compare_values(&existing, &desired);
+        let actual = diffs.to_json_patch(&desired);
+        let ops = actual.as_array().unwrap();
+        assert_eq!(2, ops.len());
+        assert!(ops.contains(&json!({
+            "op": "add",
+            "path": "/metadata/annotations/foo~1bar",
+            "value": "baz",
+        })));
+        assert!(ops.contains(&json!({
+            "op": "add",
+            "path": "/spec/ports",
+            "value": [ {"name": "a", "port": 8080}, {"name": "b", "port": 90} ],
+        })));
+    }
+
+    #[test]
+    fn values_removed_since_last_applied_are_removed_by_patches() {
+        let last_applied = json! {{
+            "metadata": {
+                "labels": { "a": "b", "c": "d" },
+            },
+            "spec": {
+                "containers": [ {"name": "c1"}, {"name": "c2"} ],
+                "replicas": 1,
+            }
+        }};
+        let existing = json! {{
+            "metadata": {
+                "labels": { "a": "b", "c": "d", "other": "label" },
+            },
+            "spec": {
+                "containers": [ {"name": "c1"}, {"name": "c2"} ],
+                "replicas": 1,
+                "defaulted": true,
+            }
+        }};
+        let desired = json! {{
+            "metadata": {
+                "labels": { "a": "b" },
+            },
+            "spec": {
+                "containers": [ {"name": "c2"} ],
+                "replicas": 1,
+            }
+        }};
+        let diffs = removed_values_with(&last_applied, &existing, &desired, &MergeKeys::default());
+        assert_eq!(2, diffs.len());
+
+        let expected = json! {{
+            "metadata": {
+                "labels": { "c": null },
+            },
+            "spec": {
+                "containers": [ {"name": "c2"} ],
+            }
+        }};
+        assert_eq!(expected, diffs.to_merge_patch(&desired));
+
+        let actual = diffs.to_json_patch(&desired);
+        let ops = actual.as_array().unwrap();
+        assert_eq!(2, ops.len());
+        assert!(ops.contains(&json!({
+            "op": "remove",
+            "path": "/metadata/labels/c",
+        })));
+        assert!(ops.contains(&json!({
+            "op": "add",
+            "path": "/spec/containers",
+            "value": [ {"name": "c2"} ],
+        })));
+    }
+
+    #[test]
+    fn ignored_paths_are_removed_from_diffs() {
+        let existing = json! {{
+            "metadata": {
+                "annotations": { "a": "b" },
+            },
+            "spec": {
+                "replicas": 3,
+                "containers": [ {"name": "c1", "image": "old"} ],
+            }
+        }};
+        let desired = json! {{
+            "metadata": {
+                "annotations": { "a": "c", "sidecar.io/inject": "true" },
+            },
+            "spec": {
+                "replicas": 1,
+                "containers": [ {"name": "c1", "image": "new"} ],
+            }
+        }};
+        let mut diffs = compare_values(&existing, &desired);
+        assert_eq!(4, diffs.len());
+        diffs.remove_ignored(&[
+            "/spec/replicas".to_owned(),
+            "/metadata/annotations/sidecar.io~1*".to_owned(),
+            "/spec/containers/*/image".to_owned(),
+        ]);
+        let expected = vec![Diff {
+            path: ".metadata.annotations.a".to_owned(),
+            pointer: "/metadata/annotations/a".to_owned(),
+            existing: &existing["metadata"]["annotations"]["a"],
+            desired: &desired["metadata"]["annotations"]["a"],
+        }];
+        assert_all_diffs_present(expected, diffs);
+
+        let mut diffs = compare_values(&existing, &desired);
+        diffs.remove_ignored(&["/spec".to_owned(), "/metadata/*".to_owned()]);
+        assert!(diffs.is_empty());
+    }
+
+    #[test]
+    fn existing_values_at_ignored_paths_are_preserved_in_desired() {
+        let existing = json! {{
+            "metadata": {
+                "annotations": { "a": "b", "sidecar.io/inject": "true" },
+            },
+            "spec": {
+                "replicas": 3,
+                "containers": [
+                    {"name": "c2", "image": "injected"},
+                    {"name": "c1", "image": "old"},
+                ],
+            }
+        }};
+        let mut desired = json! {{
+            "metadata": {},
+            "spec": {
+                "paused": true,
+                "containers": [ {"name": "c1", "image": "new", "args": ["x"]} ],
+            }
+        }};
+        preserve_ignored(
+            &existing,
+            &mut desired,
+            &[
+                "/spec/replicas".to_owned(),
+                "/spec/paused".to_owned(),
+                "/metadata/annotations/sidecar.io~1*".to_owned(),
+                "/spec/containers/*/image".to_owned(),
+            ],
+            &MergeKeys::default(),
+        );
+        let expected = json! {{
+            "metadata": {
+                "annotations": { "sidecar.io/inject": "true" },
+            },
+            "spec": {
+                "replicas": 3,
+                "containers": [ {"name": "c1", "image": "old", "args": ["x"]} ],
+            }
+        }};
+        assert_eq!(expected, desired);
+    }
+
+    #[test]
+    fn values_at_ignored_paths_are_removed_from_desired() {
+        let mut desired = json! {{
+            "metadata": {
+                "annotations": { "a": "b", "sidecar.io/inject": "true" },
+            },
+            "spec": {
+                "replicas": 3,
+                "containers": [
+                    {"name": "c1", "image": "one"},
+                    {"name": "c2", "image": "two"},
+                ],
+            }
+        }};
+        remove_ignored_values(
+            &mut desired,
+            &[
+                "/spec/replicas".to_owned(),
+                "/spec/paused".to_owned(),
+                "/metadata/annotations/sidecar.io~1*".to_owned(),
+                "/spec/containers/*/image".to_owned(),
+            ],
+        );
+        let expected = json! {{
+            "metadata": {
+                "annotations": { "a": "b" },
+            },
+            "spec": {
+                "containers": [ {"name": "c1"}, {"name": "c2"} ],
+            }
+        }};
+        assert_eq!(expected, desired);
+    }
+
     fn assert_all_diffs_present(expected: Vec<Diff>, mut actual: Diffs) {
         for expected_diff in expected.iter() {
             if !actual.0.contains(expected_diff) {
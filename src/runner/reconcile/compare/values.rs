@@ -0,0 +1,192 @@
+//! Semantic comparison of scalar values that the api server may normalize to a different representation than the
+//! one that was sent. Each value is parsed into a canonical form, and values that can't be parsed are only equal if
+//! they're identical.
+use crate::config::ValueFormat;
+
+use serde_json::Value;
+
+const NANOS_PER_UNIT: i128 = 1_000_000_000;
+
+/// Returns true if the two values represent the same value in the given format
+pub(crate) fn values_equal(format: ValueFormat, existing: &Value, desired: &Value) -> bool {
+    if existing == desired {
+        return true;
+    }
+    match format {
+        ValueFormat::Quantity => parsed_equal(existing, desired, parse_quantity),
+        ValueFormat::Duration => parsed_equal(existing, desired, parse_duration),
+        ValueFormat::IntOrString => match (as_string(existing), as_string(desired)) {
+            (Some(e), Some(d)) => e == d,
+            _ => false,
+        },
+    }
+}
+
+fn parsed_equal(existing: &Value, desired: &Value, parse: fn(&str) -> Option<i128>) -> bool {
+    let existing = as_string(existing).and_then(|s| parse(s.as_str()));
+    let desired = as_string(desired).and_then(|s| parse(s.as_str()));
+    existing.is_some() && existing == desired
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Parses a Kubernetes resource quantity, such as `500m`, `1.5Gi`, or `1e3`, into billionths of a unit. Values with
+/// more precision than that are rounded up, which is what the api server does.
+pub(crate) fn parse_quantity(quantity: &str) -> Option<i128> {
+    let quantity = quantity.trim();
+    let (negative, unsigned) = split_sign(quantity);
+    let number_len = unsigned
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(unsigned.len());
+    let (number, suffix) = unsigned.split_at(number_len);
+    let (mantissa, scale) = parse_decimal(number)?;
+
+    let (binary_exponent, decimal_exponent): (u32, i32) = match suffix {
+        "" => (0, 0),
+        "n" => (0, -9),
+        "u" => (0, -6),
+        "m" => (0, -3),
+        "k" => (0, 3),
+        "M" => (0, 6),
+        "G" => (0, 9),
+        "T" => (0, 12),
+        "P" => (0, 15),
+        "E" => (0, 18),
+        "Ki" => (10, 0),
+        "Mi" => (20, 0),
+        "Gi" => (30, 0),
+        "Ti" => (40, 0),
+        "Pi" => (50, 0),
+        "Ei" => (60, 0),
+        exponent if exponent.starts_with('e') || exponent.starts_with('E') => {
+            (0, exponent[1..].parse::<i32>().ok()?)
+        }
+        _ => return None,
+    };
+
+    let numerator = mantissa
+        .checked_mul(NANOS_PER_UNIT)?
+        .checked_mul(1i128.checked_shl(binary_exponent)?)?;
+    let exponent = decimal_exponent - scale as i32;
+    let nanos = if exponent >= 0 {
+        numerator.checked_mul(10i128.checked_pow(exponent as u32)?)?
+    } else {
+        let divisor = 10i128.checked_pow((-exponent) as u32)?;
+        let rounded_up = if numerator % divisor == 0 { 0 } else { 1 };
+        numerator / divisor + rounded_up
+    };
+    Some(if negative { -nanos } else { nanos })
+}
+
+/// Parses a Go duration string, such as `1h30m` or `1.5s`, into nanoseconds
+pub(crate) fn parse_duration(duration: &str) -> Option<i128> {
+    let (negative, mut remaining) = split_sign(duration.trim());
+    if remaining == "0" {
+        return Some(0);
+    }
+    if remaining.is_empty() {
+        return None;
+    }
+    let mut total: i128 = 0;
+    while !remaining.is_empty() {
+        let number_len = remaining
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(remaining.len());
+        let (number, rest) = remaining.split_at(number_len);
+        let (mantissa, scale) = parse_decimal(number)?;
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let (unit, rest) = rest.split_at(unit_len);
+        let unit_nanos: i128 = match unit {
+            "ns" => 1,
+            "us" | "µs" | "μs" => 1_000,
+            "ms" => 1_000_000,
+            "s" => NANOS_PER_UNIT,
+            "m" => 60 * NANOS_PER_UNIT,
+            "h" => 3600 * NANOS_PER_UNIT,
+            _ => return None,
+        };
+        let divisor = 10i128.checked_pow(scale)?;
+        total = total.checked_add(mantissa.checked_mul(unit_nanos)? / divisor)?;
+        remaining = rest;
+    }
+    Some(if negative { -total } else { total })
+}
+
+fn split_sign(value: &str) -> (bool, &str) {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some('-') => (true, chars.as_str()),
+        Some('+') => (false, chars.as_str()),
+        _ => (false, value),
+    }
+}
+
+/// Parses a decimal number into its digits and the number of digits after the decimal point
+fn parse_decimal(number: &str) -> Option<(i128, u32)> {
+    let mut parts = number.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let mut mantissa: i128 = 0;
+    for c in whole.chars().chain(fraction.chars()) {
+        let digit = c.to_digit(10)?;
+        mantissa = mantissa.checked_mul(10)?.checked_add(digit as i128)?;
+    }
+    Some((mantissa, fraction.len() as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn quantities_are_compared_by_value() {
+        let equal = |a: Value, b: Value| values_equal(ValueFormat::Quantity, &a, &b);
+        assert!(equal(json!("1"), json!("1000m")));
+        assert!(equal(json!("1Gi"), json!(1073741824)));
+        assert!(equal(json!("1.5Gi"), json!("1536Mi")));
+        assert!(equal(json!("1e3"), json!("1k")));
+        assert!(equal(json!("0.1"), json!("100m")));
+        assert!(!equal(json!("1"), json!("1001m")));
+        assert!(!equal(json!("1Gi"), json!("1G")));
+        assert!(!equal(json!("lots"), json!("1")));
+    }
+
+    #[test]
+    fn quantities_with_excess_precision_are_rounded_up() {
+        assert_eq!(Some(1), parse_quantity("0.1n"));
+        assert_eq!(Some(-500_000_000), parse_quantity("-500m"));
+        assert_eq!(None, parse_quantity("1Xi"));
+        assert_eq!(None, parse_quantity(""));
+    }
+
+    #[test]
+    fn durations_are_compared_by_value() {
+        let equal = |a: Value, b: Value| values_equal(ValueFormat::Duration, &a, &b);
+        assert!(equal(json!("1h0m0s"), json!("60m")));
+        assert!(equal(json!("1.5s"), json!("1500ms")));
+        assert!(equal(json!("2160h"), json!("90h2070h")));
+        assert!(equal(json!("0s"), json!("0")));
+        assert!(!equal(json!("1h"), json!("1m")));
+        assert!(!equal(json!("1"), json!("1s")));
+    }
+
+    #[test]
+    fn int_or_string_values_are_compared_as_strings() {
+        let equal = |a: Value, b: Value| values_equal(ValueFormat::IntOrString, &a, &b);
+        assert!(equal(json!(8080), json!("8080")));
+        assert!(equal(json!("25%"), json!("25%")));
+        assert!(!equal(json!("http"), json!(80)));
+    }
+}
@@ -1,5 +1,9 @@
-use super::{does_finalizer_exist, update_status_if_different, SyncHandler, UpdateError};
-use crate::handler::{FinalizeResponse, Handler, SyncRequest};
+use super::{
+    can_own, does_finalizer_exist, invoke_handler, update_status_if_different, SyncHandler,
+    UpdateError,
+};
+use crate::config::RetentionPolicy;
+use crate::handler::{AsyncHandler, FinalizeResponse, SyncRequest};
 use crate::resource::K8sResource;
 use crate::runner::client::{Client, Patch};
 use crate::runner::informer::{EventType, ResourceMessage};
@@ -8,6 +12,9 @@ use crate::runner::{duration_to_millis, RuntimeConfig};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+/// How long to wait before checking again whether children that aren't garbage collected have been deleted
+const CHILD_CLEANUP_RETRY: Duration = Duration::from_secs(2);
+
 pub(crate) async fn handle_finalize(handler: SyncHandler) {
     let SyncHandler {
         mut sender,
@@ -22,7 +29,8 @@ pub(crate) async fn handle_finalize(handler: SyncHandler) {
     let parent_id_ref = parent_id.as_id_ref();
     let parent_type = runtime_config.parent_type;
 
-    let result = get_finalize_result(request, handler, client, &*runtime_config).await;
+    let request = Arc::new(request);
+    let result = get_finalize_result(request.clone(), handler, client, &*runtime_config).await;
     let update_result = match result {
         Ok(retry) => {
             log::debug!(
@@ -34,6 +42,10 @@ pub(crate) async fn handle_finalize(handler: SyncHandler) {
         Err(err) => {
             runtime_config.metrics.parent_sync_error(&parent_id_ref);
             log::error!("Failed to finalize parent: {}, err: {}", parent_id, err);
+            runtime_config
+                .events
+                .warning(&request.parent, "FinalizeFailed", err.to_string())
+                .await;
             Err(())
         }
     };
@@ -49,8 +61,8 @@ pub(crate) async fn handle_finalize(handler: SyncHandler) {
 }
 
 async fn get_finalize_result(
-    request: SyncRequest,
-    handler: Arc<dyn Handler>,
+    request: Arc<SyncRequest>,
+    handler: Arc<dyn AsyncHandler>,
     client: Client,
     runtime_config: &RuntimeConfig,
 ) -> Result<Option<Duration>, UpdateError> {
@@ -59,24 +71,15 @@ async fn get_finalize_result(
         return Ok(None);
     }
 
-    let (req, finalize_result) = tokio::task::spawn_blocking(move || {
-        let start_time = Instant::now();
-        let result = handler
-            .finalize(&request)
-            .map_err(UpdateError::HandlerError);
-        {
-            log::debug!(
-                "finished invoking handler for parent: {} in {}ms",
-                request.parent.get_object_id(),
-                duration_to_millis(start_time.elapsed())
-            );
-        }
-        (request, result)
-    })
-    .await?;
+    let start_time = Instant::now();
+    let finalize_result = invoke_handler(handler.finalize(request.clone())).await;
+    log::debug!(
+        "finished invoking handler for parent: {} in {}ms",
+        request.parent.get_object_id(),
+        duration_to_millis(start_time.elapsed())
+    );
     let FinalizeResponse { retry, status } = finalize_result?;
 
-    let request: SyncRequest = req;
     let parent_id = request.parent.get_object_id();
 
     if let Some(delay) = retry {
@@ -91,12 +94,118 @@ async fn get_finalize_result(
             "handler response indicates that parent: {} has been finalized",
             parent_id
         );
+        release_retained_children(&client, runtime_config, &request).await?;
+        let remaining = delete_untracked_children(&client, runtime_config, &request).await?;
+        if remaining > 0 {
+            log::info!(
+                "Waiting for {} children of parent: {} to be deleted before removing the finalizer",
+                remaining,
+                parent_id
+            );
+            return Ok(Some(CHILD_CLEANUP_RETRY));
+        }
         remove_finalizer(&client, runtime_config, &request.parent).await?;
+        runtime_config
+            .events
+            .normal(
+                &request.parent,
+                "RemovedFinalizer",
+                format!("Removed finalizer '{}'", runtime_config.operator_name),
+            )
+            .await;
     }
 
     Ok(retry)
 }
 
+/// Removes the ownerReference and tracking labels from each child that's retained after the parent is deleted, so
+/// that it won't be garbage collected along with the parent
+async fn release_retained_children(
+    client: &Client,
+    runtime_config: &RuntimeConfig,
+    request: &SyncRequest,
+) -> Result<(), UpdateError> {
+    let parent_id = request.parent.get_object_id();
+    let labels = [
+        runtime_config.correlation_label_name.as_str(),
+        runtime_config.controller_label_name.as_str(),
+    ];
+    for child in request.children.iter() {
+        let child_config = runtime_config
+            .get_child_config(&child.get_type_ref())
+            .expect("No configuration found for existing child type");
+        if child_config.retention_policy != RetentionPolicy::Retain
+            || child.is_deletion_timestamp_set()
+        {
+            continue;
+        }
+        let child_id = child.get_object_id();
+        log::info!(
+            "Retaining child: {} of parent: {} after the parent is deleted",
+            child_id,
+            parent_id
+        );
+        let patch = Patch::release_child(child, request.parent.uid(), &labels);
+        client
+            .patch_resource(child_config.child_type, &child_id, &patch)
+            .await?;
+        runtime_config
+            .events
+            .normal(
+                &request.parent,
+                "RetainedChild",
+                format!("Retained {} {}", child_config.child_type.kind, child_id),
+            )
+            .await;
+    }
+    Ok(())
+}
+
+/// Deletes the children that can't be garbage collected by Kubernetes, because they're outside the parent's
+/// namespace. Returns the number of those children that still exist.
+async fn delete_untracked_children(
+    client: &Client,
+    runtime_config: &RuntimeConfig,
+    request: &SyncRequest,
+) -> Result<usize, UpdateError> {
+    let parent_id = request.parent.get_object_id();
+    let mut remaining = 0;
+    for child in request.children.iter() {
+        let child_id = child.get_object_id();
+        let child_config = runtime_config
+            .get_child_config(&child.get_type_ref())
+            .expect("No configuration found for existing child type");
+        if can_own(&parent_id, &child_id)
+            || child_config.retention_policy == RetentionPolicy::Retain
+        {
+            continue;
+        }
+        remaining += 1;
+        if child.is_deletion_timestamp_set() {
+            continue;
+        }
+        let child_type = child_config.child_type;
+        log::info!(
+            "Deleting child: {} of parent: {} before removing the finalizer",
+            child_id,
+            parent_id
+        );
+        let options = child_config.delete_options(child.uid());
+        client
+            .delete_resource(child_type, &child_id, &options)
+            .await?;
+        runtime_config
+            .events
+            .normal(
+                &request.parent,
+                "DeletedChild",
+                format!("Deleted {} {}", child_type.kind, child_id),
+            )
+            .await;
+    }
+    Ok(remaining)
+}
+
 async fn remove_finalizer<'a>(
     client: &Client,
     runtime_config: &RuntimeConfig,
@@ -108,3 +217,94 @@ async fn remove_finalizer<'a>(
     client.patch_resource(k8s_type, &id, &patch).await?;
     Ok(())
 }
+
+#[cfg(all(test, feature = "testkit"))]
+mod test {
+    use crate::config::{ChildConfig, RetentionPolicy};
+    use crate::handler::SyncRequest;
+    use crate::k8s_types::core::v1::{ConfigMap, Namespace, PersistentVolumeClaim, Secret};
+    use crate::resource::ObjectIdRef;
+    use crate::runner::testkit::fixture::*;
+    use serde_json::json;
+
+    #[test]
+    fn cluster_scoped_children_are_tracked_by_labels_and_deleted_by_finalizer() {
+        let operator_config =
+            operator_config().with_child(Namespace, ChildConfig::replace().cluster_scoped());
+        let handler = |req: &SyncRequest| {
+            let name = format!("{}-{}", req.parent.namespace().unwrap(), req.parent.name());
+            Ok(response(vec![v1_resource("Namespace", None, &name)]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        let child_id = ObjectIdRef::new("", "ns-parent");
+        testkit.assert_resource_exists_eventually(Namespace, &child_id, TIMEOUT);
+        let parent = get_resource(&mut testkit, PARENT_TYPE, "ns", "parent").unwrap();
+        let child = get_resource(&mut testkit, Namespace, "", "ns-parent").unwrap();
+        assert_eq!(
+            parent["metadata"]["uid"],
+            child["metadata"]["labels"]["app.kubernetes.io/instance"]
+        );
+        assert!(child.pointer("/metadata/ownerReferences").is_none());
+
+        testkit.delete_parent(&ObjectIdRef::new("ns", "parent"), TIMEOUT);
+        testkit.assert_resource_deleted_eventually(Namespace, &child_id, TIMEOUT);
+    }
+
+    #[test]
+    fn children_in_other_namespaces_are_deleted_by_finalizer() {
+        let operator_config =
+            operator_config().with_child(Secret, ChildConfig::replace().any_namespace());
+        let handler = |req: &SyncRequest| {
+            let shared = v1_resource("Secret", Some("shared"), req.parent.name());
+            let local = v1_resource("Secret", req.parent.namespace(), "local");
+            Ok(response(vec![shared, local]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("tenant-a", "a"), TIMEOUT);
+        let shared_id = ObjectIdRef::new("shared", "a");
+        let local_id = ObjectIdRef::new("tenant-a", "local");
+        testkit.assert_resource_exists_eventually(Secret, &shared_id, TIMEOUT);
+        testkit.assert_resource_exists_eventually(Secret, &local_id, TIMEOUT);
+        let shared = get_resource(&mut testkit, Secret, "shared", "a").unwrap();
+        assert!(shared.pointer("/metadata/ownerReferences").is_none());
+        let local = get_resource(&mut testkit, Secret, "tenant-a", "local").unwrap();
+        assert!(local.pointer("/metadata/ownerReferences/0").is_some());
+
+        testkit.delete_parent(&ObjectIdRef::new("tenant-a", "a"), TIMEOUT);
+        testkit.assert_resource_deleted_eventually(Secret, &shared_id, TIMEOUT);
+        testkit.assert_resource_deleted_eventually(Secret, &local_id, TIMEOUT);
+    }
+
+    #[test]
+    fn retained_children_are_released_when_parent_is_deleted() {
+        let operator_config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace())
+            .with_child(
+                PersistentVolumeClaim,
+                ChildConfig::replace().retention_policy(RetentionPolicy::Retain),
+            );
+        let handler = |req: &SyncRequest| {
+            let config_map = v1_resource("ConfigMap", req.parent.namespace(), "config");
+            let mut claim = v1_resource("PersistentVolumeClaim", req.parent.namespace(), "data");
+            claim["metadata"]["labels"] = json!({"app": "test"});
+            Ok(response(vec![config_map, claim]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        let config_id = ObjectIdRef::new("ns", "config");
+        let claim_id = ObjectIdRef::new("ns", "data");
+        testkit.assert_resource_exists_eventually(ConfigMap, &config_id, TIMEOUT);
+        testkit.assert_resource_exists_eventually(PersistentVolumeClaim, &claim_id, TIMEOUT);
+
+        testkit.delete_parent(&ObjectIdRef::new("ns", "parent"), TIMEOUT);
+        testkit.assert_resource_deleted_eventually(ConfigMap, &config_id, TIMEOUT);
+        let claim = get_resource(&mut testkit, PersistentVolumeClaim, "ns", "data")
+            .expect("retained child was deleted");
+        assert_eq!(json!({"app": "test"}), claim["metadata"]["labels"]);
+        assert_eq!(json!([]), claim["metadata"]["ownerReferences"]);
+    }
+}
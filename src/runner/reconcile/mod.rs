@@ -2,23 +2,26 @@ pub(crate) mod compare;
 mod finalize;
 mod sync;
 
-use crate::handler::{Handler, SyncRequest};
-use crate::resource::{InvalidResourceError, K8sResource};
+use crate::handler::{AsyncHandler, HandlerPanic, SyncRequest};
+use crate::resource::{InvalidResourceError, K8sResource, ObjectIdRef};
 use crate::runner::client::{self, Client};
 use crate::runner::informer::ResourceMessage;
 use crate::runner::RuntimeConfig;
 use anyhow::Error;
 
+use futures::FutureExt;
 use serde_json::Value;
 use tokio::sync::mpsc::Sender;
 
 use std::fmt::{self, Display};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 
 pub(crate) struct SyncHandler {
     pub sender: Sender<ResourceMessage>,
     pub request: SyncRequest,
-    pub handler: Arc<dyn Handler>,
+    pub handler: Arc<dyn AsyncHandler>,
     pub client: Client,
     pub runtime_config: Arc<RuntimeConfig>,
     pub parent_index_key: String,
@@ -49,7 +52,8 @@ pub enum UpdateError {
     InvalidHandlerResponse(InvalidResourceError),
     UnknownChildType(String, String),
     HandlerError(Error),
-    TaskCancelled,
+    /// A child already exists, but can't be managed by the parent. The last field says why
+    ChildConflict(&'static str, String, &'static str),
 }
 
 impl Display for UpdateError {
@@ -65,19 +69,23 @@ impl Display for UpdateError {
                 api_version, kind
             ),
             UpdateError::HandlerError(err) => write!(f, "Handler error: {}", err),
-            UpdateError::TaskCancelled => write!(f, "Task was cancelled"),
+            UpdateError::ChildConflict(kind, id, reason) => {
+                write!(f, "{} {} already exists and {}", kind, id, reason)
+            }
         }
     }
 }
 
-impl From<tokio::task::JoinError> for UpdateError {
-    fn from(err: tokio::task::JoinError) -> UpdateError {
-        if err.is_cancelled() {
-            UpdateError::TaskCancelled
-        } else {
-            UpdateError::HandlerError(anyhow::Error::from(HandlerPanic))
-        }
-    }
+/// Awaits a future returned by the handler. A panic in the handler is returned as a `HandlerError`, the same as for
+/// synchronous handlers, so that it doesn't take down the task that's responsible for reporting the sync result.
+async fn invoke_handler<T>(
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, UpdateError> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|_| Err(Error::new(HandlerPanic)))
+        .map_err(UpdateError::HandlerError)
 }
 
 impl From<client::Error> for UpdateError {
@@ -92,16 +100,6 @@ impl From<InvalidResourceError> for UpdateError {
     }
 }
 
-#[derive(Debug)]
-struct HandlerPanic;
-impl std::error::Error for HandlerPanic {}
-
-impl Display for HandlerPanic {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("Handler paniced")
-    }
-}
-
 pub(crate) async fn update_status_if_different(
     existing_parent: &K8sResource,
     client: &Client,
@@ -159,6 +157,15 @@ pub(crate) async fn update_status_if_different(
     Ok(())
 }
 
+/// Kubernetes only allows a namespaced owner to own resources in its own namespace, so children anywhere else must
+/// be tracked using only the labels, and can't be garbage collected
+fn can_own(parent_id: &ObjectIdRef<'_>, child_id: &ObjectIdRef<'_>) -> bool {
+    match parent_id.namespace() {
+        Some(ns) => child_id.namespace() == Some(ns),
+        None => true,
+    }
+}
+
 fn does_finalizer_exist(resource: &Value, runtime_config: &RuntimeConfig) -> bool {
     let finalizer_name = runtime_config.operator_name.as_str();
     resource
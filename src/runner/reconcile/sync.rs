@@ -1,11 +1,16 @@
-use crate::config::UpdateStrategy;
-use crate::handler::{Handler, SyncRequest, SyncResponse};
-use crate::resource::{InvalidResourceError, JsonObject, K8sResource, ObjectIdRef, ResourceJson};
-use crate::runner::client::{self, Client};
+use crate::config::{AdoptionPolicy, ChildScope, MergeStrategy, UpdateStrategy};
+use crate::handler::{AsyncHandler, SyncRequest, SyncResponse};
+use crate::resource::{
+    InvalidResourceError, JsonObject, K8sResource, ObjectId, ObjectIdRef, ResourceJson,
+};
+use crate::runner::client::{self, Client, Patch};
 use crate::runner::informer::{EventType, ResourceMessage};
-use crate::runner::reconcile::compare::compare_values;
+use crate::runner::reconcile::compare::{
+    compare_values_with, preserve_ignored, remove_ignored_values, removed_values_with, Diffs,
+};
 use crate::runner::reconcile::{
-    does_finalizer_exist, update_status_if_different, SyncHandler, UpdateError,
+    can_own, does_finalizer_exist, invoke_handler, update_status_if_different, SyncHandler,
+    UpdateError,
 };
 use crate::runner::resource_map::IdSet;
 use crate::runner::{duration_to_millis, ChildRuntimeConfig, RuntimeConfig};
@@ -15,6 +20,10 @@ use serde_json::{json, Value};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+/// The api server limits the total size of a resource's annotations to 256KiB, so the last applied state is only
+/// recorded if it leaves plenty of room for any other annotations
+const MAX_LAST_APPLIED_SIZE: usize = 128 * 1024;
+
 pub(crate) async fn handle_sync(handler: SyncHandler) {
     let SyncHandler {
         mut sender,
@@ -28,7 +37,15 @@ pub(crate) async fn handle_sync(handler: SyncHandler) {
     let parent_id_ref = parent_id.as_id_ref();
 
     let start_time = Instant::now();
-    let result = private_handle_sync(start_time, request, handler, client, &*runtime_config).await;
+    let request = Arc::new(request);
+    let result = private_handle_sync(
+        start_time,
+        request.clone(),
+        handler,
+        client,
+        &*runtime_config,
+    )
+    .await;
 
     let update_result = match result {
         Ok(duration) => {
@@ -38,6 +55,10 @@ pub(crate) async fn handle_sync(handler: SyncHandler) {
         Err(err) => {
             runtime_config.metrics.parent_sync_error(&parent_id_ref);
             log::error!("Error while syncing parent: {}: {:?}", parent_id, err);
+            runtime_config
+                .events
+                .warning(&request.parent, "SyncFailed", err.to_string())
+                .await;
             Err(())
         }
     };
@@ -58,8 +79,8 @@ pub(crate) async fn handle_sync(handler: SyncHandler) {
 /// status would fail due to the conflicting resource version, at least until we observe that version
 async fn private_handle_sync(
     start_time: Instant,
-    request: SyncRequest,
-    handler: Arc<dyn Handler>,
+    request: Arc<SyncRequest>,
+    handler: Arc<dyn AsyncHandler>,
     client: Client,
     runtime_config: &RuntimeConfig,
 ) -> Result<Option<Duration>, UpdateError> {
@@ -73,29 +94,31 @@ async fn private_handle_sync(
             request.parent.get_object_id(),
             runtime_config.operator_name
         );
+        runtime_config
+            .events
+            .normal(
+                &request.parent,
+                "AddedFinalizer",
+                format!("Added finalizer '{}'", runtime_config.operator_name),
+            )
+            .await;
         Ok(Some(Duration::from_secs(0)))
     } else {
-        let (request, result) = {
-            tokio::task::spawn_blocking(move || {
-                let result = handler.sync(&request);
-                log::debug!(
-                    "finished invoking handler for parent: {} in {}ms",
-                    request.parent.get_object_id(),
-                    duration_to_millis(start_time.elapsed())
-                );
-                (request, result)
-            })
-            .await?
-        };
-        let response = result.map_err(UpdateError::HandlerError)?;
+        let result = invoke_handler(handler.sync(request.clone())).await;
+        log::debug!(
+            "finished invoking handler for parent: {} in {}ms",
+            request.parent.get_object_id(),
+            duration_to_millis(start_time.elapsed())
+        );
+        let response = result?;
         let resync = response.resync;
-        update_all(request, response, client, runtime_config).await?;
+        update_all(&request, response, client, runtime_config).await?;
         Ok(resync)
     }
 }
 
 async fn update_all(
-    request: SyncRequest,
+    request: &SyncRequest,
     handler_response: SyncResponse,
     client: Client,
     runtime_config: &RuntimeConfig,
@@ -111,7 +134,7 @@ async fn update_all(
         parent_id,
         duration_to_millis(start_time.elapsed())
     );
-    let child_ids = update_children(&client, runtime_config, &request, children).await?;
+    let child_ids = update_children(&client, runtime_config, request, children).await?;
     log::debug!(
         "Successfully updated all {} children of parent: {} in {}ms",
         child_ids.len(),
@@ -120,7 +143,7 @@ async fn update_all(
     );
 
     // now that all the child updates have completed successfully, we'll delete any children that are no longer desired
-    delete_undesired_children(&client, runtime_config, &child_ids, &request).await?;
+    delete_undesired_children(&client, runtime_config, &child_ids, request).await?;
     Ok(())
 }
 
@@ -147,10 +170,22 @@ async fn delete_undesired_children(
         if !desired_children.contains(&child_id) && !existing_child.is_deletion_timestamp_set() {
             log::info!("Need to delete child: {} of parent: {} because it was not included in the handler response",
                     child_id, sync_request.parent.get_object_id());
-            let child_type = runtime_config
-                .type_for(&existing_child.get_type_ref())
+            let child_config = runtime_config
+                .get_child_config(&existing_child.get_type_ref())
                 .expect("No configuration found for existing child type");
-            client.delete_resource(child_type, &child_id).await?;
+            let child_type = child_config.child_type;
+            let options = child_config.delete_options(existing_child.uid());
+            client
+                .delete_resource(child_type, &child_id, &options)
+                .await?;
+            runtime_config
+                .events
+                .normal(
+                    &sync_request.parent,
+                    "DeletedChild",
+                    format!("Deleted {} {}", child_type.kind, child_id),
+                )
+                .await;
         }
     }
     Ok(())
@@ -171,26 +206,6 @@ async fn update_children(
             .ok_or_else(|| InvalidResourceError::new("missing name", child.clone()))?
             .to_owned();
 
-        // ensure that the child has the same namespace as the parent. This is a deliberate constraint that
-        // we place on users of this library, as having non-namespaced children of namespaced parents would
-        // add considerable complexity.
-        let valid_namespaces = match (parent_id.namespace(), child_id.namespace()) {
-            (None, None) => true,
-            (None, Some(_)) => true,
-            (Some(p), Some(c)) => p == c,
-            (Some(_), None) => false,
-        };
-
-        if !valid_namespaces {
-            log::error!(
-                "Child {} is not in the same namespace as parent: {}",
-                child_id,
-                parent_id
-            );
-            const MESSAGE: &str = "Child namespace does not match the namespace of the parent";
-            return Err(InvalidResourceError::new(MESSAGE, child.clone()).into());
-        }
-
         let child_config: &ChildRuntimeConfig = {
             let child_type_ref = child.get_type_ref().ok_or_else(|| {
                 InvalidResourceError::new("missing either apiVersion or kind", child.clone())
@@ -206,18 +221,80 @@ async fn update_children(
                     )
                 })?
         };
+        // ensure that the child is in a namespace that's allowed by the scope of its type. Children outside of the
+        // parent's namespace can't be garbage collected by Kubernetes, so they must be explicitly configured.
+        let valid_namespaces = match (
+            child_config.scope,
+            parent_id.namespace(),
+            child_id.namespace(),
+        ) {
+            (ChildScope::Cluster, _, child_ns) => child_ns.is_none(),
+            (ChildScope::AnyNamespace, _, child_ns) => child_ns.is_some(),
+            (ChildScope::SameNamespace, None, _) => true,
+            (ChildScope::SameNamespace, Some(p), Some(c)) => p == c,
+            (ChildScope::SameNamespace, Some(_), None) => false,
+        };
+
+        if !valid_namespaces {
+            log::error!(
+                "Child {} is not in a namespace that's allowed for parent: {}",
+                child_id,
+                parent_id
+            );
+            let message = match child_config.scope {
+                ChildScope::Cluster => {
+                    "Child type is cluster-scoped, so the child must not have a namespace"
+                }
+                ChildScope::SameNamespace => {
+                    "Child namespace does not match the namespace of the parent"
+                }
+                ChildScope::AnyNamespace => {
+                    "Child type is namespaced, so the child must have a namespace"
+                }
+            };
+            return Err(InvalidResourceError::new(message, child.clone()).into());
+        }
+
         let existing_child = req
             .children()
             .of_type(child_config.child_type)
             .get(&child_id);
+        let owner_ref = can_own(&parent_id, &child_id.as_id_ref());
+        if child_config.record_last_applied {
+            add_last_applied(runtime_config, &child_id, &mut child)?;
+        }
+        add_parent_references(
+            runtime_config,
+            parent_id.name(),
+            parent_uid,
+            owner_ref,
+            &mut child,
+        )?;
+        if let Some(existing) = existing_child {
+            let ignored_paths = child_config.ignored_paths.as_slice();
+            match child_config.update_strategy {
+                // applying a field would take ownership of it away from whatever manages it, so they're left out
+                UpdateStrategy::Apply | UpdateStrategy::Patch(MergeStrategy::Apply) => {
+                    remove_ignored_values(&mut child, ignored_paths)
+                }
+                // the whole child is sent for some update strategies, so it needs to include the ignored fields as
+                // they are
+                _ => preserve_ignored(
+                    existing.as_ref(),
+                    &mut child,
+                    ignored_paths,
+                    &child_config.merge_keys,
+                ),
+            }
+        }
         let update_required = is_child_update_required(
             &parent_id,
             child_config,
+            runtime_config.last_applied_annotation_name.as_str(),
             existing_child,
             &child_id.as_id_ref(),
             &child,
         )?;
-        add_parent_references(runtime_config, parent_id.name(), parent_uid, &mut child)?;
         if let Some(update_type) = update_required {
             let start_time = Instant::now();
             log::debug!(
@@ -226,7 +303,10 @@ async fn update_children(
                 child_config.child_type,
                 child_id
             );
-            let result = do_child_update(update_type, child_config, client, child).await;
+            let (reason, action) = update_type.event_reason_and_action();
+            let is_create = update_type == UpdateType::Create;
+            let result =
+                do_child_update(update_type, child_config, runtime_config, client, child).await;
             let total_millis = duration_to_millis(start_time.elapsed());
             log::debug!(
                 "Finshed child update for {} in {}ms with result: {:?}",
@@ -234,16 +314,172 @@ async fn update_children(
                 total_millis,
                 result
             );
-            result?; // return early if it failed
+            let event = match result {
+                Ok(()) => Some((reason, action)),
+                // a 409 on create means that the child exists, but the informer doesn't know it's ours
+                Err(ref err) if is_create && err.is_http_status(409) => {
+                    handle_existing_child(
+                        client,
+                        runtime_config,
+                        req,
+                        child_config,
+                        &child_id.as_id_ref(),
+                        owner_ref,
+                    )
+                    .await?
+                }
+                // an update that changes an immutable field can only be made by re-creating the child
+                Err(ref err)
+                    if err.is_immutable_field()
+                        && child_config.recreate_on_immutable_field
+                        && existing_child.is_some() =>
+                {
+                    recreate_child(
+                        client,
+                        runtime_config,
+                        child_config,
+                        existing_child.unwrap(),
+                        &child_id.as_id_ref(),
+                    )
+                    .await?
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if let Some((reason, action)) = event {
+                runtime_config
+                    .events
+                    .normal(
+                        &req.parent,
+                        reason,
+                        format!("{} {} {}", action, child_config.child_type.kind, child_id),
+                    )
+                    .await;
+            }
         }
         child_ids.insert(child_id);
     }
     Ok(child_ids)
 }
 
+/// Handles a child that couldn't be created because a resource with the same name already exists. Returns the reason
+/// and action for the Event to record, if anything was done.
+async fn handle_existing_child(
+    client: &Client,
+    runtime_config: &RuntimeConfig,
+    req: &SyncRequest,
+    child_config: &ChildRuntimeConfig,
+    child_id: &ObjectIdRef<'_>,
+    owner_ref: bool,
+) -> Result<Option<(&'static str, &'static str)>, UpdateError> {
+    let kind = child_config.child_type.kind;
+    match child_config.adoption_policy {
+        AdoptionPolicy::Refuse => {
+            return Err(UpdateError::ChildConflict(
+                kind,
+                child_id.to_string(),
+                "is not managed by this parent",
+            ));
+        }
+        AdoptionPolicy::Skip => {
+            log::info!(
+                "Skipping child: {} of parent: {} because it already exists",
+                child_id,
+                req.parent.get_object_id()
+            );
+            return Ok(None);
+        }
+        AdoptionPolicy::Adopt => {}
+    }
+
+    let existing = match client
+        .get_resource(child_config.child_type, child_id)
+        .await?
+    {
+        Some(existing) => existing,
+        // it was deleted since we tried to create it, so it'll get created on the next sync
+        None => return Ok(None),
+    };
+    let parent_uid = req.parent.uid();
+    let tracking_label = existing
+        .pointer("/metadata/labels")
+        .and_then(|labels| labels.get(runtime_config.correlation_label_name.as_str()))
+        .and_then(Value::as_str);
+    if tracking_label.map(|uid| uid != parent_uid).unwrap_or(false) {
+        return Err(UpdateError::ChildConflict(
+            kind,
+            child_id.to_string(),
+            "is a child of a different parent",
+        ));
+    }
+    let mut owner_refs = existing
+        .pointer("/metadata/ownerReferences")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let other_controller = owner_refs.iter().any(|owner| {
+        owner.get("controller").and_then(Value::as_bool) == Some(true)
+            && owner.get("uid").and_then(Value::as_str) != Some(parent_uid)
+    });
+    if other_controller {
+        return Err(UpdateError::ChildConflict(
+            kind,
+            child_id.to_string(),
+            "is controlled by another owner",
+        ));
+    }
+
+    let mut metadata = json!({
+        "resourceVersion": existing.pointer("/metadata/resourceVersion"),
+        "labels": {
+            runtime_config.correlation_label_name.as_str(): parent_uid,
+            runtime_config.controller_label_name.as_str(): runtime_config.operator_name.as_str(),
+        },
+    });
+    let new_ref = make_owner_ref(parent_uid, req.parent.name(), runtime_config);
+    if owner_ref && !owner_refs.contains(&new_ref) {
+        owner_refs.push(new_ref);
+        metadata["ownerReferences"] = Value::Array(owner_refs);
+    }
+    log::info!(
+        "Adopting existing child: {} of parent: {}",
+        child_id,
+        req.parent.get_object_id()
+    );
+    let patch = Patch::new(MergeStrategy::JsonMerge, json!({ "metadata": metadata }));
+    client
+        .patch_resource(child_config.child_type, child_id, &patch)
+        .await?;
+    Ok(Some(("AdoptedChild", "Adopted")))
+}
+
+/// Deletes an existing child that couldn't be updated because the update would change an immutable field. It'll be
+/// re-created with the desired state once the delete has finished, just like with the `Recreate` strategy.
+async fn recreate_child(
+    client: &Client,
+    runtime_config: &RuntimeConfig,
+    child_config: &ChildRuntimeConfig,
+    existing_child: &K8sResource,
+    child_id: &ObjectIdRef<'_>,
+) -> Result<Option<(&'static str, &'static str)>, UpdateError> {
+    log::warn!(
+        "Deleting child: {} with type: {} so that it can be re-created, because the update changed an immutable field",
+        child_id,
+        child_config.child_type
+    );
+    runtime_config
+        .metrics
+        .child_recreated_for_immutable_field(child_config.child_type);
+    let options = child_config.delete_options(existing_child.uid());
+    client
+        .delete_resource(child_config.child_type, child_id, &options)
+        .await?;
+    Ok(Some(("RecreatingChild", "Recreating")))
+}
+
 async fn do_child_update(
     update_type: UpdateType,
     child_config: &ChildRuntimeConfig,
+    runtime_config: &RuntimeConfig,
     client: &Client,
     mut desired_child: Value,
 ) -> Result<(), client::Error> {
@@ -270,12 +506,28 @@ async fn do_child_update(
                 .replace_resource(k8s_type, &child_id, &desired_child)
                 .await
         }
-        UpdateType::Delete => {
+        UpdateType::Apply => {
+            let child_id = desired_child
+                .get_id_ref()
+                .expect("failed to get id from desired child resource")
+                .to_owned();
+            let patch = Patch::apply(desired_child, runtime_config.field_manager.as_str());
+            client
+                .patch_resource(k8s_type, &child_id.as_id_ref(), &patch)
+                .await
+        }
+        UpdateType::Patch(patch) => {
             let child_id = desired_child
                 .get_id_ref()
                 .expect("failed to get id from desired child resource");
-            // TODO: Deleting a resource could return a 409 error if it's already being deleted. Figure out how to deal with that
-            client.delete_resource(k8s_type, &child_id).await
+            client.patch_resource(k8s_type, &child_id, &patch).await
+        }
+        UpdateType::Delete(uid) => {
+            let child_id = desired_child
+                .get_id_ref()
+                .expect("failed to get id from desired child resource");
+            let options = child_config.delete_options(uid.as_str());
+            client.delete_resource(k8s_type, &child_id, &options).await
         }
     }
 }
@@ -284,12 +536,27 @@ async fn do_child_update(
 enum UpdateType {
     Create,
     Replace(String),
-    Delete,
+    Apply,
+    Patch(Patch),
+    /// Deletes the existing child with the given uid
+    Delete(String),
+}
+
+impl UpdateType {
+    /// Returns the reason and a description of the action, for the Event that's recorded after a successful update
+    fn event_reason_and_action(&self) -> (&'static str, &'static str) {
+        match self {
+            UpdateType::Create => ("CreatedChild", "Created"),
+            UpdateType::Delete(_) => ("DeletedChild", "Deleted"),
+            _ => ("UpdatedChild", "Updated"),
+        }
+    }
 }
 
 fn is_child_update_required(
     parent_id: &ObjectIdRef<'_>,
     child_config: &ChildRuntimeConfig,
+    last_applied_annotation: &str,
     existing_child: Option<&K8sResource>,
     child_id: &ObjectIdRef<'_>,
     child: &Value,
@@ -301,8 +568,33 @@ fn is_child_update_required(
             None
         }
         (Some(existing_child), update_strategy) => {
-            let diffs = compare_values(existing_child.as_ref(), child);
-            if diffs.non_empty() {
+            // a last applied state that's no longer being recorded would be stale, so it's only used along with a new one
+            let records_last_applied = child
+                .pointer("/metadata/annotations")
+                .and_then(|annotations| annotations.get(last_applied_annotation))
+                .is_some();
+            let last_applied = if records_last_applied {
+                get_last_applied(existing_child.as_ref(), last_applied_annotation)
+            } else {
+                None
+            };
+            // The last applied annotation changes whenever the desired state does, so it's only a difference by
+            // itself if the existing child doesn't have it yet. That's not worth updating children for, though.
+            let unannotated = without_annotation(child, last_applied_annotation);
+            let has_changes = child_diffs(
+                child_config,
+                last_applied.as_ref(),
+                existing_child.as_ref(),
+                &unannotated,
+            )
+            .non_empty();
+            if has_changes {
+                let diffs = child_diffs(
+                    child_config,
+                    last_applied.as_ref(),
+                    existing_child.as_ref(),
+                    child,
+                );
                 log::info!(
                     "Found {} diffs in child of parent: {} with type: {} and id: {}, diffs: {}",
                     diffs.len(),
@@ -311,7 +603,7 @@ fn is_child_update_required(
                     child_id,
                     diffs
                 );
-                determine_update_type(existing_child, update_strategy)
+                determine_update_type(existing_child, update_strategy, &diffs, child)
             } else {
                 log::debug!(
                     "No difference in child of parent: {}, with type: {} and id: {}",
@@ -340,6 +632,8 @@ fn is_child_update_required(
 fn determine_update_type(
     existing_child: &K8sResource,
     update_strategy: UpdateStrategy,
+    diffs: &Diffs,
+    desired_child: &Value,
 ) -> Option<UpdateType> {
     if existing_child.is_deletion_timestamp_set() {
         log::debug!(
@@ -353,17 +647,121 @@ fn determine_update_type(
         // When updateStrategy is recreate, we only delete it on the first go around and then we'll do a Create
         // once the delete has finished. This allows us to continue to make progress on the rest of the sync operations
         // since deletion can sometimes take quite a while due to finalizers needing to run.
-        Some(UpdateType::Delete)
+        Some(UpdateType::Delete(existing_child.uid().to_owned()))
     } else {
-        let resource_version = existing_child.resource_version();
-        Some(UpdateType::Replace(resource_version.to_owned()))
+        match update_strategy {
+            // server-side apply doesn't need the existing resourceVersion, since the api server
+            // will merge our desired fields with whatever else is already there
+            UpdateStrategy::Apply | UpdateStrategy::Patch(MergeStrategy::Apply) => {
+                Some(UpdateType::Apply)
+            }
+            UpdateStrategy::Patch(MergeStrategy::Json) => {
+                let patch = Patch::new(MergeStrategy::Json, diffs.to_json_patch(desired_child));
+                Some(UpdateType::Patch(patch))
+            }
+            // a strategic merge patch merges keyed lists, like containers or ports, with the existing ones, so it can't
+            // remove items from them. A JSON patch replaces the whole list instead.
+            UpdateStrategy::Patch(MergeStrategy::StrategicMerge) if diffs.removes_array_items() => {
+                let patch = Patch::new(MergeStrategy::Json, diffs.to_json_patch(desired_child));
+                Some(UpdateType::Patch(patch))
+            }
+            UpdateStrategy::Patch(merge_strategy) => {
+                let patch = Patch::new(merge_strategy, diffs.to_merge_patch(desired_child));
+                Some(UpdateType::Patch(patch))
+            }
+            _ => {
+                let resource_version = existing_child.resource_version();
+                Some(UpdateType::Replace(resource_version.to_owned()))
+            }
+        }
+    }
+}
+
+/// Returns the differences between the existing and desired child, including any values that were removed since the
+/// `last_applied` state, but not including any of the ignored paths for the child type
+fn child_diffs<'a>(
+    child_config: &ChildRuntimeConfig,
+    last_applied: Option<&Value>,
+    existing: &'a Value,
+    desired: &'a Value,
+) -> Diffs<'a> {
+    let mut diffs = compare_values_with(
+        existing,
+        desired,
+        &child_config.merge_keys,
+        &child_config.value_formats,
+    );
+    if let Some(last) = last_applied {
+        diffs.append(removed_values_with(
+            last,
+            existing,
+            desired,
+            &child_config.merge_keys,
+        ));
+    }
+    diffs.remove_ignored(child_config.ignored_paths.as_slice());
+    diffs
+}
+
+/// Returns the desired state that was last applied to the existing child, if it was recorded
+fn get_last_applied(existing: &Value, annotation: &str) -> Option<Value> {
+    existing
+        .pointer("/metadata/annotations")
+        .and_then(|annotations| annotations.get(annotation))
+        .and_then(Value::as_str)
+        .and_then(|json| serde_json::from_str(json).ok())
+}
+
+fn without_annotation(child: &Value, annotation: &str) -> Value {
+    let mut child = child.clone();
+    if let Some(annotations) = child
+        .pointer_mut("/metadata/annotations")
+        .and_then(Value::as_object_mut)
+    {
+        annotations.remove(annotation);
+    }
+    child
+}
+
+/// Records the desired state from the handler in the last applied annotation, so that we can tell when it stops setting
+/// a field. This must be called before anything else is added to the child.
+fn add_last_applied(
+    runtime_config: &RuntimeConfig,
+    child_id: &ObjectId,
+    child: &mut Value,
+) -> Result<(), InvalidResourceError> {
+    let last_applied = serde_json::to_string(&*child)
+        .map_err(|_| InvalidResourceError::new("child could not be serialized", child.clone()))?;
+    if last_applied.len() > MAX_LAST_APPLIED_SIZE {
+        log::warn!(
+            "Not recording the last applied state of child: {} because it's {} bytes, which is over the limit of {}",
+            child_id,
+            last_applied.len(),
+            MAX_LAST_APPLIED_SIZE
+        );
+        return Ok(());
     }
+    let meta = require_object_mut(child, "/metadata", "child object is missing 'metadata'")?;
+    if !meta.contains_key("annotations") || !meta.get("annotations").unwrap().is_object() {
+        meta.insert("annotations".to_owned(), Value::Object(JsonObject::new()));
+    }
+    meta.get_mut("annotations")
+        .unwrap()
+        .as_object_mut()
+        .unwrap() // we just ensured this above
+        .insert(
+            runtime_config.last_applied_annotation_name.clone(),
+            last_applied.into(),
+        );
+    Ok(())
 }
 
+/// Adds the tracking labels to the child, as well as an ownerReference if `owner_ref` is true
 fn add_parent_references(
     runtime_config: &RuntimeConfig,
     parent_name: &str,
     parent_uid: &str,
+    owner_ref: bool,
     child: &mut Value,
 ) -> Result<(), InvalidResourceError> {
     let meta = require_object_mut(child, "/metadata", "child object is missing 'metadata'")?;
@@ -381,6 +779,9 @@ fn add_parent_references(
             runtime_config.operator_name.as_str().into(),
         );
     }
+    if !owner_ref {
+        return Ok(());
+    }
     if !meta.contains_key("ownerReferences") || !meta.get("ownerReferences").unwrap().is_array() {
         meta.insert("ownerReferences".to_owned(), Value::Array(Vec::new()));
     }
@@ -424,3 +825,341 @@ fn require_object_mut<'a>(
         Err(InvalidResourceError::new(err_msg, value.clone()))
     }
 }
+
+#[cfg(all(test, feature = "testkit"))]
+mod test {
+    use super::MAX_LAST_APPLIED_SIZE;
+    use crate::config::{AdoptionPolicy, ChildConfig, MergeStrategy};
+    use crate::handler::{AsyncHandler, SyncRequest, SyncResponse};
+    use crate::k8s_types::core::v1::{ConfigMap, Pod, Secret};
+    use crate::resource::ObjectIdRef;
+    use crate::runner::testkit::fixture::*;
+    use anyhow::Error;
+    use futures::future::BoxFuture;
+    use serde_json::{json, Value};
+
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn existing_children_are_adopted_or_refused() {
+        let operator_config = operator_config()
+            .with_child(
+                ConfigMap,
+                ChildConfig::replace().adoption_policy(AdoptionPolicy::Adopt),
+            )
+            .with_child(Secret, ChildConfig::replace());
+        let handler = |req: &SyncRequest| {
+            let mut config_map = v1_resource("ConfigMap", req.parent.namespace(), "existing");
+            config_map["data"] = json!({"new": "value"});
+            let secret = v1_resource("Secret", req.parent.namespace(), "existing");
+            Ok(response(vec![config_map, secret]))
+        };
+        let mut testkit = start(operator_config, handler);
+        let mut existing_config_map = v1_resource("ConfigMap", Some("ns"), "existing");
+        existing_config_map["data"] = json!({"old": "value"});
+        testkit
+            .create_resource(ConfigMap, &existing_config_map)
+            .unwrap();
+        let existing_secret = v1_resource("Secret", Some("ns"), "existing");
+        testkit.create_resource(Secret, &existing_secret).unwrap();
+        testkit
+            .create_resource(PARENT_TYPE, &parent("ns", "parent"))
+            .unwrap();
+
+        eventually(&mut testkit, "config map to be adopted", |testkit| {
+            let config_map = get_resource(testkit, ConfigMap, "ns", "existing").unwrap();
+            config_map["data"] == json!({"new": "value"})
+                && config_map["metadata"]["labels"]["app.kubernetes.io/managed-by"] == OPERATOR_NAME
+                && config_map
+                    .pointer("/metadata/ownerReferences/0/kind")
+                    .and_then(Value::as_str)
+                    == Some("Parent")
+        });
+        eventually(&mut testkit, "secret to be refused", |testkit| {
+            list_events(testkit, "ns").iter().any(|event| {
+                event.pointer("/reason") == Some(&json!("SyncFailed"))
+                    && event
+                        .pointer("/message")
+                        .and_then(Value::as_str)
+                        .map(|message| message.contains("already exists and is not managed"))
+                        .unwrap_or(false)
+            })
+        });
+        let secret = get_resource(&mut testkit, Secret, "ns", "existing").unwrap();
+        assert!(secret.pointer("/metadata/labels").is_none());
+    }
+
+    #[test]
+    fn fields_the_handler_stops_setting_are_removed_from_children() {
+        let operator_config = operator_config().with_child(
+            ConfigMap,
+            ChildConfig::patch(MergeStrategy::JsonMerge).record_last_applied(),
+        );
+        let include_extra = Arc::new(AtomicBool::new(true));
+        let handler = {
+            let include_extra = include_extra.clone();
+            move |req: &SyncRequest| {
+                let mut config_map = v1_resource("ConfigMap", req.parent.namespace(), "config");
+                config_map["data"] = json!({ "kept": "value" });
+                if include_extra.load(Ordering::SeqCst) {
+                    config_map["data"]["extra"] = json!("value");
+                }
+                Ok(SyncResponse {
+                    resync: Some(Duration::from_millis(100)),
+                    ..response(vec![config_map])
+                })
+            }
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("ns", "config"),
+            TIMEOUT,
+        );
+        let config_map = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        assert_eq!(Some(&json!("value")), config_map.pointer("/data/extra"));
+        let last_applied = config_map
+            .pointer("/metadata/annotations/roperator.io~1last-applied-configuration")
+            .and_then(Value::as_str)
+            .expect("missing last applied annotation");
+        let last_applied: Value = serde_json::from_str(last_applied).unwrap();
+        assert_eq!(Some(&json!("value")), last_applied.pointer("/data/extra"));
+        assert!(last_applied.pointer("/metadata/labels").is_none());
+
+        include_extra.store(false, Ordering::SeqCst);
+        eventually(&mut testkit, "removed field to be removed", |testkit| {
+            let config_map = get_resource(testkit, ConfigMap, "ns", "config").unwrap();
+            config_map.pointer("/data/extra").is_none()
+        });
+        let config_map = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        assert_eq!(Some(&json!("value")), config_map.pointer("/data/kept"));
+    }
+
+    #[test]
+    fn items_the_handler_stops_setting_are_removed_from_strategic_merge_lists() {
+        let operator_config = operator_config().with_child(
+            Pod,
+            ChildConfig::patch(MergeStrategy::StrategicMerge).record_last_applied(),
+        );
+        let include_sidecar = Arc::new(AtomicBool::new(true));
+        let handler = {
+            let include_sidecar = include_sidecar.clone();
+            move |req: &SyncRequest| {
+                let mut pod = v1_resource("Pod", req.parent.namespace(), "pod");
+                pod["spec"] = json!({ "containers": [{ "name": "main", "image": "main" }] });
+                if include_sidecar.load(Ordering::SeqCst) {
+                    pod["spec"]["containers"]
+                        .as_array_mut()
+                        .unwrap()
+                        .push(json!({ "name": "sidecar", "image": "sidecar" }));
+                }
+                Ok(SyncResponse {
+                    resync: Some(Duration::from_millis(100)),
+                    ..response(vec![pod])
+                })
+            }
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        eventually(&mut testkit, "pod to have both containers", |testkit| {
+            get_resource(testkit, Pod, "ns", "pod")
+                .and_then(|pod| pod.pointer("/spec/containers").cloned())
+                .map(|containers| containers.as_array().unwrap().len() == 2)
+                .unwrap_or(false)
+        });
+
+        include_sidecar.store(false, Ordering::SeqCst);
+        eventually(&mut testkit, "sidecar to be removed", |testkit| {
+            let pod = get_resource(testkit, Pod, "ns", "pod").unwrap();
+            pod.pointer("/spec/containers") == Some(&json!([{ "name": "main", "image": "main" }]))
+        });
+    }
+
+    #[test]
+    fn last_applied_is_not_recorded_unless_configured_or_when_too_large() {
+        let operator_config = operator_config()
+            .with_child(ConfigMap, ChildConfig::replace().record_last_applied())
+            .with_child(Secret, ChildConfig::replace());
+        let handler = |req: &SyncRequest| {
+            let mut config_map = v1_resource("ConfigMap", req.parent.namespace(), "large");
+            config_map["data"] = json!({ "large": "x".repeat(MAX_LAST_APPLIED_SIZE) });
+            let mut secret = v1_resource("Secret", req.parent.namespace(), "unconfigured");
+            secret["stringData"] = json!({ "key": "value" });
+            Ok(response(vec![config_map, secret]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("ns", "large"),
+            TIMEOUT,
+        );
+        testkit.assert_resource_exists_eventually(
+            Secret,
+            &ObjectIdRef::new("ns", "unconfigured"),
+            TIMEOUT,
+        );
+        for (k8s_type, name) in &[(ConfigMap, "large"), (Secret, "unconfigured")] {
+            let child = get_resource(&mut testkit, k8s_type, "ns", name).unwrap();
+            assert!(child
+                .pointer("/metadata/annotations/roperator.io~1last-applied-configuration")
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn ignored_paths_are_not_reverted_in_children() {
+        let operator_config = operator_config().with_child(
+            ConfigMap,
+            ChildConfig::patch(MergeStrategy::JsonMerge).ignore_path("/data/replicas"),
+        );
+        let handler = |req: &SyncRequest| {
+            let mut config_map = v1_resource("ConfigMap", req.parent.namespace(), "config");
+            config_map["data"] = json!({"replicas": "1", "image": "foo"});
+            Ok(SyncResponse {
+                resync: Some(Duration::from_millis(100)),
+                ..response(vec![config_map])
+            })
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        let config_id = ObjectIdRef::new("ns", "config");
+        testkit.assert_resource_exists_eventually(ConfigMap, &config_id, TIMEOUT);
+
+        // another controller changes both fields, but only the one that isn't ignored should be reverted
+        let mut config_map = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        config_map["data"] = json!({"replicas": "3", "image": "bar"});
+        testkit
+            .replace_resource(ConfigMap, &config_id, config_map)
+            .unwrap();
+        eventually(&mut testkit, "child to be updated", |testkit| {
+            let config_map = get_resource(testkit, ConfigMap, "ns", "config").unwrap();
+            config_map.pointer("/data/image") == Some(&json!("foo"))
+        });
+        let config_map = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        assert_eq!(Some(&json!("3")), config_map.pointer("/data/replicas"));
+    }
+
+    #[test]
+    fn ignored_paths_are_kept_when_children_are_replaced() {
+        let operator_config = operator_config().with_child(
+            ConfigMap,
+            ChildConfig::replace()
+                .ignore_path("/data/replicas")
+                .ignore_path("/metadata/annotations/sidecar.io~1*"),
+        );
+        let handler = |req: &SyncRequest| {
+            let mut config_map = v1_resource("ConfigMap", req.parent.namespace(), "config");
+            config_map["data"] = json!({"replicas": "1", "image": "foo"});
+            Ok(SyncResponse {
+                resync: Some(Duration::from_millis(100)),
+                ..response(vec![config_map])
+            })
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        let config_id = ObjectIdRef::new("ns", "config");
+        testkit.assert_resource_exists_eventually(ConfigMap, &config_id, TIMEOUT);
+
+        let mut config_map = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        config_map["data"] = json!({"replicas": "3", "image": "bar"});
+        config_map["metadata"]["annotations"]["sidecar.io/inject"] = json!("true");
+        testkit
+            .replace_resource(ConfigMap, &config_id, config_map)
+            .unwrap();
+        eventually(&mut testkit, "child to be replaced", |testkit| {
+            let config_map = get_resource(testkit, ConfigMap, "ns", "config").unwrap();
+            config_map.pointer("/data/image") == Some(&json!("foo"))
+        });
+        let config_map = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        assert_eq!(Some(&json!("3")), config_map.pointer("/data/replicas"));
+        assert_eq!(
+            Some(&json!("true")),
+            config_map.pointer("/metadata/annotations/sidecar.io~1inject")
+        );
+    }
+
+    #[test]
+    fn children_are_recreated_when_an_update_changes_an_immutable_field() {
+        let operator_config = operator_config().with_child(
+            ConfigMap,
+            ChildConfig::replace().recreate_on_immutable_field(),
+        );
+        let version = Arc::new(AtomicUsize::new(1));
+        let handler = {
+            let version = version.clone();
+            move |req: &SyncRequest| {
+                let mut config_map = v1_resource("ConfigMap", req.parent.namespace(), "config");
+                config_map["immutable"] = json!(true);
+                config_map["data"] =
+                    json!({ "version": version.load(Ordering::SeqCst).to_string() });
+                Ok(SyncResponse {
+                    resync: Some(Duration::from_millis(100)),
+                    ..response(vec![config_map])
+                })
+            }
+        };
+        let mut testkit = start(operator_config, handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        testkit.assert_resource_exists_eventually(
+            ConfigMap,
+            &ObjectIdRef::new("ns", "config"),
+            TIMEOUT,
+        );
+        let original = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+
+        version.store(2, Ordering::SeqCst);
+        eventually(&mut testkit, "child to be re-created", |testkit| {
+            get_resource(testkit, ConfigMap, "ns", "config")
+                .map(|config_map| config_map.pointer("/data/version") == Some(&json!("2")))
+                .unwrap_or(false)
+        });
+        let recreated = get_resource(&mut testkit, ConfigMap, "ns", "config").unwrap();
+        assert_ne!(
+            original.pointer("/metadata/uid"),
+            recreated.pointer("/metadata/uid")
+        );
+    }
+
+    /// Panics the first time it syncs a parent, and sets a status after that
+    struct PanicsOnce(AtomicBool);
+
+    impl AsyncHandler for PanicsOnce {
+        fn sync(
+            self: Arc<Self>,
+            _request: Arc<SyncRequest>,
+        ) -> BoxFuture<'static, Result<SyncResponse, Error>> {
+            Box::pin(async move {
+                if !self.0.swap(true, Ordering::SeqCst) {
+                    panic!("handler panicked on purpose");
+                }
+                Ok(SyncResponse {
+                    status: json!({ "synced": true }),
+                    ..response(Vec::new())
+                })
+            })
+        }
+    }
+
+    #[test]
+    fn async_handler_panics_are_treated_as_errors() {
+        let handler = PanicsOnce(AtomicBool::new(false));
+        let mut testkit = start(operator_config(), handler);
+
+        testkit.create_parent(parent("ns", "parent"), TIMEOUT);
+        eventually(&mut testkit, "parent to be synced", |testkit| {
+            get_resource(testkit, PARENT_TYPE, "ns", "parent")
+                .map(|parent| parent.pointer("/status/synced") == Some(&json!(true)))
+                .unwrap_or(false)
+        });
+    }
+}
@@ -39,6 +39,22 @@ impl<T> IdMap<T> {
     pub fn len(&self) -> usize {
         self.0.values().map(HashMap::len).sum()
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = ObjectIdRef> {
+        self.0.iter().flat_map(|(namespace, by_name)| {
+            by_name
+                .keys()
+                .map(move |name| ObjectIdRef { namespace, name })
+        })
+    }
+
+    /// Removes and returns all of the values in the given namespace
+    pub fn remove_namespace(&mut self, namespace: &str) -> Vec<T> {
+        self.0
+            .remove(namespace)
+            .map(|by_name| by_name.into_iter().map(|entry| entry.1).collect())
+            .unwrap_or_default()
+    }
 }
 
 impl IdMap<K8sResource> {
@@ -68,12 +84,4 @@ impl IdMap<()> {
         let by_name = self.0.entry(namespace).or_default();
         by_name.insert(name, ()).is_none()
     }
-
-    pub fn iter(&self) -> impl Iterator<Item = ObjectIdRef> {
-        self.0.iter().flat_map(|(namespace, by_name)| {
-            by_name
-                .keys()
-                .map(move |name| ObjectIdRef { namespace, name })
-        })
-    }
 }
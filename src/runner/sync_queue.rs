@@ -0,0 +1,72 @@
+use std::collections::{HashSet, VecDeque};
+
+/// A FIFO queue of parent uids that need to be synced. Each uid may only be in the queue once, so a parent that's
+/// triggered many times while it's waiting keeps its original position. A parent that's triggered again after it's been
+/// removed goes to the back of the queue, which ensures that a parent that syncs repeatedly can't starve the others.
+#[derive(Debug, Default)]
+pub(crate) struct SyncQueue {
+    order: VecDeque<String>,
+    members: HashSet<String>,
+}
+
+impl SyncQueue {
+    pub fn new() -> SyncQueue {
+        SyncQueue::default()
+    }
+
+    /// Adds the uid to the back of the queue, and returns true if it was not already queued
+    pub fn insert(&mut self, parent_uid: String) -> bool {
+        if self.members.contains(&parent_uid) {
+            false
+        } else {
+            self.members.insert(parent_uid.clone());
+            self.order.push_back(parent_uid);
+            true
+        }
+    }
+
+    /// Removes the uid from the queue, returning true if it was present
+    pub fn remove(&mut self, parent_uid: &str) -> bool {
+        if self.members.remove(parent_uid) {
+            self.order.retain(|uid| uid != parent_uid);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns an iterator over the queued uids, starting with the one that was queued first
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.order.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queue_keeps_insertion_order_and_ignores_duplicates() {
+        let mut queue = SyncQueue::new();
+        assert!(queue.insert("a".to_owned()));
+        assert!(queue.insert("b".to_owned()));
+        assert!(!queue.insert("a".to_owned()));
+        assert!(queue.insert("c".to_owned()));
+        assert_eq!(3, queue.len());
+
+        assert!(queue.remove("a"));
+        assert!(!queue.remove("a"));
+        // a parent that's triggered again after being synced goes to the back
+        assert!(queue.insert("a".to_owned()));
+        let order = queue.iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(vec!["b", "c", "a"], order);
+    }
+}
@@ -0,0 +1,1367 @@
+//! An in-memory fake of the Kubernetes api server, which allows operators to be tested without a real cluster.
+//! The `FakeApiServer` listens on a random port on `127.0.0.1` and serves plain HTTP, so a `Client` can talk to
+//! it exactly the same way it talks to a real api server.
+//!
+//! The fake api server doesn't know anything about specific resource types, so it'll happily accept any resource at
+//! any api path. It implements just enough of the api server semantics to test operators:
+//!
+//! - list, watch, get, create, replace, patch, and delete requests, plus replacing the `status` subresource
+//! - `resourceVersion`s that are incremented on every change, and optimistic concurrency checks for updates
+//! - equality based label selectors (`foo`, `!foo`, `foo=bar`, `foo!=bar`), and field selectors (`metadata.name=bar`)
+//! - ADDED and DELETED watch events when a resource is modified so that it starts or stops matching a selector
+//! - finalizers and `deletionTimestamp`s
+//! - garbage collection of resources whose owner has been deleted, and of resources within a deleted Namespace
+//!
+//! Every resource is treated as though it has the `status` subresource enabled, so changes to the `status` are only
+//! persisted when they're made through the `/status` endpoint. Strategic merge patches and server-side apply patches
+//! are both approximated by a JSON merge patch that merges arrays of objects using their `name` fields.
+use crate::config::{ClientConfig, Credentials};
+use crate::runner::format_micro_time;
+
+use bytes::Bytes;
+use futures::channel::{mpsc, oneshot};
+use futures_util::StreamExt;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+type JsonObject = serde_json::Map<String, Value>;
+
+const NAMESPACE_TYPE_KEY: &str = "v1/namespaces";
+const CRD_TYPE_KEY: &str = "apiextensions.k8s.io/v1/customresourcedefinitions";
+
+/// An in-memory api server that runs on its own thread. The server is shutdown when this struct is dropped.
+pub struct FakeApiServer {
+    address: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for FakeApiServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FakeApiServer({})", self.address)
+    }
+}
+
+impl FakeApiServer {
+    /// Starts a new, empty api server on a random port on localhost
+    pub fn start() -> Result<FakeApiServer, io::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let mut runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(2)
+            .enable_all()
+            .build()?;
+
+        let thread = std::thread::Builder::new()
+            .name("fake-api-server".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let store = Arc::new(Mutex::new(Store::default()));
+                    let service = make_service_fn(move |_| {
+                        let store = store.clone();
+                        async move {
+                            Ok::<_, hyper::Error>(service_fn(move |request| {
+                                handle_request(store.clone(), request)
+                            }))
+                        }
+                    });
+                    let server = match Server::from_tcp(listener) {
+                        Ok(builder) => builder.serve(service),
+                        Err(err) => {
+                            log::error!("Failed to start fake api server: {}", err);
+                            return;
+                        }
+                    };
+                    // we don't use a graceful shutdown here because watch responses never end on their own
+                    futures_util::future::select(server, shutdown_rx).await;
+                });
+                log::debug!("Fake api server has been shutdown");
+            })?;
+        log::info!("Started fake api server on: {}", address);
+
+        Ok(FakeApiServer {
+            address,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the base url of the api server
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Returns a `ClientConfig` that can be used to connect to this api server
+    pub fn client_config(&self, user_agent: impl Into<String>) -> ClientConfig {
+        ClientConfig {
+            api_server_endpoint: self.endpoint(),
+            credentials: Credentials::Header("Bearer fake-api-server-token".to_owned()),
+            ca_data: None,
+            user_agent: user_agent.into(),
+            verify_ssl_certs: false,
+            impersonate: None,
+            impersonate_groups: Vec::new(),
+        }
+    }
+}
+
+impl Drop for FakeApiServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn handle_request(
+    store: Arc<Mutex<Store>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let content_type = parts
+        .headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json");
+    log::debug!("Fake api server received {} {}", parts.method, parts.uri);
+
+    let path = match ResourcePath::parse(parts.uri.path()) {
+        Some(p) => p,
+        None => {
+            return Ok(status_response(
+                StatusCode::NOT_FOUND,
+                "NotFound",
+                format!("the server could not find the path: {}", parts.uri.path()),
+            ))
+        }
+    };
+    let query: BTreeMap<String, String> = parts
+        .uri
+        .query()
+        .map(|q| {
+            url::form_urlencoded::parse(q.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut store = store.lock().unwrap();
+    let response = match (&parts.method, path.name.is_some(), path.status) {
+        (&Method::GET, false, false) if query.get("watch").map(String::as_str) == Some("true") => {
+            store.watch(&path, &query)
+        }
+        (&Method::GET, false, false) => store.list(&path, &query),
+        (&Method::GET, true, false) => store.get(&path),
+        (&Method::POST, false, false) => store.create(&path, body.as_ref()),
+        (&Method::PUT, true, status) => store.replace(&path, status, body.as_ref()),
+        (&Method::PATCH, true, status) => store.patch(&path, status, content_type, body.as_ref()),
+        (&Method::DELETE, true, false) => store.delete(&path, body.as_ref()),
+        _ => status_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "MethodNotAllowed",
+            format!(
+                "{} is not supported for: {}",
+                parts.method,
+                parts.uri.path()
+            ),
+        ),
+    };
+    Ok(response)
+}
+
+/// The parsed path of an api request
+#[derive(Debug, PartialEq)]
+struct ResourcePath {
+    /// The apiVersion and plural kind, for example `apps/v1/deployments` or `v1/pods`
+    type_key: String,
+    namespace: Option<String>,
+    name: Option<String>,
+    status: bool,
+}
+
+impl ResourcePath {
+    fn parse(path: &str) -> Option<ResourcePath> {
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .map(|s| urlencoding::decode(s).unwrap_or_else(|_| s.to_owned()))
+            .collect::<Vec<_>>();
+        let (api_version, rest) = match segments.first().map(String::as_str) {
+            Some("api") if segments.len() > 2 => (segments[1].clone(), &segments[2..]),
+            Some("apis") if segments.len() > 3 => {
+                (format!("{}/{}", segments[1], segments[2]), &segments[3..])
+            }
+            _ => return None,
+        };
+        let (namespace, rest) = if rest.len() > 2 && rest[0] == "namespaces" {
+            (Some(rest[1].clone()), &rest[2..])
+        } else {
+            (None, rest)
+        };
+        let status = match rest.get(2).map(String::as_str) {
+            None => false,
+            Some("status") if rest.len() == 3 => true,
+            _ => return None,
+        };
+        Some(ResourcePath {
+            type_key: format!("{}/{}", api_version, rest[0]),
+            namespace,
+            name: rest.get(1).cloned(),
+            status,
+        })
+    }
+
+    fn key(&self) -> ResourceKey {
+        ResourceKey {
+            type_key: self.type_key.clone(),
+            namespace: self.namespace.clone().unwrap_or_default(),
+            name: self.name.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ResourceKey {
+    type_key: String,
+    namespace: String,
+    name: String,
+}
+
+/// Equality based label and field selectors
+#[derive(Debug, Clone, PartialEq)]
+struct Selector {
+    labels: Vec<Requirement>,
+    fields: Vec<Requirement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Requirement {
+    Exists(String),
+    NotExists(String),
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl Requirement {
+    fn parse_all(selector: Option<&String>) -> Vec<Requirement> {
+        selector
+            .map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|term| !term.is_empty())
+                    .map(|term| {
+                        if let Some(idx) = term.find("!=") {
+                            Requirement::NotEquals(
+                                term[..idx].trim().to_owned(),
+                                term[(idx + 2)..].trim().to_owned(),
+                            )
+                        } else if let Some(idx) = term.find('=') {
+                            Requirement::Equals(
+                                term[..idx].trim().to_owned(),
+                                term[(idx + 1)..].trim_start_matches('=').trim().to_owned(),
+                            )
+                        } else if term.starts_with('!') {
+                            Requirement::NotExists(term.trim_start_matches('!').trim().to_owned())
+                        } else {
+                            Requirement::Exists(term.to_owned())
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn matches<'a>(&self, get: impl Fn(&str) -> Option<&'a str>) -> bool {
+        match self {
+            Requirement::Exists(name) => get(name).is_some(),
+            Requirement::NotExists(name) => get(name).is_none(),
+            Requirement::Equals(name, value) => get(name) == Some(value.as_str()),
+            Requirement::NotEquals(name, value) => get(name) != Some(value.as_str()),
+        }
+    }
+}
+
+impl Selector {
+    fn from_query(query: &BTreeMap<String, String>) -> Selector {
+        Selector {
+            labels: Requirement::parse_all(query.get("labelSelector")),
+            fields: Requirement::parse_all(query.get("fieldSelector")),
+        }
+    }
+
+    fn matches(&self, resource: &Value) -> bool {
+        let label = |name: &str| {
+            resource
+                .pointer("/metadata/labels")
+                .and_then(|labels| labels.get(name))
+                .and_then(Value::as_str)
+        };
+        // field selectors use dotted paths, like `metadata.name`
+        let field = |path: &str| get_str(resource, &format!("/{}", path.replace('.', "/")));
+        self.labels.iter().all(|r| r.matches(label)) && self.fields.iter().all(|r| r.matches(field))
+    }
+}
+
+struct Watcher {
+    type_key: String,
+    namespace: Option<String>,
+    selector: Selector,
+    sender: mpsc::UnboundedSender<Bytes>,
+}
+
+impl Watcher {
+    fn matches(&self, type_key: &str, resource: &Value) -> bool {
+        self.type_key == type_key
+            && self
+                .namespace
+                .as_ref()
+                .map(|ns| Some(ns.as_str()) == get_str(resource, "/metadata/namespace"))
+                .unwrap_or(true)
+            && self.selector.matches(resource)
+    }
+
+    /// Returns the type of event that this watcher should receive, if any. Just like the real api server, a resource
+    /// that's modified so that it starts or stops matching the selector results in an ADDED or DELETED event.
+    fn event_type(
+        &self,
+        type_key: &str,
+        event_type: &'static str,
+        previous: Option<&Value>,
+        resource: &Value,
+    ) -> Option<&'static str> {
+        let matches = self.matches(type_key, resource);
+        match previous.map(|prev| self.matches(type_key, prev)) {
+            Some(false) if matches => Some("ADDED"),
+            Some(true) if !matches => Some("DELETED"),
+            _ if matches => Some(event_type),
+            _ => None,
+        }
+    }
+}
+
+struct StoredEvent {
+    resource_version: u64,
+    type_key: String,
+    event_type: &'static str,
+    previous: Option<Value>,
+    resource: Value,
+}
+
+#[derive(Default)]
+struct Store {
+    resource_version: u64,
+    uid_counter: u64,
+    resources: BTreeMap<ResourceKey, Value>,
+    events: Vec<StoredEvent>,
+    watchers: Vec<Watcher>,
+}
+
+impl Store {
+    fn list(&self, path: &ResourcePath, query: &BTreeMap<String, String>) -> Response<Body> {
+        let selector = Selector::from_query(query);
+        let items = self
+            .resources
+            .iter()
+            .filter(|(key, resource)| {
+                key.type_key == path.type_key
+                    && path
+                        .namespace
+                        .as_ref()
+                        .map(|ns| *ns == key.namespace)
+                        .unwrap_or(true)
+                    && selector.matches(resource)
+            })
+            .map(|(_, resource)| resource.clone())
+            .collect::<Vec<_>>();
+        let list = json!({
+            "apiVersion": "v1",
+            "kind": "List",
+            "metadata": {
+                "resourceVersion": self.resource_version.to_string(),
+            },
+            "items": items,
+        });
+        json_response(StatusCode::OK, &list)
+    }
+
+    fn watch(&mut self, path: &ResourcePath, query: &BTreeMap<String, String>) -> Response<Body> {
+        let (sender, receiver) = mpsc::unbounded();
+        let watcher = Watcher {
+            type_key: path.type_key.clone(),
+            namespace: path.namespace.clone(),
+            selector: Selector::from_query(query),
+            sender,
+        };
+        match query
+            .get("resourceVersion")
+            .and_then(|v| v.parse::<u64>().ok())
+        {
+            Some(since) if since > 0 => {
+                // replay all of the events that happened after the given resourceVersion
+                for event in self.events.iter().filter(|e| e.resource_version > since) {
+                    let event_type = watcher.event_type(
+                        &event.type_key,
+                        event.event_type,
+                        event.previous.as_ref(),
+                        &event.resource,
+                    );
+                    if let Some(event_type) = event_type {
+                        let _ = watcher
+                            .sender
+                            .unbounded_send(watch_line(event_type, &event.resource));
+                    }
+                }
+            }
+            _ => {
+                // without a resourceVersion, the watch starts with synthetic ADDED events for every existing resource
+                for (key, resource) in self.resources.iter() {
+                    if watcher.matches(&key.type_key, resource) {
+                        let _ = watcher.sender.unbounded_send(watch_line("ADDED", resource));
+                    }
+                }
+            }
+        }
+        self.watchers.push(watcher);
+
+        let body = Body::wrap_stream(receiver.map(Ok::<_, io::Error>));
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap()
+    }
+
+    fn get(&self, path: &ResourcePath) -> Response<Body> {
+        match self.resources.get(&path.key()) {
+            Some(resource) => json_response(StatusCode::OK, resource),
+            None => not_found(path),
+        }
+    }
+
+    fn create(&mut self, path: &ResourcePath, body: &[u8]) -> Response<Body> {
+        let mut resource = match parse_resource(body) {
+            Ok(r) => r,
+            Err(message) => return status_response(StatusCode::BAD_REQUEST, "BadRequest", message),
+        };
+        let namespace = path.namespace.clone().unwrap_or_default();
+        if !namespace.is_empty() {
+            let existing_ns = get_str(&resource, "/metadata/namespace").unwrap_or("");
+            if !existing_ns.is_empty() && existing_ns != namespace {
+                return status_response(
+                    StatusCode::BAD_REQUEST,
+                    "BadRequest",
+                    "the namespace of the provided object does not match the namespace sent on the request".to_owned(),
+                );
+            }
+            metadata_mut(&mut resource).insert("namespace".to_owned(), namespace.clone().into());
+        }
+        self.uid_counter += 1;
+        let name = match get_str(&resource, "/metadata/name") {
+            Some(name) => name.to_owned(),
+            None => match get_str(&resource, "/metadata/generateName") {
+                Some(prefix) => format!("{}{:05x}", prefix, self.uid_counter),
+                None => {
+                    return status_response(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "Invalid",
+                        "metadata.name: Required value".to_owned(),
+                    )
+                }
+            },
+        };
+        let key = ResourceKey {
+            type_key: path.type_key.clone(),
+            namespace,
+            name: name.clone(),
+        };
+        if self.resources.contains_key(&key) {
+            return status_response(
+                StatusCode::CONFLICT,
+                "AlreadyExists",
+                format!("{} \"{}\" already exists", path.type_key, name),
+            );
+        }
+
+        let uid = format!("00000000-0000-4000-8000-{:012x}", self.uid_counter);
+        let resource_version = self.next_resource_version();
+        {
+            let meta = metadata_mut(&mut resource);
+            meta.insert("name".to_owned(), name.into());
+            meta.insert("uid".to_owned(), uid.into());
+            meta.insert("resourceVersion".to_owned(), resource_version.into());
+            meta.insert("generation".to_owned(), 1.into());
+            meta.insert(
+                "creationTimestamp".to_owned(),
+                format_micro_time(SystemTime::now()).into(),
+            );
+            meta.remove("deletionTimestamp");
+        }
+        if key.type_key == CRD_TYPE_KEY {
+            set_established(&mut resource);
+        }
+        self.resources.insert(key, resource.clone());
+        self.record_event(path.type_key.as_str(), "ADDED", None, &resource);
+        json_response(StatusCode::CREATED, &resource)
+    }
+
+    fn replace(&mut self, path: &ResourcePath, status_only: bool, body: &[u8]) -> Response<Body> {
+        let key = path.key();
+        let existing = match self.resources.get(&key) {
+            Some(r) => r.clone(),
+            None => return not_found(path),
+        };
+        let new_resource = match parse_resource(body) {
+            Ok(r) => r,
+            Err(message) => return status_response(StatusCode::BAD_REQUEST, "BadRequest", message),
+        };
+        if let Some(resp) = check_resource_version(&existing, &new_resource) {
+            return resp;
+        }
+        let updated = if status_only {
+            with_status_from(existing.clone(), &new_resource)
+        } else {
+            with_status_from(new_resource, &existing)
+        };
+        self.update(key, &existing, updated)
+    }
+
+    fn patch(
+        &mut self,
+        path: &ResourcePath,
+        status_only: bool,
+        content_type: &str,
+        body: &[u8],
+    ) -> Response<Body> {
+        let key = path.key();
+        let existing = match self.resources.get(&key) {
+            Some(r) => r.clone(),
+            None => return not_found(path),
+        };
+        let patch: Value = match serde_json::from_slice(body) {
+            Ok(p) => p,
+            Err(err) => {
+                return status_response(StatusCode::BAD_REQUEST, "BadRequest", err.to_string())
+            }
+        };
+        let mut patched = existing.clone();
+        let content_type = content_type.split(';').next().unwrap_or("").trim();
+        match content_type {
+            "application/json-patch+json" => {
+                if let Err(message) = apply_json_patch(&mut patched, &patch) {
+                    return status_response(StatusCode::UNPROCESSABLE_ENTITY, "Invalid", message);
+                }
+            }
+            "application/merge-patch+json" => {
+                if let Some(resp) = check_resource_version(&existing, &patch) {
+                    return resp;
+                }
+                merge_patch(&mut patched, &patch, false);
+            }
+            "application/strategic-merge-patch+json" | "application/apply-patch+yaml" => {
+                if let Some(resp) = check_resource_version(&existing, &patch) {
+                    return resp;
+                }
+                merge_patch(&mut patched, &patch, true);
+            }
+            other => {
+                return status_response(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "UnsupportedMediaType",
+                    format!(
+                        "the body of the request was in an unknown format: {}",
+                        other
+                    ),
+                )
+            }
+        }
+        let updated = if status_only {
+            with_status_from(existing.clone(), &patched)
+        } else {
+            with_status_from(patched, &existing)
+        };
+        self.update(key, &existing, updated)
+    }
+
+    /// Deletes the resource, honoring the uid precondition and `Orphan` propagation policy from the `DeleteOptions`
+    fn delete(&mut self, path: &ResourcePath, body: &[u8]) -> Response<Body> {
+        let options = if body.is_empty() {
+            Value::Null
+        } else {
+            match parse_resource(body) {
+                Ok(options) => options,
+                Err(message) => {
+                    return status_response(StatusCode::BAD_REQUEST, "BadRequest", message)
+                }
+            }
+        };
+        let key = path.key();
+        if let Some(uid) = get_str(&options, "/preconditions/uid") {
+            let actual = self
+                .resources
+                .get(&key)
+                .and_then(|existing| get_str(existing, "/metadata/uid"));
+            if actual.map(|actual| actual != uid).unwrap_or(false) {
+                return status_response(
+                    StatusCode::CONFLICT,
+                    "Conflict",
+                    format!(
+                        "Precondition failed: UID in precondition: {}, UID in object meta: {}",
+                        uid,
+                        actual.unwrap()
+                    ),
+                );
+            }
+        }
+        let orphan = get_str(&options, "/propagationPolicy") == Some("Orphan");
+        match self.delete_resource(key, orphan) {
+            Some(resource) => json_response(StatusCode::OK, &resource),
+            None => not_found(path),
+        }
+    }
+
+    /// Persists an update to an existing resource, taking care of the fields that are managed by the api server
+    fn update(&mut self, key: ResourceKey, existing: &Value, mut updated: Value) -> Response<Body> {
+        if !updated.is_object() {
+            return status_response(
+                StatusCode::BAD_REQUEST,
+                "BadRequest",
+                "resource must be a json object".to_owned(),
+            );
+        }
+        if let Some(message) = immutable_field_error(existing, &updated) {
+            return status_response(StatusCode::UNPROCESSABLE_ENTITY, "Invalid", message);
+        }
+        let generation = existing
+            .pointer("/metadata/generation")
+            .and_then(Value::as_i64)
+            .unwrap_or(1);
+        let generation = if spec_changed(existing, &updated) {
+            generation + 1
+        } else {
+            generation
+        };
+        let resource_version = self.next_resource_version();
+        {
+            let meta = metadata_mut(&mut updated);
+            for field in &[
+                "name",
+                "namespace",
+                "uid",
+                "creationTimestamp",
+                "deletionTimestamp",
+            ] {
+                match existing.pointer(&format!("/metadata/{}", field)) {
+                    Some(value) => meta.insert((*field).to_owned(), value.clone()),
+                    None => meta.remove(*field),
+                };
+            }
+            meta.insert("generation".to_owned(), generation.into());
+            meta.insert("resourceVersion".to_owned(), resource_version.into());
+        }
+        let type_key = key.type_key.clone();
+        if type_key == CRD_TYPE_KEY {
+            set_established(&mut updated);
+        }
+        if is_deleting(&updated) && !has_finalizers(&updated) {
+            self.resources.insert(key.clone(), updated);
+            let deleted = self.delete_resource(key, false).unwrap();
+            return json_response(StatusCode::OK, &deleted);
+        }
+        self.resources.insert(key, updated.clone());
+        self.record_event(type_key.as_str(), "MODIFIED", Some(existing), &updated);
+        json_response(StatusCode::OK, &updated)
+    }
+
+    /// Deletes the resource, or sets the deletionTimestamp if it has any finalizers. Also deletes any resources
+    /// that are owned by deleted resources, unless `orphan` is true.
+    fn delete_resource(&mut self, key: ResourceKey, orphan: bool) -> Option<Value> {
+        let mut first = None;
+        let mut to_delete = vec![key];
+        while let Some(key) = to_delete.pop() {
+            let mut resource = match self.resources.remove(&key) {
+                Some(r) => r,
+                None => continue,
+            };
+            let resource_version = self.next_resource_version();
+            if has_finalizers(&resource) {
+                if !is_deleting(&resource) {
+                    let meta = metadata_mut(&mut resource);
+                    meta.insert(
+                        "deletionTimestamp".to_owned(),
+                        format_micro_time(SystemTime::now()).into(),
+                    );
+                    meta.insert("resourceVersion".to_owned(), resource_version.into());
+                    self.record_event(key.type_key.as_str(), "MODIFIED", None, &resource);
+                }
+                self.resources.insert(key, resource.clone());
+            } else {
+                metadata_mut(&mut resource)
+                    .insert("resourceVersion".to_owned(), resource_version.into());
+                self.record_event(key.type_key.as_str(), "DELETED", None, &resource);
+                if !orphan {
+                    to_delete.extend(self.dependents_of(&key, &resource));
+                }
+            }
+            if first.is_none() {
+                first = Some(resource);
+            }
+        }
+        first
+    }
+
+    /// Returns the keys of all the resources that should be garbage collected when the given resource is deleted
+    fn dependents_of(&self, key: &ResourceKey, resource: &Value) -> Vec<ResourceKey> {
+        let uid = get_str(resource, "/metadata/uid").unwrap_or("");
+        let is_namespace = key.type_key == NAMESPACE_TYPE_KEY;
+        self.resources
+            .iter()
+            .filter(|(dependent_key, dependent)| {
+                let in_namespace = is_namespace && dependent_key.namespace == key.name;
+                let owned = dependent
+                    .pointer("/metadata/ownerReferences")
+                    .and_then(Value::as_array)
+                    .map(|refs| refs.iter().any(|r| get_str(r, "/uid") == Some(uid)))
+                    .unwrap_or(false);
+                in_namespace || owned
+            })
+            .map(|(dependent_key, _)| dependent_key.clone())
+            .collect()
+    }
+
+    fn next_resource_version(&mut self) -> String {
+        self.resource_version += 1;
+        self.resource_version.to_string()
+    }
+
+    fn record_event(
+        &mut self,
+        type_key: &str,
+        event_type: &'static str,
+        previous: Option<&Value>,
+        resource: &Value,
+    ) {
+        self.watchers.retain(|watcher| {
+            match watcher.event_type(type_key, event_type, previous, resource) {
+                Some(event_type) => watcher
+                    .sender
+                    .unbounded_send(watch_line(event_type, resource))
+                    .is_ok(),
+                None => true,
+            }
+        });
+        self.events.push(StoredEvent {
+            resource_version: self.resource_version,
+            type_key: type_key.to_owned(),
+            event_type,
+            previous: previous.cloned(),
+            resource: resource.clone(),
+        });
+    }
+}
+
+fn watch_line(event_type: &str, resource: &Value) -> Bytes {
+    let event = json!({
+        "type": event_type,
+        "object": resource,
+    });
+    let mut line = serde_json::to_vec(&event).unwrap();
+    line.push(b'\n');
+    Bytes::from(line)
+}
+
+fn get_str<'a>(value: &'a Value, pointer: &str) -> Option<&'a str> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+fn metadata_mut(resource: &mut Value) -> &mut JsonObject {
+    let obj = resource
+        .as_object_mut()
+        .expect("resource must be an object");
+    let meta = obj
+        .entry("metadata")
+        .or_insert_with(|| Value::Object(JsonObject::new()));
+    if !meta.is_object() {
+        *meta = Value::Object(JsonObject::new());
+    }
+    meta.as_object_mut().unwrap()
+}
+
+fn is_deleting(resource: &Value) -> bool {
+    get_str(resource, "/metadata/deletionTimestamp").is_some()
+}
+
+fn has_finalizers(resource: &Value) -> bool {
+    resource
+        .pointer("/metadata/finalizers")
+        .and_then(Value::as_array)
+        .map(|f| !f.is_empty())
+        .unwrap_or(false)
+}
+
+/// CustomResourceDefinitions are established immediately, since the fake api server accepts any resource type anyway
+fn set_established(crd: &mut Value) {
+    crd["status"] = json!({
+        "conditions": [
+            {"type": "NamesAccepted", "status": "True"},
+            {"type": "Established", "status": "True"},
+        ],
+    });
+}
+
+/// returns true if anything other than the metadata or status has changed
+fn spec_changed(existing: &Value, updated: &Value) -> bool {
+    let without_meta_and_status = |value: &Value| {
+        let mut obj = value.as_object().cloned().unwrap_or_default();
+        obj.remove("metadata");
+        obj.remove("status");
+        obj
+    };
+    without_meta_and_status(existing) != without_meta_and_status(updated)
+}
+
+/// returns `resource` with the status replaced by the status from `status_source`
+fn with_status_from(mut resource: Value, status_source: &Value) -> Value {
+    if let Some(obj) = resource.as_object_mut() {
+        match status_source.get("status") {
+            Some(status) => obj.insert("status".to_owned(), status.clone()),
+            None => obj.remove("status"),
+        };
+    }
+    resource
+}
+
+fn check_resource_version(existing: &Value, update: &Value) -> Option<Response<Body>> {
+    let expected = get_str(update, "/metadata/resourceVersion")?;
+    let actual = get_str(existing, "/metadata/resourceVersion").unwrap_or("");
+    if expected != actual {
+        Some(status_response(
+            StatusCode::CONFLICT,
+            "Conflict",
+            "the object has been modified; please apply your changes to the latest version and try again".to_owned(),
+        ))
+    } else {
+        None
+    }
+}
+
+fn parse_resource(body: &[u8]) -> Result<Value, String> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(value) if value.is_object() => Ok(value),
+        Ok(_) => Err("resource must be a json object".to_owned()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Applies a JSON merge patch (RFC 7386). If `merge_named_arrays` is true, then arrays of objects that
+/// all have a `name` will be merged by name, which approximates strategic merge patches
+fn merge_patch(target: &mut Value, patch: &Value, merge_named_arrays: bool) {
+    match patch {
+        Value::Object(patch_obj) => {
+            if !target.is_object() {
+                *target = Value::Object(JsonObject::new());
+            }
+            let target_obj = target.as_object_mut().unwrap();
+            for (key, value) in patch_obj.iter() {
+                if value.is_null() {
+                    target_obj.remove(key);
+                } else {
+                    let target_value = target_obj.entry(key.as_str()).or_insert(Value::Null);
+                    merge_patch(target_value, value, merge_named_arrays);
+                }
+            }
+        }
+        Value::Array(patch_items) if merge_named_arrays && is_named_array(target, patch_items) => {
+            let target_items = target.as_array_mut().unwrap();
+            for patch_item in patch_items.iter() {
+                let name = patch_item.get("name");
+                match target_items.iter_mut().find(|t| t.get("name") == name) {
+                    Some(existing) => merge_patch(existing, patch_item, merge_named_arrays),
+                    None => target_items.push(patch_item.clone()),
+                }
+            }
+        }
+        other => {
+            *target = other.clone();
+        }
+    }
+}
+
+fn is_named_array(target: &Value, patch_items: &[Value]) -> bool {
+    let has_name = |v: &Value| v.get("name").map(Value::is_string).unwrap_or(false);
+    target
+        .as_array()
+        .map(|items| items.iter().all(has_name))
+        .unwrap_or(false)
+        && patch_items.iter().all(has_name)
+}
+
+/// Applies a JSON patch (RFC 6902). Only the `add`, `replace`, `remove`, and `test` operations are supported.
+fn apply_json_patch(target: &mut Value, patch: &Value) -> Result<(), String> {
+    let ops = patch
+        .as_array()
+        .ok_or_else(|| "json patch must be an array".to_owned())?;
+    for op in ops {
+        let op_name = get_str(op, "/op").ok_or_else(|| "missing op".to_owned())?;
+        let path = op
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "missing path".to_owned())?;
+        let value = op.get("value").cloned().unwrap_or(Value::Null);
+        match op_name {
+            "test" => {
+                if target.pointer(path) != Some(&value) {
+                    return Err(format!("test operation failed for path: {}", path));
+                }
+            }
+            "add" | "replace" | "remove" => {
+                if path.is_empty() {
+                    *target = value;
+                    continue;
+                }
+                let split = path.rfind('/').unwrap_or(0);
+                let (parent_path, last) = (&path[..split], &path[(split + 1)..]);
+                let last = last.replace("~1", "/").replace("~0", "~");
+                let parent = target
+                    .pointer_mut(parent_path)
+                    .ok_or_else(|| format!("path does not exist: {}", parent_path))?;
+                match parent {
+                    Value::Object(obj) => {
+                        if op_name == "remove" {
+                            obj.remove(&last)
+                                .ok_or_else(|| format!("path does not exist: {}", path))?;
+                        } else if op_name == "replace" && !obj.contains_key(&last) {
+                            return Err(format!("path does not exist: {}", path));
+                        } else {
+                            obj.insert(last, value);
+                        }
+                    }
+                    Value::Array(items) => {
+                        let index = if last == "-" {
+                            items.len()
+                        } else {
+                            last.parse::<usize>()
+                                .map_err(|_| format!("invalid array index: {}", path))?
+                        };
+                        match op_name {
+                            "add" if index <= items.len() => items.insert(index, value),
+                            "replace" if index < items.len() => items[index] = value,
+                            "remove" if index < items.len() => {
+                                items.remove(index);
+                            }
+                            _ => return Err(format!("array index out of bounds: {}", path)),
+                        }
+                    }
+                    _ => return Err(format!("path does not exist: {}", path)),
+                }
+            }
+            other => return Err(format!("unsupported json patch operation: {}", other)),
+        }
+    }
+    Ok(())
+}
+
+fn not_found(path: &ResourcePath) -> Response<Body> {
+    status_response(
+        StatusCode::NOT_FOUND,
+        "NotFound",
+        format!(
+            "{} \"{}\" not found",
+            path.type_key,
+            path.name.clone().unwrap_or_default()
+        ),
+    )
+}
+
+/// Returns the error message if the update changes one of the few immutable fields that are checked here
+fn immutable_field_error(existing: &Value, updated: &Value) -> Option<String> {
+    let is_immutable = existing.get("immutable").and_then(Value::as_bool) == Some(true);
+    if is_immutable && existing.get("data") != updated.get("data") {
+        return Some("data: Forbidden: field is immutable when `immutable` is set".to_owned());
+    }
+    let cluster_ip = existing.pointer("/spec/clusterIP");
+    match (
+        cluster_ip.and_then(Value::as_str),
+        updated.pointer("/spec/clusterIP"),
+    ) {
+        (Some(ip), Some(updated_ip)) if !ip.is_empty() && Some(updated_ip) != cluster_ip => {
+            Some(format!(
+                "spec.clusterIP: Invalid value: {}: field is immutable",
+                updated_ip
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn status_response(code: StatusCode, reason: &str, message: String) -> Response<Body> {
+    let status = json!({
+        "apiVersion": "v1",
+        "kind": "Status",
+        "metadata": {},
+        "status": "Failure",
+        "message": message,
+        "reason": reason,
+        "code": code.as_u16(),
+    });
+    json_response(code, &status)
+}
+
+fn json_response(code: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MergeStrategy;
+    use crate::k8s_types::core::v1::ConfigMap;
+    use crate::resource::ObjectIdRef;
+    use crate::runner::client::{Client, DeleteOptions, Patch, WatchEvent};
+    use crate::runner::metrics::Metrics;
+
+    fn setup() -> (FakeApiServer, Client, tokio::runtime::Runtime) {
+        let server = FakeApiServer::start().expect("failed to start server");
+        let client = Client::new(
+            server.client_config("test"),
+            Metrics::new().client_metrics(),
+        )
+        .expect("failed to create client");
+        let runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        (server, client, runtime)
+    }
+
+    fn config_map(name: &str, labels: Value) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "namespace": "ns",
+                "name": name,
+                "labels": labels,
+            },
+            "data": {
+                "foo": "bar",
+            }
+        })
+    }
+
+    #[test]
+    fn parses_resource_paths() {
+        let expected = ResourcePath {
+            type_key: "apps/v1/deployments".to_owned(),
+            namespace: Some("ns".to_owned()),
+            name: Some("foo".to_owned()),
+            status: true,
+        };
+        assert_eq!(
+            Some(expected),
+            ResourcePath::parse("/apis/apps/v1/namespaces/ns/deployments/foo/status")
+        );
+        let expected = ResourcePath {
+            type_key: "v1/namespaces".to_owned(),
+            namespace: None,
+            name: Some("ns".to_owned()),
+            status: false,
+        };
+        assert_eq!(Some(expected), ResourcePath::parse("/api/v1/namespaces/ns"));
+        assert_eq!(None, ResourcePath::parse("/healthz"));
+    }
+
+    #[test]
+    fn creates_updates_and_lists_resources() {
+        let (_server, client, mut runtime) = setup();
+        runtime.block_on(async move {
+            let id = ObjectIdRef::new("ns", "one");
+            client
+                .create_resource(ConfigMap, &config_map("one", json!({"a": "b"})))
+                .await
+                .expect("failed to create");
+            client
+                .create_resource(ConfigMap, &config_map("two", json!({"a": "c"})))
+                .await
+                .expect("failed to create");
+            let err = client
+                .create_resource(ConfigMap, &config_map("one", json!({})))
+                .await
+                .unwrap_err();
+            assert!(err.is_http_status(409));
+
+            let created = client.get_resource(ConfigMap, &id).await.unwrap().unwrap();
+            assert!(get_str(&created, "/metadata/uid").is_some());
+            assert_eq!(
+                Some(1),
+                created
+                    .pointer("/metadata/generation")
+                    .and_then(Value::as_i64)
+            );
+
+            let mut stale = created.clone();
+            stale["metadata"]["resourceVersion"] = "1".into();
+            stale["data"]["foo"] = "baz".into();
+            let patch = Patch::new(MergeStrategy::JsonMerge, json!({"data": {"foo": "baz"}}));
+            client.patch_resource(ConfigMap, &id, &patch).await.unwrap();
+            let err = client
+                .replace_resource(ConfigMap, &id, &stale)
+                .await
+                .unwrap_err();
+            assert!(err.is_http_status(409));
+
+            let updated = client.get_resource(ConfigMap, &id).await.unwrap().unwrap();
+            assert_eq!("baz", updated["data"]["foo"]);
+            assert_eq!(
+                Some(2),
+                updated
+                    .pointer("/metadata/generation")
+                    .and_then(Value::as_i64)
+            );
+
+            let list = client
+                .list_all(ConfigMap, Some("ns"), Some("a=c"), None)
+                .await
+                .unwrap();
+            assert_eq!(1, list.items.len());
+            assert_eq!("two", list.items[0]["metadata"]["name"]);
+
+            let list = client
+                .list_all(ConfigMap, Some("ns"), None, Some("metadata.name!=two"))
+                .await
+                .unwrap();
+            assert!(list
+                .items
+                .iter()
+                .all(|item| item["metadata"]["name"] != "two"));
+        });
+    }
+
+    #[test]
+    fn deletes_resources_with_finalizers_and_garbage_collects_dependents() {
+        let (_server, client, mut runtime) = setup();
+        runtime.block_on(async move {
+            let owner_id = ObjectIdRef::new("ns", "owner");
+            let mut owner = config_map("owner", json!({}));
+            owner["metadata"]["finalizers"] = json!(["test-finalizer"]);
+            client.create_resource(ConfigMap, &owner).await.unwrap();
+            let owner = client
+                .get_resource(ConfigMap, &owner_id)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let mut dependent = config_map("dependent", json!({}));
+            dependent["metadata"]["ownerReferences"] = json!([{
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "name": "owner",
+                "uid": owner["metadata"]["uid"],
+            }]);
+            client.create_resource(ConfigMap, &dependent).await.unwrap();
+
+            client
+                .delete_resource(ConfigMap, &owner_id, &DeleteOptions::default())
+                .await
+                .unwrap();
+            let deleting = client
+                .get_resource(ConfigMap, &owner_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(is_deleting(&deleting));
+
+            let patch = Patch::new(
+                MergeStrategy::JsonMerge,
+                json!({"metadata": {"finalizers": []}}),
+            );
+            client
+                .patch_resource(ConfigMap, &owner_id, &patch)
+                .await
+                .unwrap();
+
+            assert!(client
+                .get_resource(ConfigMap, &owner_id)
+                .await
+                .unwrap()
+                .is_none());
+            let dependent_id = ObjectIdRef::new("ns", "dependent");
+            assert!(client
+                .get_resource(ConfigMap, &dependent_id)
+                .await
+                .unwrap()
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn delete_honors_uid_precondition_and_orphan_propagation() {
+        use crate::config::PropagationPolicy;
+
+        let (_server, client, mut runtime) = setup();
+        runtime.block_on(async move {
+            let owner_id = ObjectIdRef::new("ns", "owner");
+            client
+                .create_resource(ConfigMap, &config_map("owner", json!({})))
+                .await
+                .unwrap();
+            let owner = client
+                .get_resource(ConfigMap, &owner_id)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut dependent = config_map("dependent", json!({}));
+            dependent["metadata"]["ownerReferences"] = json!([{
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "name": "owner",
+                "uid": owner["metadata"]["uid"],
+            }]);
+            client.create_resource(ConfigMap, &dependent).await.unwrap();
+
+            // the 409 from a failed precondition is treated as success by the client
+            let options = DeleteOptions::default().uid_precondition("not-the-uid");
+            client
+                .delete_resource(ConfigMap, &owner_id, &options)
+                .await
+                .unwrap();
+            assert!(client
+                .get_resource(ConfigMap, &owner_id)
+                .await
+                .unwrap()
+                .is_some());
+
+            let uid = owner["metadata"]["uid"].as_str().unwrap();
+            let options =
+                DeleteOptions::new(Some(PropagationPolicy::Orphan), Some(0)).uid_precondition(uid);
+            client
+                .delete_resource(ConfigMap, &owner_id, &options)
+                .await
+                .unwrap();
+            assert!(client
+                .get_resource(ConfigMap, &owner_id)
+                .await
+                .unwrap()
+                .is_none());
+            assert!(client
+                .get_resource(ConfigMap, &ObjectIdRef::new("ns", "dependent"))
+                .await
+                .unwrap()
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn updates_to_immutable_fields_are_rejected() {
+        let (_server, client, mut runtime) = setup();
+        runtime.block_on(async move {
+            let id = ObjectIdRef::new("ns", "frozen");
+            let mut frozen = config_map("frozen", json!({}));
+            frozen["immutable"] = json!(true);
+            client.create_resource(ConfigMap, &frozen).await.unwrap();
+
+            let patch = Patch::new(MergeStrategy::JsonMerge, json!({"data": {"foo": "baz"}}));
+            let err = client
+                .patch_resource(ConfigMap, &id, &patch)
+                .await
+                .unwrap_err();
+            assert!(err.is_http_status(422));
+            assert!(err.is_immutable_field());
+
+            // other changes are still allowed
+            let patch = Patch::new(
+                MergeStrategy::JsonMerge,
+                json!({"metadata": {"labels": {"a": "b"}}}),
+            );
+            client.patch_resource(ConfigMap, &id, &patch).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn watch_returns_events_after_resource_version() {
+        let (_server, client, mut runtime) = setup();
+        runtime.block_on(async move {
+            client
+                .create_resource(ConfigMap, &config_map("before", json!({"watched": "true"})))
+                .await
+                .unwrap();
+            let list = client
+                .list_all(ConfigMap, None, Some("watched"), None)
+                .await
+                .unwrap();
+            let resource_version = list.metadata.resource_version.unwrap();
+
+            let mut events = client
+                .watch(
+                    ConfigMap,
+                    None,
+                    Some(resource_version.as_str()),
+                    Some("watched"),
+                    None,
+                )
+                .await
+                .unwrap();
+            client
+                .create_resource(ConfigMap, &config_map("ignored", json!({})))
+                .await
+                .unwrap();
+            client
+                .create_resource(ConfigMap, &config_map("after", json!({"watched": "true"})))
+                .await
+                .unwrap();
+            client
+                .delete_resource(
+                    ConfigMap,
+                    &ObjectIdRef::new("ns", "before"),
+                    &DeleteOptions::default(),
+                )
+                .await
+                .unwrap();
+
+            match events.next().await.unwrap().unwrap() {
+                WatchEvent::Added(resource) => assert_eq!("after", resource["metadata"]["name"]),
+                _ => panic!("expected an ADDED event"),
+            }
+            match events.next().await.unwrap().unwrap() {
+                WatchEvent::Deleted(resource) => assert_eq!("before", resource["metadata"]["name"]),
+                _ => panic!("expected a DELETED event"),
+            }
+        });
+    }
+
+    #[test]
+    fn applies_json_patches() {
+        let mut target = json!({"spec": {"ports": [1, 2]}, "metadata": {"labels": {}}});
+        let patch = json!([
+            {"op": "add", "path": "/metadata/labels/foo~1bar", "value": "baz"},
+            {"op": "replace", "path": "/spec/ports/0", "value": 3},
+            {"op": "add", "path": "/spec/ports/-", "value": 4},
+            {"op": "remove", "path": "/spec/ports/1"},
+        ]);
+        apply_json_patch(&mut target, &patch).expect("failed to apply patch");
+        let expected =
+            json!({"spec": {"ports": [3, 4]}, "metadata": {"labels": {"foo/bar": "baz"}}});
+        assert_eq!(expected, target);
+    }
+}
@@ -0,0 +1,109 @@
+//! Shared setup for the tests that run the whole operator against a `FakeApiServer`. These tests live next to the
+//! features that they cover, and this module keeps them from each repeating the same parent type and boilerplate.
+use crate::config::OperatorConfig;
+use crate::handler::{AsyncHandler, SyncResponse};
+use crate::k8s_types::core::v1::Event;
+use crate::k8s_types::K8sType;
+use crate::resource::ObjectIdRef;
+use crate::runner::testkit::TestKit;
+
+use serde_json::{json, Value};
+
+use std::time::{Duration, Instant};
+
+pub(crate) const OPERATOR_NAME: &str = "fake-api-server-test";
+
+/// How long tests wait for the operator to do something before failing
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) static PARENT_TYPE: &K8sType = &K8sType {
+    api_version: "example.com/v1",
+    kind: "Parent",
+    plural_kind: "parents",
+};
+
+pub(crate) fn operator_config() -> OperatorConfig {
+    OperatorConfig::new(OPERATOR_NAME, PARENT_TYPE)
+}
+
+/// Starts a `TestKit` that runs the operator against a new `FakeApiServer`
+pub(crate) fn start(operator_config: OperatorConfig, handler: impl AsyncHandler) -> TestKit {
+    TestKit::with_fake_api_server(operator_config, handler).expect("failed to create testkit")
+}
+
+pub(crate) fn parent(namespace: &str, name: &str) -> Value {
+    json!({
+        "apiVersion": PARENT_TYPE.api_version,
+        "kind": PARENT_TYPE.kind,
+        "metadata": {
+            "namespace": namespace,
+            "name": name,
+        },
+    })
+}
+
+/// Returns a resource of one of the core `v1` kinds, which is what the tests use for children
+pub(crate) fn v1_resource(kind: &str, namespace: Option<&str>, name: &str) -> Value {
+    let mut resource = json!({
+        "apiVersion": "v1",
+        "kind": kind,
+        "metadata": {
+            "name": name,
+        },
+    });
+    if let Some(namespace) = namespace {
+        resource["metadata"]["namespace"] = json!(namespace);
+    }
+    resource
+}
+
+/// A `SyncResponse` with the given children, an empty status, and no resync
+pub(crate) fn response(children: Vec<Value>) -> SyncResponse {
+    SyncResponse {
+        status: json!({}),
+        children,
+        resync: None,
+    }
+}
+
+pub(crate) fn get_resource(
+    testkit: &mut TestKit,
+    k8s_type: &K8sType,
+    namespace: &str,
+    name: &str,
+) -> Option<Value> {
+    testkit
+        .get_resource_from_api_server(k8s_type, &ObjectIdRef::new(namespace, name))
+        .expect("failed to get resource")
+}
+
+/// Returns all of the Events in the given namespace
+pub(crate) fn list_events(testkit: &mut TestKit, namespace: &str) -> Vec<Value> {
+    let TestKit {
+        ref client,
+        ref mut runtime,
+        ..
+    } = *testkit;
+    runtime
+        .block_on(async { client.list_all(Event, Some(namespace), None, None).await })
+        .expect("failed to list events")
+        .items
+}
+
+/// Runs the reconciliation loop until the `condition` returns true, and panics with the `description` if that doesn't
+/// happen before the `TIMEOUT`. Reconciliation errors are ignored, since some tests expect them.
+pub(crate) fn eventually(
+    testkit: &mut TestKit,
+    description: &str,
+    mut condition: impl FnMut(&mut TestKit) -> bool,
+) {
+    let start = Instant::now();
+    while !condition(testkit) {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "timed out waiting for: {}",
+            description
+        );
+        testkit.reconcile(Duration::from_millis(200)).ok();
+    }
+}
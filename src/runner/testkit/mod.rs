@@ -17,18 +17,33 @@
 //!
 //! Here, name corresponds to the filename that's used under the `tests/` directory, which is the usual place
 //! for integration tests.
+//!
+//! Tests can also be run without a Kubernetes cluster by using `TestKit::with_fake_api_server`, which runs the
+//! operator against an in-memory `FakeApiServer`. The fake api server doesn't validate resources, and it doesn't run
+//! any other controllers, so it's best suited to tests that only involve the parent and child resources of your operator.
+mod fake_api_server;
+#[cfg(test)]
+pub(crate) mod fixture;
+
+pub use self::fake_api_server::FakeApiServer;
+
 use crate::{
     config::{ClientConfig, OperatorConfig},
-    handler::{FinalizeResponse, Handler, SyncRequest, SyncResponse},
+    handler::{AsyncHandler, FinalizeResponse, SyncRequest, SyncResponse},
     k8s_types::K8sType,
     resource::{K8sResource, ObjectId, ObjectIdRef},
     runner::{
-        client::Client, create_operator_state, metrics::Metrics, reconcile::compare, HandlerRef,
-        OperatorState,
+        client::{Client, DeleteOptions},
+        crd_install, create_operator_state,
+        metrics::Metrics,
+        reconcile::compare,
+        sync_queue::SyncQueue,
+        HandlerRef, OperatorState, ShutdownSignal,
     },
 };
 
 use anyhow::Error;
+use futures::future::BoxFuture;
 use serde::Serialize;
 use serde_json::Value;
 use tokio::runtime::Runtime;
@@ -57,11 +72,13 @@ pub struct TestKit {
     instrumented_handler: InstrumentedHandler,
     runtime: Runtime,
     client: Client,
-    parents_needing_sync: HashSet<String>,
+    parents_needing_sync: SyncQueue,
     delete_namespace_on_drop: bool,
     namespace: Option<String>,
     parents: HashSet<ObjectId>,
     cleanup_timeout: Duration,
+    // declared last so that the server is only shutdown after the operator's runtime
+    fake_api_server: Option<FakeApiServer>,
 }
 
 impl Debug for TestKit {
@@ -71,6 +88,7 @@ impl Debug for TestKit {
             .field("instrumented_handler", &self.instrumented_handler)
             .field("client", &self.client)
             .field("parents_needing_sync", &self.parents_needing_sync)
+            .field("fake_api_server", &self.fake_api_server)
             .finish()
     }
 }
@@ -110,7 +128,11 @@ impl Drop for TestKit {
                 let id = ObjectIdRef::new("", ns);
                 let result = runtime.block_on(async {
                     client
-                        .delete_resource(crate::k8s_types::core::v1::Namespace, &id)
+                        .delete_resource(
+                            crate::k8s_types::core::v1::Namespace,
+                            &id,
+                            &DeleteOptions::default(),
+                        )
                         .await
                 });
                 if let Err(err) = result {
@@ -128,7 +150,7 @@ impl TestKit {
         namespace: impl Into<String>,
         operator_config: OperatorConfig,
         client_config: ClientConfig,
-        handler: impl Handler,
+        handler: impl AsyncHandler,
     ) -> Result<TestKit, Error> {
         let ns = namespace.into();
         let mut testkit = TestKit::create(
@@ -175,7 +197,7 @@ impl TestKit {
     pub fn create(
         operator_config: OperatorConfig,
         client_config: ClientConfig,
-        handler: impl Handler,
+        handler: impl AsyncHandler,
     ) -> Result<TestKit, Error> {
         let metrics = Metrics::new();
         let client = Client::new(client_config, metrics.client_metrics())?;
@@ -186,6 +208,10 @@ impl TestKit {
             .basic_scheduler()
             .build()?;
 
+        if let Some(crd) = operator_config.crd.as_ref() {
+            runtime.block_on(crd_install::install_crd(&client, crd))?;
+        }
+
         let executor = runtime.handle().clone();
         let operator_client = client.clone();
         let state = runtime.block_on(async move {
@@ -193,6 +219,7 @@ impl TestKit {
                 executor,
                 metrics,
                 Arc::new(AtomicBool::new(true)),
+                Arc::new(ShutdownSignal::default()),
                 operator_config,
                 operator_client,
             )
@@ -208,13 +235,32 @@ impl TestKit {
             runtime,
             client,
             namespace,
-            parents_needing_sync: HashSet::new(),
+            parents_needing_sync: SyncQueue::new(),
             delete_namespace_on_drop: false,
             parents: HashSet::new(),
             cleanup_timeout: Duration::from_secs(10),
+            fake_api_server: None,
         })
     }
 
+    /// Creates a new `TestKit` for the given `OperatorConfig` and `Handler`, which runs against a new, empty
+    /// `FakeApiServer` instead of a real Kubernetes cluster. The fake api server is shutdown when the testkit is dropped.
+    pub fn with_fake_api_server(
+        operator_config: OperatorConfig,
+        handler: impl AsyncHandler,
+    ) -> Result<TestKit, Error> {
+        let server = FakeApiServer::start()?;
+        let client_config = server.client_config(operator_config.operator_name.as_str());
+        let mut testkit = TestKit::create(operator_config, client_config, handler)?;
+        testkit.fake_api_server = Some(server);
+        Ok(testkit)
+    }
+
+    /// Returns the `FakeApiServer` that this testkit is running against, if it was created using `with_fake_api_server`
+    pub fn fake_api_server(&self) -> Option<&FakeApiServer> {
+        self.fake_api_server.as_ref()
+    }
+
     /// Creates the given parent resource in the kubernetes cluster, and then runs the reconciliation loop
     /// with the given timeout. Panics if the resource could not be created or if reconciliation fails
     pub fn create_parent(&mut self, resource: impl ToJson, reconciliation_timeout: Duration) {
@@ -537,7 +583,11 @@ impl TestKit {
             ref mut runtime,
             ..
         } = *self;
-        runtime.block_on(async { client.delete_resource(k8s_type, id).await })?;
+        runtime.block_on(async {
+            client
+                .delete_resource(k8s_type, id, &DeleteOptions::default())
+                .await
+        })?;
         Ok(())
     }
 
@@ -660,7 +710,7 @@ impl Debug for InstrumentedHandler {
 }
 
 impl InstrumentedHandler {
-    fn wrap(wrapped: impl Handler) -> (InstrumentedHandler, HandlerRef) {
+    fn wrap(wrapped: impl AsyncHandler) -> (InstrumentedHandler, HandlerRef) {
         let handler_ref = Arc::new(wrapped);
         let handler = InstrumentedHandler {
             wrapped: handler_ref,
@@ -699,48 +749,45 @@ impl InstrumentedHandler {
     }
 }
 
-impl Handler for InstrumentedHandler {
-    fn sync(&self, req: &SyncRequest) -> Result<SyncResponse, Error> {
-        let InstrumentedHandler {
-            ref wrapped,
-            ref records,
-        } = self;
-
+impl InstrumentedHandler {
+    fn record<F: FnOnce(&mut SyncRecord)>(&self, req: &SyncRequest, fun: F) {
         let parent_id = req.parent.get_object_id().to_owned();
-        let mut records_lock = records.write().unwrap();
-        let record = records_lock
-            .entry(parent_id)
-            .or_insert_with(SyncRecord::default);
-        record.sync_started(req);
-
-        let result = wrapped.sync(req);
-        record.sync_finished(&result);
-        result
+        let mut records_lock = self.records.write().unwrap();
+        let record = records_lock.entry(parent_id).or_default();
+        fun(record);
     }
+}
 
-    fn finalize(&self, req: &SyncRequest) -> Result<FinalizeResponse, Error> {
-        let InstrumentedHandler {
-            ref wrapped,
-            ref records,
-        } = self;
+impl AsyncHandler for InstrumentedHandler {
+    fn sync(
+        self: Arc<Self>,
+        req: Arc<SyncRequest>,
+    ) -> BoxFuture<'static, Result<SyncResponse, Error>> {
+        Box::pin(async move {
+            self.record(&req, |record| record.sync_started(&req));
+            let result = self.wrapped.clone().sync(req.clone()).await;
+            self.record(&req, |record| record.sync_finished(&result));
+            result
+        })
+    }
 
-        let parent_id = req.parent.get_object_id().to_owned();
-        let mut records_lock = records.write().unwrap();
-        let record = records_lock
-            .entry(parent_id)
-            .or_insert_with(SyncRecord::default);
-        record.finalize_started(req);
-
-        let result = wrapped.finalize(req);
-        record.finalize_finished(&result);
-        result
+    fn finalize(
+        self: Arc<Self>,
+        req: Arc<SyncRequest>,
+    ) -> BoxFuture<'static, Result<FinalizeResponse, Error>> {
+        Box::pin(async move {
+            self.record(&req, |record| record.finalize_started(&req));
+            let result = self.wrapped.clone().finalize(req.clone()).await;
+            self.record(&req, |record| record.finalize_finished(&result));
+            result
+        })
     }
 }
 
 // TODO: add some sort of "required_quiet_period" parameter so that we can detect hot-loop scenarios
 async fn do_reconciliation_run(
     state: &mut OperatorState,
-    parents_needing_sync: &mut HashSet<String>,
+    parents_needing_sync: &mut SyncQueue,
     handler: &HandlerRef,
     instrumented_handler: &InstrumentedHandler,
     max_timeout: Duration,
@@ -929,3 +976,37 @@ fn diff_resources(actual: Value, expected: &Value) -> Option<Diff> {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::fixture::*;
+    use crate::config::ChildConfig;
+    use crate::handler::SyncRequest;
+    use crate::k8s_types::core::v1::ConfigMap;
+    use crate::resource::ObjectIdRef;
+
+    #[test]
+    fn testkit_runs_operator_against_fake_api_server() {
+        let operator_config = operator_config().with_child(ConfigMap, ChildConfig::replace());
+        let handler = |req: &SyncRequest| {
+            let mut child = v1_resource("ConfigMap", req.parent.namespace(), "child");
+            child["data"] = req.parent.get("spec").cloned().unwrap_or_default();
+            Ok(response(vec![child]))
+        };
+        let mut testkit = start(operator_config, handler);
+
+        let mut parent = parent("ns", "parent");
+        parent["spec"] = serde_json::json!({"foo": "bar"});
+        testkit.create_parent(parent, TIMEOUT);
+        let child_id = ObjectIdRef::new("ns", "child");
+        testkit.assert_resource_eq_eventually(
+            ConfigMap,
+            &child_id,
+            serde_json::json!({"data": {"foo": "bar"}}),
+            TIMEOUT,
+        );
+
+        testkit.delete_parent(&ObjectIdRef::new("ns", "parent"), TIMEOUT);
+        testkit.assert_resource_deleted_eventually(ConfigMap, &child_id, TIMEOUT);
+    }
+}
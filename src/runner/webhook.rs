@@ -0,0 +1,811 @@
+//! Serves validating and mutating admission webhooks for the parent type. Each `AdmissionReview` is decoded and
+//! dispatched to `AsyncHandler::validate` or `AsyncHandler::mutate`, and the response is built from the returned
+//! `AdmissionResponse`. The same server also handles `ConversionReview`s at `/convert` using the `Conversion`s from
+//! the `OperatorConfig`. This server is separate from the metrics and health server, since the api server requires
+//! webhooks to be served over HTTPS.
+use crate::config::{Conversion, WebhookConfig, WebhookTlsConfig};
+use crate::handler::{AdmissionRequest, AdmissionResponse, AsyncHandler};
+use crate::k8s_types::K8sType;
+
+use hyper::server::conn::Http;
+use hyper::server::Server;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response};
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+const DEFAULT_REVIEW_API_VERSION: &str = "admission.k8s.io/v1";
+const DEFAULT_CONVERSION_API_VERSION: &str = "apiextensions.k8s.io/v1";
+
+/// How long to wait before accepting more connections after an error
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+pub(crate) async fn start(
+    config: WebhookConfig,
+    parent_type: &'static K8sType,
+    conversions: Vec<Conversion>,
+    handler: Arc<dyn AsyncHandler>,
+) {
+    let address: SocketAddr = ([0u8; 4], config.port).into();
+    log::info!(
+        "Starting admission webhook server on address: {}, tls: {}",
+        address,
+        config.tls.is_some()
+    );
+    let svc = WebhookSvc {
+        parent_type,
+        conversions: Arc::new(conversions),
+        handler,
+    };
+    let result = match config.tls {
+        Some(ref tls) => serve_tls(address, tls, svc).await,
+        None => serve_plain(address, svc).await,
+    };
+    if let Err(err) = result {
+        log::error!("Admission webhook server failed with error: {:?}", err);
+    }
+}
+
+async fn serve_plain(address: SocketAddr, svc: WebhookSvc) -> Result<(), Error> {
+    let service = make_service_fn(move |_| {
+        let svc = svc.clone();
+        async move { Ok::<_, hyper::Error>(service_fn(move |request| svc.clone().serve(request))) }
+    });
+    Server::bind(&address).serve(service).await?;
+    Ok(())
+}
+
+async fn serve_tls(
+    address: SocketAddr,
+    tls: &WebhookTlsConfig,
+    svc: WebhookSvc,
+) -> Result<(), Error> {
+    let acceptor = create_acceptor(tls)?;
+    let mut listener = TcpListener::bind(address).await?;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                // errors like running out of file descriptors are usually temporary, so keep serving after a pause
+                log::error!("Failed to accept webhook connection: {:?}", err);
+                tokio::time::delay_for(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let svc = svc.clone();
+        tokio::spawn(async move {
+            let stream = match tokio_openssl::accept(&acceptor, stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn!("TLS handshake with {} failed: {:?}", peer, err);
+                    return;
+                }
+            };
+            let service = service_fn(move |request| svc.clone().serve(request));
+            if let Err(err) = Http::new().serve_connection(stream, service).await {
+                log::warn!("Error serving webhook connection from {}: {:?}", peer, err);
+            }
+        });
+    }
+}
+
+fn create_acceptor(tls: &WebhookTlsConfig) -> Result<SslAcceptor, Error> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key_file(tls.private_key_path.as_str(), SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(tls.certificate_path.as_str())?;
+    builder.check_private_key()?;
+    Ok(builder.build())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReviewType {
+    Validate,
+    Mutate,
+}
+
+#[derive(Clone)]
+struct WebhookSvc {
+    parent_type: &'static K8sType,
+    conversions: Arc<Vec<Conversion>>,
+    handler: Arc<dyn AsyncHandler>,
+}
+
+impl WebhookSvc {
+    async fn serve(self, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let response = self.handle_request(request).await.unwrap_or_else(|err| {
+            log::error!("Error handling webhook request: {:?}", err);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        });
+        Ok(response)
+    }
+
+    async fn handle_request(&self, request: Request<Body>) -> Result<Response<Body>, Error> {
+        let req_method = request.method().clone();
+        let req_uri = request.uri().clone();
+        log::debug!("Got webhook request {} {}", req_method, req_uri);
+
+        let review_type = match (&req_method, req_uri.path().trim_end_matches('/')) {
+            (&Method::POST, "/validate") => Some(ReviewType::Validate),
+            (&Method::POST, "/mutate") => Some(ReviewType::Mutate),
+            (&Method::POST, "/convert") => None,
+            _ => {
+                let resp = Response::builder().status(404).body(Body::empty())?;
+                return Ok(resp);
+            }
+        };
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+        let review_result = match review_type {
+            Some(review_type) => self.review(review_type, body.as_ref()).await,
+            None => self.convert(body.as_ref()),
+        };
+        let result = match review_result {
+            Ok(review) => Response::builder()
+                .status(200)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&review)?)),
+            Err(message) => {
+                log::error!("Invalid review from {}: {}", req_uri, message);
+                Response::builder().status(400).body(Body::from(message))
+            }
+        };
+        Ok(result?)
+    }
+
+    /// Decodes the `AdmissionReview` and returns the review that should be sent in the response. Returns an error
+    /// only if the review itself is invalid, since errors from the handler are returned as a denial.
+    async fn review(&self, review_type: ReviewType, body: &[u8]) -> Result<Value, String> {
+        let review: Value = serde_json::from_slice(body)
+            .map_err(|err| format!("failed to parse AdmissionReview: {}", err))?;
+        let api_version = review
+            .pointer("/apiVersion")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_REVIEW_API_VERSION)
+            .to_owned();
+        let raw_request = review
+            .get("request")
+            .ok_or_else(|| "AdmissionReview is missing 'request'".to_owned())?;
+        let request_kind = RequestKind::from_request(raw_request);
+        let request: AdmissionRequest = serde_json::from_value(raw_request.clone())
+            .map_err(|err| format!("invalid AdmissionReview request: {}", err))?;
+
+        let result = if !request_kind.is_kind(self.parent_type) {
+            log::warn!(
+                "Allowing AdmissionReview for {} because it is not the parent type: {}",
+                request_kind,
+                self.parent_type
+            );
+            Ok(AdmissionResponse::allow())
+        } else {
+            self.review_parent(review_type, &request_kind, &request)
+                .await
+        };
+        log::debug!(
+            "{:?} {:?} request for {} '{}' completed with result: {:?}",
+            review_type,
+            request.operation,
+            request_kind,
+            request.name.clone().unwrap_or_default(),
+            result
+        );
+        let response = match result {
+            Ok(response) => admission_response(review_type, &request, response),
+            Err(err) => {
+                log::error!(
+                    "Handler returned error for {:?} request: {}, err: {:?}",
+                    review_type,
+                    request.uid,
+                    err
+                );
+                json!({
+                    "uid": request.uid,
+                    "allowed": false,
+                    "status": {
+                        "code": 500,
+                        "message": err.to_string(),
+                    },
+                })
+            }
+        };
+        Ok(json!({
+            "apiVersion": api_version,
+            "kind": "AdmissionReview",
+            "response": response,
+        }))
+    }
+}
+
+impl WebhookSvc {
+    /// Invokes the handler for a request for any version of the parent type. Objects in other versions are converted
+    /// to the version that the handler works with, and the request is denied if that's not possible, so that the
+    /// handler can't be bypassed by using a different version.
+    async fn review_parent(
+        &self,
+        review_type: ReviewType,
+        request_kind: &RequestKind<'_>,
+        request: &AdmissionRequest,
+    ) -> Result<AdmissionResponse, anyhow::Error> {
+        let (_, parent_version) = self.parent_type.as_group_and_version();
+        let is_other_version = request_kind.version != parent_version;
+        let request = if is_other_version {
+            match self.convert_request(request) {
+                Ok(converted) => converted,
+                Err(message) => {
+                    log::error!(
+                        "Denying AdmissionReview for {} because it can't be converted: {}",
+                        request_kind,
+                        message
+                    );
+                    return Ok(AdmissionResponse::deny(message));
+                }
+            }
+        } else {
+            request.clone()
+        };
+        let handler = self.handler.clone();
+        let request = Arc::new(request);
+        let response = match review_type {
+            ReviewType::Validate => handler.validate(request).await?,
+            ReviewType::Mutate => handler.mutate(request).await?,
+        };
+        // the patch refers to fields of the converted object, so it can't be applied to the original one
+        if is_other_version && review_type == ReviewType::Mutate && response.patch.is_some() {
+            return Ok(AdmissionResponse::deny(format!(
+                "{} must be mutated using apiVersion: {}",
+                request_kind, self.parent_type.api_version
+            )));
+        }
+        Ok(response)
+    }
+
+    /// Converts the objects in a request for another version of the parent to the version that the handler works with
+    fn convert_request(&self, request: &AdmissionRequest) -> Result<AdmissionRequest, String> {
+        let mut converted = request.clone();
+        for object in [&mut converted.object, &mut converted.old_object].iter_mut() {
+            if !object.is_null() {
+                **object = self.convert_object(object.take(), self.parent_type.api_version)?;
+            }
+        }
+        Ok(converted)
+    }
+}
+
+impl WebhookSvc {
+    /// Decodes the `ConversionReview` and converts each of the objects to the desired version. If any object
+    /// fails to convert, then the whole review fails.
+    fn convert(&self, body: &[u8]) -> Result<Value, String> {
+        let review: Value = serde_json::from_slice(body)
+            .map_err(|err| format!("failed to parse ConversionReview: {}", err))?;
+        let api_version = review
+            .pointer("/apiVersion")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_CONVERSION_API_VERSION)
+            .to_owned();
+        let uid = review
+            .pointer("/request/uid")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "ConversionReview is missing 'request.uid'".to_owned())?;
+        let desired_api_version = review
+            .pointer("/request/desiredAPIVersion")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "ConversionReview is missing 'request.desiredAPIVersion'".to_owned())?;
+        let objects = review
+            .pointer("/request/objects")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+
+        let result = objects
+            .into_iter()
+            .map(|object| self.convert_object(object, desired_api_version))
+            .collect::<Result<Vec<Value>, String>>();
+        let response = match result {
+            Ok(converted) => json!({
+                "uid": uid,
+                "convertedObjects": converted,
+                "result": {
+                    "status": "Success",
+                },
+            }),
+            Err(message) => {
+                log::error!(
+                    "Failed to convert objects for request: {}, {}",
+                    uid,
+                    message
+                );
+                json!({
+                    "uid": uid,
+                    "convertedObjects": [],
+                    "result": {
+                        "status": "Failure",
+                        "message": message,
+                    },
+                })
+            }
+        };
+        Ok(json!({
+            "apiVersion": api_version,
+            "kind": "ConversionReview",
+            "response": response,
+        }))
+    }
+
+    fn convert_object(&self, object: Value, desired_api_version: &str) -> Result<Value, String> {
+        let api_version = object
+            .pointer("/apiVersion")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_owned();
+        if api_version == desired_api_version {
+            return Ok(object);
+        }
+        let conversion = self
+            .conversions
+            .iter()
+            .find(|c| c.from.api_version == api_version && c.to.api_version == desired_api_version)
+            .ok_or_else(|| {
+                format!(
+                    "no conversion from '{}' to '{}'",
+                    api_version, desired_api_version
+                )
+            })?;
+        conversion.convert(object).map_err(|err| {
+            format!(
+                "failed to convert from '{}' to '{}': {}",
+                api_version, desired_api_version, err
+            )
+        })
+    }
+}
+
+fn admission_response(
+    review_type: ReviewType,
+    request: &AdmissionRequest,
+    response: AdmissionResponse,
+) -> Value {
+    let AdmissionResponse {
+        allowed,
+        reason,
+        patch,
+    } = response;
+    let mut result = json!({
+        "uid": request.uid,
+        "allowed": allowed,
+    });
+    if !allowed {
+        result["status"] = json!({
+            "code": 403,
+            "message": reason.unwrap_or_default(),
+        });
+    }
+    match (review_type, patch) {
+        (ReviewType::Mutate, Some(patch)) if allowed => {
+            let encoded = base64::encode(serde_json::to_vec(&patch).unwrap_or_default());
+            result["patchType"] = Value::String("JSONPatch".to_owned());
+            result["patch"] = Value::String(encoded);
+        }
+        (ReviewType::Validate, Some(_)) => {
+            log::warn!(
+                "Ignoring patch from validate response for request: {}, since patches are only allowed when mutating",
+                request.uid
+            );
+        }
+        _ => {}
+    }
+    result
+}
+
+/// The group, version, and kind from the AdmissionReview request
+struct RequestKind<'a> {
+    group: &'a str,
+    version: &'a str,
+    kind: &'a str,
+}
+
+impl<'a> RequestKind<'a> {
+    fn from_request(request: &'a Value) -> RequestKind<'a> {
+        let get = |pointer: &str| {
+            request
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .unwrap_or("")
+        };
+        RequestKind {
+            group: get("/kind/group"),
+            version: get("/kind/version"),
+            kind: get("/kind/kind"),
+        }
+    }
+
+    /// Returns true if this is any version of the given type
+    fn is_kind(&self, k8s_type: &K8sType) -> bool {
+        let (group, _) = k8s_type.as_group_and_version();
+        self.group == group && self.kind == k8s_type.kind
+    }
+}
+
+impl<'a> std::fmt::Display for RequestKind<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.group.is_empty() {
+            write!(f, "{}/{}", self.version, self.kind)
+        } else {
+            write!(f, "{}/{}/{}", self.group, self.version, self.kind)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handler::{Handler, SyncRequest, SyncResponse};
+
+    static PARENT_TYPE: &K8sType = &K8sType {
+        api_version: "example.com/v1",
+        kind: "Parent",
+        plural_kind: "parents",
+    };
+
+    struct AdmissionHandler;
+    impl Handler for AdmissionHandler {
+        fn sync(&self, _request: &SyncRequest) -> Result<SyncResponse, anyhow::Error> {
+            Ok(SyncResponse::new(Value::Null))
+        }
+
+        fn validate(&self, request: &AdmissionRequest) -> Result<AdmissionResponse, anyhow::Error> {
+            match request
+                .object
+                .pointer("/spec/replicas")
+                .and_then(Value::as_i64)
+            {
+                Some(n) if n < 0 => Ok(AdmissionResponse::deny("replicas must not be negative")),
+                Some(_) => Ok(AdmissionResponse::allow()),
+                None => Err(anyhow::anyhow!("spec.replicas is required")),
+            }
+        }
+
+        fn mutate(&self, request: &AdmissionRequest) -> Result<AdmissionResponse, anyhow::Error> {
+            if request.object.pointer("/spec/replicas").is_none() {
+                Ok(AdmissionResponse::patch(json!([
+                    {"op": "add", "path": "/spec/replicas", "value": 1}
+                ])))
+            } else {
+                Ok(AdmissionResponse::allow())
+            }
+        }
+    }
+
+    static V1ALPHA1: &K8sType = &K8sType {
+        api_version: "example.com/v1alpha1",
+        kind: "Parent",
+        plural_kind: "parents",
+    };
+
+    /// converts from v1alpha1, where `spec.size` was renamed to `spec.replicas` in v1
+    fn v1alpha1_conversion() -> Conversion {
+        Conversion::new(V1ALPHA1, PARENT_TYPE, |mut parent| {
+            let size = parent
+                .pointer_mut("/spec")
+                .and_then(Value::as_object_mut)
+                .and_then(|spec| spec.remove("size"))
+                .ok_or_else(|| anyhow::anyhow!("spec.size is missing"))?;
+            parent["spec"]["replicas"] = size;
+            Ok(parent)
+        })
+    }
+
+    fn review(kind: &str, spec: Value) -> Vec<u8> {
+        versioned_review("v1", kind, spec)
+    }
+
+    fn versioned_review(version: &str, kind: &str, spec: Value) -> Vec<u8> {
+        let review = json!({
+            "apiVersion": "admission.k8s.io/v1beta1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "the-uid",
+                "kind": {"group": "example.com", "version": version, "kind": kind},
+                "resource": {"group": "example.com", "version": version, "resource": "parents"},
+                "operation": "CREATE",
+                "namespace": "ns",
+                "name": "foo",
+                "userInfo": {"username": "someone"},
+                "object": {
+                    "apiVersion": format!("example.com/{}", version),
+                    "kind": kind,
+                    "metadata": {"namespace": "ns", "name": "foo"},
+                    "spec": spec,
+                },
+                "oldObject": null,
+                "dryRun": false,
+            }
+        });
+        serde_json::to_vec(&review).unwrap()
+    }
+
+    fn run_review(review_type: ReviewType, body: Vec<u8>) -> Result<Value, String> {
+        run_review_with(Vec::new(), review_type, body)
+    }
+
+    fn run_review_with(
+        conversions: Vec<Conversion>,
+        review_type: ReviewType,
+        body: Vec<u8>,
+    ) -> Result<Value, String> {
+        let svc = WebhookSvc {
+            parent_type: PARENT_TYPE,
+            conversions: Arc::new(conversions),
+            handler: Arc::new(AdmissionHandler),
+        };
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async move { svc.review(review_type, body.as_slice()).await })
+    }
+
+    #[test]
+    fn validate_returns_denial_reason() {
+        let result = run_review(
+            ReviewType::Validate,
+            review("Parent", json!({"replicas": -1})),
+        )
+        .unwrap();
+        let expected = json!({
+            "apiVersion": "admission.k8s.io/v1beta1",
+            "kind": "AdmissionReview",
+            "response": {
+                "uid": "the-uid",
+                "allowed": false,
+                "status": {
+                    "code": 403,
+                    "message": "replicas must not be negative",
+                },
+            },
+        });
+        assert_eq!(expected, result);
+
+        let result = run_review(
+            ReviewType::Validate,
+            review("Parent", json!({"replicas": 3})),
+        )
+        .unwrap();
+        assert_eq!(Some(&json!(true)), result.pointer("/response/allowed"));
+    }
+
+    #[test]
+    fn handler_errors_deny_the_request() {
+        let result = run_review(ReviewType::Validate, review("Parent", json!({}))).unwrap();
+        assert_eq!(Some(&json!(false)), result.pointer("/response/allowed"));
+        assert_eq!(Some(&json!(500)), result.pointer("/response/status/code"));
+    }
+
+    #[test]
+    fn mutate_returns_base64_encoded_patch() {
+        let result = run_review(ReviewType::Mutate, review("Parent", json!({}))).unwrap();
+        assert_eq!(Some(&json!(true)), result.pointer("/response/allowed"));
+        assert_eq!(
+            Some(&json!("JSONPatch")),
+            result.pointer("/response/patchType")
+        );
+        let encoded = result
+            .pointer("/response/patch")
+            .and_then(Value::as_str)
+            .unwrap();
+        let patch: Value = serde_json::from_slice(&base64::decode(encoded).unwrap()).unwrap();
+        assert_eq!(
+            json!([{"op": "add", "path": "/spec/replicas", "value": 1}]),
+            patch
+        );
+    }
+
+    #[test]
+    fn requests_for_other_types_are_allowed() {
+        let result = run_review(
+            ReviewType::Validate,
+            review("Other", json!({"replicas": -1})),
+        )
+        .unwrap();
+        assert_eq!(Some(&json!(true)), result.pointer("/response/allowed"));
+    }
+
+    #[test]
+    fn requests_for_other_versions_are_converted_before_review() {
+        let denied = run_review_with(
+            vec![v1alpha1_conversion()],
+            ReviewType::Validate,
+            versioned_review("v1alpha1", "Parent", json!({"size": -1})),
+        )
+        .unwrap();
+        assert_eq!(Some(&json!(false)), denied.pointer("/response/allowed"));
+        assert_eq!(
+            Some(&json!("replicas must not be negative")),
+            denied.pointer("/response/status/message")
+        );
+
+        let allowed = run_review_with(
+            vec![v1alpha1_conversion()],
+            ReviewType::Mutate,
+            versioned_review("v1alpha1", "Parent", json!({"size": 3})),
+        )
+        .unwrap();
+        assert_eq!(Some(&json!(true)), allowed.pointer("/response/allowed"));
+        assert!(allowed.pointer("/response/patch").is_none());
+
+        // without a conversion, the handler can't review the request, so it must not be allowed
+        let unconverted = run_review(
+            ReviewType::Validate,
+            versioned_review("v1alpha1", "Parent", json!({"size": 3})),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&json!(false)),
+            unconverted.pointer("/response/allowed")
+        );
+    }
+
+    #[test]
+    fn invalid_reviews_are_rejected() {
+        assert!(run_review(ReviewType::Validate, b"not json".to_vec()).is_err());
+        assert!(run_review(ReviewType::Validate, b"{}".to_vec()).is_err());
+    }
+
+    #[test]
+    fn converts_objects_to_the_desired_version() {
+        let svc = WebhookSvc {
+            parent_type: PARENT_TYPE,
+            conversions: Arc::new(vec![v1alpha1_conversion()]),
+            handler: Arc::new(AdmissionHandler),
+        };
+        let review = |desired: &str, objects: Value| {
+            let review = json!({
+                "apiVersion": "apiextensions.k8s.io/v1",
+                "kind": "ConversionReview",
+                "request": {
+                    "uid": "the-uid",
+                    "desiredAPIVersion": desired,
+                    "objects": objects,
+                },
+            });
+            svc.convert(&serde_json::to_vec(&review).unwrap()).unwrap()
+        };
+
+        let result = review(
+            "example.com/v1",
+            json!([
+                {"apiVersion": "example.com/v1alpha1", "kind": "Parent", "spec": {"size": 3}},
+                {"apiVersion": "example.com/v1", "kind": "Parent", "spec": {"replicas": 2}},
+            ]),
+        );
+        let expected = json!({
+            "apiVersion": "apiextensions.k8s.io/v1",
+            "kind": "ConversionReview",
+            "response": {
+                "uid": "the-uid",
+                "convertedObjects": [
+                    {"apiVersion": "example.com/v1", "kind": "Parent", "spec": {"replicas": 3}},
+                    {"apiVersion": "example.com/v1", "kind": "Parent", "spec": {"replicas": 2}},
+                ],
+                "result": {
+                    "status": "Success",
+                },
+            },
+        });
+        assert_eq!(expected, result);
+
+        // there's no conversion in the other direction
+        let result = review(
+            "example.com/v1alpha1",
+            json!([{"apiVersion": "example.com/v1", "kind": "Parent", "spec": {"replicas": 2}}]),
+        );
+        assert_eq!(
+            Some(&json!("Failure")),
+            result.pointer("/response/result/status")
+        );
+        assert_eq!(
+            Some(&json!([])),
+            result.pointer("/response/convertedObjects")
+        );
+    }
+
+    /// writes a self-signed certificate and key to a temp dir, and returns their paths
+    fn write_self_signed_cert(dir: &std::path::Path) -> WebhookTlsConfig {
+        use openssl::asn1::Asn1Time;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::x509::{X509Builder, X509NameBuilder};
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = cert.build();
+
+        let certificate_path = dir.join("tls.crt");
+        let private_key_path = dir.join("tls.key");
+        std::fs::write(&certificate_path, cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&private_key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        WebhookTlsConfig {
+            certificate_path: certificate_path.to_str().unwrap().to_owned(),
+            private_key_path: private_key_path.to_str().unwrap().to_owned(),
+        }
+    }
+
+    #[test]
+    fn serves_reviews_over_tls() {
+        use hyper_openssl::HttpsConnector;
+        use openssl::ssl::{SslConnector, SslVerifyMode};
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("roperator-webhook-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = write_self_signed_cert(&dir);
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = WebhookConfig {
+            port,
+            tls: Some(tls),
+        };
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.spawn(start(
+            config,
+            PARENT_TYPE,
+            Vec::new(),
+            Arc::new(AdmissionHandler),
+        ));
+
+        let mut ssl = SslConnector::builder(SslMethod::tls()).unwrap();
+        ssl.set_verify(SslVerifyMode::NONE);
+        let mut http = hyper::client::HttpConnector::new();
+        http.enforce_http(false);
+        let https = HttpsConnector::with_connector(http, ssl).unwrap();
+        let client = hyper::Client::builder().build::<_, Body>(https);
+
+        let result = runtime.block_on(async move {
+            // give the server a chance to start listening
+            for _ in 0..50 {
+                let request = Request::post(format!("https://localhost:{}/validate", port))
+                    .body(Body::from(review("Parent", json!({"replicas": -1}))))
+                    .unwrap();
+                if let Ok(response) = client.request(request).await {
+                    assert_eq!(200, response.status().as_u16());
+                    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                    return serde_json::from_slice::<Value>(body.as_ref()).unwrap();
+                }
+                tokio::time::delay_for(Duration::from_millis(20)).await;
+            }
+            panic!("failed to connect to webhook server");
+        });
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(Some(&json!(false)), result.pointer("/response/allowed"));
+    }
+}